@@ -0,0 +1,225 @@
+//! A self-check of the config run before serving, so a bad workspace, rpc or
+//! key is reported up front rather than on the first user request.
+use crate::config::Config;
+use near_account_id::AccountId;
+use near_crypto::PublicKey;
+use near_primitives::views::{AccessKeyInfoView, AccessKeyPermissionView};
+use serde_json::json;
+use std::path::Path;
+
+/// The contract methods the prover calls when serving
+const PROVER_METHODS: [&str; 2] = ["verified_loan", "register_verified"];
+
+/// Everything wrong with the config
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Problems(pub Vec<String>);
+
+impl std::fmt::Display for Problems {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Found {} problem(s) with the config:", self.0.len())?;
+        for problem in &self.0 {
+            write!(f, "\n - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Problems {}
+
+/// Check the workspace, rpc and signer key, reporting every problem found
+pub async fn check(config: &Config) -> Result<(), Problems> {
+    let mut problems = check_workspace(&config.nargo_workspace_dir, &config.packages());
+
+    let profile = config.profile();
+    let rpc = config.rpc();
+    let client = near_fetch::Client::new(&rpc);
+    if let Err(e) = client
+        .view(&profile.contract_id, "get_totals")
+        .args_json(json!({}))
+        .await
+    {
+        problems.push(format!(
+            "Can't view {} via {}: {}",
+            profile.contract_id, rpc, e
+        ));
+    }
+
+    match profile.secret.load() {
+        Ok(secret) => match client.view_access_keys(&profile.signer_account).await {
+            Ok(keys) => problems.extend(check_access_key(
+                &keys.keys,
+                &secret.public_key(),
+                &profile.signer_account,
+                &profile.contract_id,
+            )),
+            Err(e) => problems.push(format!(
+                "Can't fetch the access keys of {}: {}",
+                profile.signer_account, e
+            )),
+        },
+        Err(e) => problems.push(format!("Can't load the secret key: {}", e)),
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(Problems(problems))
+    }
+}
+
+/// The workspace must contain every package the prover runs
+fn check_workspace(dir: &Path, required: &[&str]) -> Vec<String> {
+    let manifest = match std::fs::read_to_string(dir.join("Nargo.toml")) {
+        Ok(manifest) => manifest,
+        Err(_) => return vec![format!("No Nargo.toml in {:?}", dir)],
+    };
+    let members: Vec<String> = toml::from_str::<toml::Value>(&manifest)
+        .ok()
+        .and_then(|x| x.get("workspace")?.get("members")?.clone().try_into().ok())
+        .unwrap_or_default();
+
+    let packages: Vec<String> = members
+        .iter()
+        .filter_map(|member| std::fs::read_to_string(dir.join(member).join("Nargo.toml")).ok())
+        .filter_map(|manifest| {
+            let manifest = toml::from_str::<toml::Value>(&manifest).ok()?;
+            Some(manifest.get("package")?.get("name")?.as_str()?.to_string())
+        })
+        .collect();
+
+    required
+        .iter()
+        .filter(|package| !packages.iter().any(|x| x == *package))
+        .map(|package| format!("The workspace {:?} has no `{}` package", dir, package))
+        .collect()
+}
+
+/// The signer key must be a full access key, or a function call key for the
+/// contract that allows the prover's methods
+fn check_access_key(
+    keys: &[AccessKeyInfoView],
+    public_key: &PublicKey,
+    signer: &AccountId,
+    contract: &AccountId,
+) -> Option<String> {
+    let key = match keys.iter().find(|x| &x.public_key == public_key) {
+        Some(key) => key,
+        None => return Some(format!("{} is not an access key of {}", public_key, signer)),
+    };
+    match &key.access_key.permission {
+        AccessKeyPermissionView::FullAccess => None,
+        AccessKeyPermissionView::FunctionCall {
+            receiver_id,
+            method_names,
+            ..
+        } => {
+            if receiver_id != contract.as_str() {
+                return Some(format!(
+                    "{} is a function call key for {}, not {}",
+                    public_key, receiver_id, contract
+                ));
+            }
+            let missing: Vec<&str> = PROVER_METHODS
+                .iter()
+                .filter(|x| !method_names.is_empty() && !method_names.iter().any(|m| m == *x))
+                .copied()
+                .collect();
+            if missing.is_empty() {
+                None
+            } else {
+                Some(format!(
+                    "{} can't call {} on {}",
+                    public_key,
+                    missing.join(", "),
+                    contract
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_crypto::{KeyType, SecretKey};
+    use near_primitives::views::AccessKeyView;
+
+    fn key_info(public_key: PublicKey, permission: AccessKeyPermissionView) -> AccessKeyInfoView {
+        AccessKeyInfoView {
+            public_key,
+            access_key: AccessKeyView {
+                nonce: 0,
+                permission,
+            },
+        }
+    }
+
+    #[test]
+    fn test_check_workspace() {
+        let required = ["apply", "register"];
+        assert!(check_workspace(Path::new("../.."), &required).is_empty());
+
+        let problems = check_workspace(Path::new("../../circuits"), &required);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("No Nargo.toml"));
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Nargo.toml"), "[workspace]\nmembers = []").unwrap();
+        assert_eq!(check_workspace(dir.path(), &required).len(), required.len());
+    }
+
+    #[test]
+    fn test_check_access_key() {
+        let public_key = SecretKey::from_seed(KeyType::ED25519, "prover").public_key();
+        let other = SecretKey::from_seed(KeyType::ED25519, "other").public_key();
+        let signer: AccountId = "prover.testnet".parse().unwrap();
+        let contract: AccountId = "ghostfi.testnet".parse().unwrap();
+        let function_call =
+            |receiver_id: &str, method_names: &[&str]| AccessKeyPermissionView::FunctionCall {
+                allowance: None,
+                receiver_id: receiver_id.to_string(),
+                method_names: method_names.iter().map(|x| x.to_string()).collect(),
+            };
+
+        let full = [key_info(
+            public_key.clone(),
+            AccessKeyPermissionView::FullAccess,
+        )];
+        assert_eq!(
+            check_access_key(&full, &public_key, &signer, &contract),
+            None
+        );
+        assert!(check_access_key(&full, &other, &signer, &contract).is_some());
+
+        let any_method = [key_info(
+            public_key.clone(),
+            function_call("ghostfi.testnet", &[]),
+        )];
+        assert_eq!(
+            check_access_key(&any_method, &public_key, &signer, &contract),
+            None
+        );
+
+        let wrong_contract = [key_info(
+            public_key.clone(),
+            function_call("other.testnet", &[]),
+        )];
+        assert!(check_access_key(&wrong_contract, &public_key, &signer, &contract).is_some());
+
+        let missing = [key_info(
+            public_key.clone(),
+            function_call("ghostfi.testnet", &["verified_loan"]),
+        )];
+        let problem = check_access_key(&missing, &public_key, &signer, &contract).unwrap();
+        assert!(problem.contains("register_verified"));
+    }
+
+    #[test]
+    fn test_problems_display() {
+        let problems = Problems(vec!["a".into(), "b".into()]);
+        assert_eq!(
+            problems.to_string(),
+            "Found 2 problem(s) with the config:\n - a\n - b"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! The compiled circuits the prover serves, each is identified by a hash of its
+//! nargo artifact so a proof records which release of the circuit produced it.
+//! nargo compiles from source, so the circuits are proven from a snapshot of
+//! the workspace taken with the artifacts, later edits to the workspace only
+//! take effect once a new artifact is loaded.
+use crate::{
+    config::Config, prover, social::SocialProfile, Proof, ProofRequest, RegistrationProof,
+    RegistrationRequest, VerificationResult,
+};
+use blake2::{Blake2s256, Digest};
+use eyre::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+/// A proof produced by a circuit that has since been replaced
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetiredCircuit {
+    pub version: Option<String>,
+    pub active: String,
+}
+
+impl std::fmt::Display for RetiredCircuit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The proof is for circuit version {}, it has been retired for {}",
+            self.version.as_deref().unwrap_or("unknown"),
+            self.active
+        )
+    }
+}
+
+impl std::error::Error for RetiredCircuit {}
+
+/// Circuit versions by package, packages that haven't been compiled have none
+#[derive(Clone, Debug)]
+pub struct Circuits {
+    versions: HashMap<String, String>,
+    /// The workspace as it was when the artifacts were read
+    snapshot: Arc<TempDir>,
+}
+
+impl Circuits {
+    pub fn load(workspace: &Path, packages: &[&str]) -> Result<Self> {
+        let snapshot = tempfile::tempdir()?;
+        copy_workspace(workspace, snapshot.path())?;
+        std::fs::create_dir_all(snapshot.path().join("target"))?;
+        std::fs::create_dir_all(snapshot.path().join("proofs"))?;
+
+        let mut versions = HashMap::new();
+        for package in packages {
+            match std::fs::read(artifact_path(workspace, package)) {
+                Ok(artifact) => {
+                    // The bytes hashed are the bytes proven with
+                    std::fs::write(artifact_path(snapshot.path(), package), &artifact)?;
+                    versions.insert(package.to_string(), version(&artifact));
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    log::warn!(
+                        "{} isn't compiled, its proofs won't carry a version",
+                        package
+                    );
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(Self {
+            versions,
+            snapshot: Arc::new(snapshot),
+        })
+    }
+
+    pub fn version(&self, package: &str) -> Option<&str> {
+        self.versions.get(package).map(String::as_str)
+    }
+
+    /// Only proofs of the active version of a compiled circuit are accepted, the
+    /// lending criteria may have changed since a retired version.
+    pub fn check_version(
+        &self,
+        package: &str,
+        version: Option<&str>,
+    ) -> std::result::Result<(), RetiredCircuit> {
+        match self.version(package) {
+            Some(active) if version != Some(active) => Err(RetiredCircuit {
+                version: version.map(String::from),
+                active: active.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// `config` with nargo pointed at the snapshot
+    fn pinned(&self, config: &Config) -> Config {
+        Config {
+            nargo_workspace_dir: self.snapshot.path().to_path_buf(),
+            ..config.clone()
+        }
+    }
+
+    /// Prove a credit application, stamped with the active circuit version
+    pub async fn prove(&self, config: &Config, req: ProofRequest) -> Result<Proof> {
+        let cmd = prover::Command::Prove(req);
+        let package = cmd.package(config)?.to_string();
+        let mut proof: Proof = prover::execute(&self.pinned(config), cmd).await?;
+        proof.circuit_version = self.version(&package).map(String::from);
+        Ok(proof)
+    }
+
+    /// Verify a credit application, checked with the active circuit version
+    pub async fn verify(&self, config: &Config, proof: Proof) -> Result<bool> {
+        let cmd = prover::Command::Verify(proof);
+        let VerificationResult(verified) = prover::execute(&self.pinned(config), cmd).await?;
+        Ok(verified)
+    }
+
+    /// Prove the registration criteria over the `social` profile read for the
+    /// applicant, stamped with the active circuit version
+    pub async fn prove_registration(
+        &self,
+        config: &Config,
+        req: RegistrationRequest,
+        social: SocialProfile,
+    ) -> Result<RegistrationProof> {
+        let cmd = prover::Command::ProveRegistration(req, social);
+        let package = cmd.package(config)?.to_string();
+        let mut proof: RegistrationProof = prover::execute(&self.pinned(config), cmd).await?;
+        proof.circuit_version = self.version(&package).map(String::from);
+        Ok(proof)
+    }
+
+    /// Verify a registration proof, checked with the active circuit version
+    pub async fn verify_registration(
+        &self,
+        config: &Config,
+        proof: RegistrationProof,
+    ) -> Result<bool> {
+        let cmd = prover::Command::VerifyRegistration(proof);
+        let VerificationResult(verified) = prover::execute(&self.pinned(config), cmd).await?;
+        Ok(verified)
+    }
+}
+
+/// Where `nargo compile` writes the artifact of `package`
+pub fn artifact_path(workspace: &Path, package: &str) -> PathBuf {
+    workspace.join("target").join(format!("{}.json", package))
+}
+
+fn version(artifact: &[u8]) -> String {
+    hex::encode(Blake2s256::digest(artifact))
+}
+
+/// Copy the workspace manifest and the sources of its members, a workspace
+/// without a manifest has nothing to copy
+fn copy_workspace(workspace: &Path, to: &Path) -> Result<()> {
+    let manifest = match std::fs::read_to_string(workspace.join("Nargo.toml")) {
+        Ok(manifest) => manifest,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    std::fs::write(to.join("Nargo.toml"), &manifest)?;
+    let members: Vec<String> = toml::from_str::<toml::Value>(&manifest)?
+        .get("workspace")
+        .and_then(|x| x.get("members")?.clone().try_into().ok())
+        .unwrap_or_default();
+    for member in members {
+        copy_dir(&workspace.join(&member), &to.join(&member))?;
+    }
+    Ok(())
+}
+
+/// Copy a package's sources, skipping any build output
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if entry.file_name() != "target" {
+                copy_dir(&path, &to.join(entry.file_name()))?;
+            }
+        } else {
+            std::fs::copy(&path, to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKAGES: [&str; 2] = ["apply", "register"];
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            Circuits::load(dir.path(), &PACKAGES)
+                .unwrap()
+                .version("apply"),
+            None
+        );
+
+        std::fs::create_dir(dir.path().join("target")).unwrap();
+        std::fs::write(artifact_path(dir.path(), "apply"), b"v1").unwrap();
+        let v1 = Circuits::load(dir.path(), &PACKAGES).unwrap();
+        assert_eq!(v1.version("apply").unwrap().len(), 64);
+        assert_eq!(v1.version("register"), None);
+
+        std::fs::write(artifact_path(dir.path(), "apply"), b"v2").unwrap();
+        let v2 = Circuits::load(dir.path(), &PACKAGES).unwrap();
+        assert_ne!(v1.version("apply"), v2.version("apply"));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = "[workspace]\nmembers = [\"circuits/apply\"]\n";
+        std::fs::write(dir.path().join("Nargo.toml"), manifest).unwrap();
+        let src = dir.path().join("circuits/apply/src");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(src.join("main.nr"), "fn main() {}").unwrap();
+        std::fs::create_dir(dir.path().join("target")).unwrap();
+        std::fs::write(artifact_path(dir.path(), "apply"), b"v1").unwrap();
+
+        let circuits = Circuits::load(dir.path(), &PACKAGES).unwrap();
+        std::fs::write(src.join("main.nr"), "fn main() { assert(false); }").unwrap();
+        std::fs::write(artifact_path(dir.path(), "apply"), b"v2").unwrap();
+
+        // Proving uses the workspace as loaded, not as it is now
+        let snapshot = circuits.snapshot.path();
+        let main = snapshot.join("circuits/apply/src/main.nr");
+        assert_eq!(std::fs::read_to_string(main).unwrap(), "fn main() {}");
+        assert_eq!(
+            std::fs::read(artifact_path(snapshot, "apply")).unwrap(),
+            b"v1"
+        );
+        assert_eq!(circuits.version("apply"), Some(version(b"v1").as_str()));
+    }
+
+    #[test]
+    fn test_check_version() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("target")).unwrap();
+        std::fs::write(artifact_path(dir.path(), "apply"), b"v1").unwrap();
+        let circuits = Circuits::load(dir.path(), &PACKAGES).unwrap();
+        let active = circuits.version("apply").unwrap().to_string();
+
+        assert_eq!(circuits.check_version("apply", Some(&active)), Ok(()));
+        assert_eq!(
+            circuits.check_version("apply", Some("v0")),
+            Err(RetiredCircuit {
+                version: Some("v0".into()),
+                active: active.clone()
+            })
+        );
+        assert!(circuits.check_version("apply", None).is_err());
+        // Nothing to check against until the circuit is compiled
+        assert_eq!(circuits.check_version("register", None), Ok(()));
+    }
+}
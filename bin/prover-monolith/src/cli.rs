@@ -0,0 +1,250 @@
+//! The command line, besides serving the http api the prover can run each step
+//! offline so operators can script proofs and contract calls.
+use crate::{
+    check, circuit::Circuits, config::Config, contract, groth16, prover, reload, reload::Active,
+    secret, Proof, ProofRequest, Result,
+};
+use arc_swap::ArcSwap;
+use clap::{Parser, Subcommand};
+use near_account_id::AccountId;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about)]
+pub struct Cli {
+    /// Use this network profile rather than the configured one
+    #[arg(long, global = true)]
+    pub network: Option<String>,
+    /// Defaults to `serve`
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Start the http api
+    Serve,
+    /// Prove a credit application, the proof is printed as json
+    Prove {
+        /// A json `ProofRequest`
+        #[arg(long)]
+        request: PathBuf,
+    },
+    /// Verify a proof, printing whether it verified
+    Verify {
+        /// A json `Proof`
+        #[arg(long)]
+        proof: PathBuf,
+        /// Attest to the loan onchain once verified
+        #[arg(long)]
+        submit: bool,
+    },
+    /// Register a user with the lender, the storage deposit is paid by the signer,
+    /// which must be a registrar of the contract (see `add_registrar`)
+    Register { account: AccountId },
+    /// Deploy the lender contract and initialise it, the signer must be the contract account
+    Deploy {
+        /// The compiled contract
+        #[arg(
+            long,
+            default_value = "target/wasm32-unknown-unknown/release/ghostfi.wasm"
+        )]
+        wasm: PathBuf,
+        /// The prover allowed to release loans, defaults to the signer
+        #[arg(long)]
+        prover: Option<AccountId>,
+        #[arg(long)]
+        max_loan: Option<u128>,
+        #[arg(long)]
+        max_outstanding: Option<u128>,
+        #[arg(long)]
+        max_exposure: Option<u128>,
+    },
+    /// Enable onchain verification with the snarkjs json key of a Groth16 backend,
+    /// requiring the default product's heuristics, and the registrar key when
+    /// `registrar.attestation_key` is configured. The signer must be the contract
+    /// account
+    SetVerificationKey {
+        #[arg(long)]
+        vk: PathBuf,
+    },
+    /// Convert the snarkjs json proof of a Groth16 backend to what
+    /// `verify_and_loan` takes, printed as json
+    Groth16Proof {
+        #[arg(long)]
+        proof: PathBuf,
+    },
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum ConfigCommand {
+    /// Check the circuits, rpc and signer key, reporting every problem found
+    Check,
+    /// Encrypt the configured secret key into a keystore, the passphrase is read
+    /// from `GHOSTFI_KEYSTORE_PASSPHRASE` unless `--passphrase-env` names another variable
+    EncryptKey {
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+        #[arg(long, default_value = "keystore.json")]
+        name: String,
+        #[arg(long)]
+        passphrase_env: Option<String>,
+    },
+}
+
+impl Cli {
+    pub async fn run(self, config: Config) -> Result<()> {
+        match self.command.unwrap_or(Command::Serve) {
+            Command::Serve => {
+                check::check(&config).await?;
+                let shared = Arc::new(ArcSwap::from_pointee(Active::new(config)?));
+                // Reloads stop when the watcher is dropped
+                let _watcher = reload::watch(shared.clone(), self.network)?;
+                crate::serve(shared).await
+            }
+            Command::Prove { request } => {
+                let req: ProofRequest = read_json(&request)?;
+                let circuits = Circuits::load(&config.nargo_workspace_dir, &config.packages())?;
+                let proof = circuits.prove(&config, req).await?;
+                println!("{}", serde_json::to_string_pretty(&proof)?);
+                Ok(())
+            }
+            Command::Verify { proof, submit } => {
+                let mut proof: Proof = read_json(&proof)?;
+                let cmd = prover::Command::Verify(proof.clone());
+                let package = cmd.package(&config)?;
+                let circuits = Circuits::load(&config.nargo_workspace_dir, &config.packages())?;
+                circuits.check_version(package, proof.circuit_version.as_deref())?;
+                proof.circuit_version = circuits.version(package).map(String::from);
+                proof.product = config.product_of(package);
+                let mut verified = circuits.verify(&config, proof.clone()).await?;
+                if submit {
+                    verified = contract::Client::new(&config).verified(proof).await?;
+                }
+                println!("{}", verified);
+                Ok(())
+            }
+            Command::Register { account } => {
+                let registered = contract::Client::new(&config).register(&account).await?;
+                println!("{}", registered);
+                Ok(())
+            }
+            Command::Deploy {
+                wasm,
+                prover,
+                max_loan,
+                max_outstanding,
+                max_exposure,
+            } => {
+                let wasm = std::fs::read(&wasm)?;
+                let prover = prover.unwrap_or_else(|| config.profile().signer_account.clone());
+                let limits = contract::LoanLimits {
+                    max_loan,
+                    max_outstanding,
+                    max_exposure,
+                };
+                contract::Client::new(&config)
+                    .deploy(wasm, &prover, limits)
+                    .await
+            }
+            Command::SetVerificationKey { vk } => {
+                let vk: groth16::SnarkjsVerificationKey = read_json(&vk)?;
+                let key = groth16::VerificationKey::try_from(&vk)?;
+                let client = contract::Client::new(&config);
+                client.set_verification_key(&key).await?;
+                let heuristics = match config.product(None) {
+                    Ok(product) => product.heuristic_ids()?,
+                    Err(_) => Vec::new(),
+                };
+                client.set_required_heuristics(&heuristics).await?;
+                match config.registrar.registrar_key()? {
+                    Some(key) => client.set_registrar_key(&key).await,
+                    None => Ok(()),
+                }
+            }
+            Command::Groth16Proof { proof } => {
+                let proof: groth16::SnarkjsProof = read_json(&proof)?;
+                let proof = groth16::Groth16Proof::try_from(&proof)?;
+                println!("{}", serde_json::to_string_pretty(&proof)?);
+                Ok(())
+            }
+            Command::Config(ConfigCommand::Check) => {
+                check::check(&config).await?;
+                let profile = config.profile();
+                println!(
+                    "Config OK, {} serving {} on {} at {}",
+                    profile.signer_account,
+                    profile.contract_id,
+                    config.network,
+                    config.rpc()
+                );
+                Ok(())
+            }
+            Command::Config(ConfigCommand::EncryptKey {
+                dir,
+                name,
+                passphrase_env,
+            }) => {
+                let key = config.profile().secret.load()?;
+                let path = secret::encrypt(&key, &dir, &name, passphrase_env.as_deref())?;
+                println!("Wrote keystore to {:?}", path);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let json = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["prover-monolith"]).unwrap();
+        assert_eq!(cli.command, None);
+
+        let args = ["prover-monolith", "verify", "--proof", "p.json", "--submit"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::Verify {
+                proof: "p.json".into(),
+                submit: true
+            })
+        );
+
+        let cli =
+            Cli::try_parse_from(["prover-monolith", "config", "check", "--network", "mainnet"])
+                .unwrap();
+        assert_eq!(cli.command, Some(Command::Config(ConfigCommand::Check)));
+        assert_eq!(cli.network.as_deref(), Some("mainnet"));
+
+        assert!(Cli::try_parse_from(["prover-monolith", "register", "not an account"]).is_err());
+
+        let args = ["prover-monolith", "set-verification-key", "--vk", "vk.json"];
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(
+            cli.command,
+            Some(Command::SetVerificationKey {
+                vk: "vk.json".into()
+            })
+        );
+    }
+
+    #[test]
+    fn test_read_json() {
+        let req: ProofRequest = read_json(Path::new("fixtures/simple.json")).unwrap();
+        assert!(req.is_valid());
+    }
+}
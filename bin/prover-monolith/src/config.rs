@@ -1,32 +1,281 @@
-use std::path::PathBuf;
+use crate::{document::RegistrarConfig, prover, secret::SecretSource, Heuristic};
 use config::{Config as ConfigExt, ConfigError, Environment, File};
 use near_account_id::AccountId;
-use near_crypto::SecretKey;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
-    /// The near account for integration with the contract
-    pub account: AccountId,
-    /// The private key for the account that integrates with the contract
-    pub secret: SecretKey,
-    /// The near rpc provider
-    pub rpc: String,
+    /// The active network profile
+    #[serde(default = "default_network")]
+    pub network: String,
+    /// Profiles by network name, e.g. `testnet`, `mainnet` or `localnet`
+    pub networks: HashMap<String, NetworkProfile>,
     /// The circuits location, usually where `Nargo.toml` is
     pub nargo_workspace_dir: PathBuf,
+    /// The proving backend nargo runs, defaults to nargo's own. A Groth16
+    /// backend makes proofs the lender can verify onchain.
+    #[serde(default)]
+    pub nargo_backend: Option<PathBuf>,
+    /// Loan products by name, each proven with its own circuit
+    #[serde(default = "default_products")]
+    pub products: HashMap<String, Product>,
+    /// How the http api is served
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// How identity documents are read
+    #[serde(default)]
+    pub registrar: RegistrarConfig,
+}
+
+/// The contract the prover serves on a network and who it signs as, the signer
+/// doesn't need to own the contract.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NetworkProfile {
+    /// The lending contract
+    pub contract_id: AccountId,
+    /// The prover account calling the contract
+    pub signer_account: AccountId,
+    /// The private key for `signer_account`
+    pub secret: SecretSource,
+    /// The near rpc provider, defaults to the public rpc for well known networks
+    pub rpc: Option<String>,
+    /// The near.social contract registration criteria are read from, defaults
+    /// to the deployment on testnet and mainnet
+    #[serde(default)]
+    pub social_contract: Option<AccountId>,
+}
+
+impl NetworkProfile {
+    pub fn rpc_url(&self, network: &str) -> Option<String> {
+        self.rpc.clone().or_else(|| {
+            let rpc = match network {
+                "mainnet" => "https://rpc.mainnet.near.org",
+                "testnet" => "https://rpc.testnet.near.org",
+                "localnet" => "http://localhost:3030",
+                _ => return None,
+            };
+            Some(rpc.to_string())
+        })
+    }
+
+    pub fn social_contract_id(&self, network: &str) -> Option<AccountId> {
+        self.social_contract.clone().or_else(|| {
+            let contract = match network {
+                "mainnet" => "social.near",
+                "testnet" => "v1.social08.testnet",
+                _ => return None,
+            };
+            contract.parse().ok()
+        })
+    }
+}
+
+fn default_network() -> String {
+    "testnet".to_string()
+}
+
+/// The product used when a request doesn't name one
+pub const DEFAULT_PRODUCT: &str = "default";
+
+/// A loan product and the circuit its applications are proven with
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Product {
+    /// The Nargo package in the workspace
+    pub package: String,
+    /// The heuristics every application must prove, and the only ones it may.
+    /// Empty accepts any, or none at all.
+    #[serde(default)]
+    pub heuristics: Vec<String>,
+}
+
+impl Product {
+    /// Check an application before it's proven
+    pub fn check_heuristics(&self, params: &[Heuristic]) -> eyre::Result<()> {
+        let names: Vec<&str> = params.iter().map(|x| x.name()).collect();
+        self.check_names(&names)
+    }
+
+    /// Check the heuristic ids a proof is public over, so a proof can't be
+    /// verified for a product with heuristics it wasn't proven with
+    pub fn check_heuristic_ids(&self, ids: &[u8]) -> eyre::Result<()> {
+        let names: Vec<&str> = ids
+            .iter()
+            .filter(|x| **x != 0)
+            .map(|x| Heuristic::name_of(*x).unwrap_or("Unknown"))
+            .collect();
+        self.check_names(&names)
+    }
+
+    /// The circuit ids of the required heuristics
+    pub fn heuristic_ids(&self) -> eyre::Result<Vec<u8>> {
+        self.heuristics
+            .iter()
+            .map(|x| Heuristic::id_of(x).ok_or_else(|| eyre::eyre!("Unknown heuristic {}", x)))
+            .collect()
+    }
+
+    fn check_names(&self, names: &[&str]) -> eyre::Result<()> {
+        if self.heuristics.is_empty() {
+            return Ok(());
+        }
+        for name in names {
+            eyre::ensure!(
+                self.heuristics.iter().any(|x| x == name),
+                "{} isn't a heuristic of {}",
+                name,
+                self.package
+            );
+        }
+        for required in &self.heuristics {
+            eyre::ensure!(
+                names.contains(&required.as_str()),
+                "{} requires the {} heuristic",
+                self.package,
+                required
+            );
+        }
+        Ok(())
+    }
+}
+
+fn default_products() -> HashMap<String, Product> {
+    let product = Product {
+        package: "apply".to_string(),
+        heuristics: vec![],
+    };
+    HashMap::from([(DEFAULT_PRODUCT.to_string(), product)])
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    /// Origins allowed to call the api, `*` allows any
+    pub cors_origins: Vec<String>,
+    /// The largest request body accepted, in bytes
+    pub body_limit: usize,
+    /// Requests taking longer are aborted and their nargo killed, proving is
+    /// slow so this is generous
+    pub request_timeout_secs: u64,
+    /// Serve over https rather than relying on a tunnel
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            cors_origins: vec!["*".to_string()],
+            body_limit: 2 * 1024 * 1024,
+            request_timeout_secs: 120,
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_secs)
+    }
+
+    pub fn allows_any_origin(&self) -> bool {
+        self.cors_origins.iter().any(|x| x == "*")
+    }
+}
+
+/// PEM encoded certificate chain and private key
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
 }
 
 impl Config {
-    pub fn new() -> Result<Self, ConfigError> {
+    /// Load the config, `network` overrides the configured network profile
+    pub fn new(network: Option<&str>) -> Result<Self, ConfigError> {
         let s = ConfigExt::builder()
             .add_source(File::with_name("config"))
             // This file shouldn't be checked in to git
             .add_source(File::with_name("local").required(false))
             // Add in settings from the environment (with a prefix of APP)
             .add_source(Environment::with_prefix("GHOSTFI"))
+            .set_override_option("network", network)?
             .build()?;
 
-        s.try_deserialize()
+        Self::checked(s.try_deserialize()?)
+    }
+
+    /// The active network profile
+    pub fn profile(&self) -> &NetworkProfile {
+        &self.networks[&self.network]
+    }
+
+    /// The product named in a request, `None` is the default product
+    pub fn product(&self, name: Option<&str>) -> eyre::Result<&Product> {
+        let Some(name) = name else {
+            return self.products.get(DEFAULT_PRODUCT).ok_or_else(|| {
+                eyre::eyre!("There is no default product, the request must name a product")
+            });
+        };
+        self.products
+            .get(name)
+            .ok_or_else(|| eyre::eyre!("Unknown product {}", name))
+    }
+
+    /// The product proven with `package`, as recorded with a loan. Products
+    /// have their own package so a verified proof identifies its product, the
+    /// default product is `None`.
+    pub fn product_of(&self, package: &str) -> Option<String> {
+        self.products
+            .iter()
+            .find(|(_, product)| product.package == package)
+            .map(|(name, _)| name)
+            .filter(|name| *name != DEFAULT_PRODUCT)
+            .cloned()
+    }
+
+    /// Every Nargo package the prover runs
+    pub fn packages(&self) -> Vec<&str> {
+        let mut packages: Vec<&str> = self.products.values().map(|x| x.package.as_str()).collect();
+        packages.push(prover::REGISTRATION_PACKAGE);
+        packages.sort_unstable();
+        packages.dedup();
+        packages
+    }
+
+    /// The rpc of the active network
+    pub fn rpc(&self) -> String {
+        // Checked on load
+        self.profile().rpc_url(&self.network).unwrap_or_default()
+    }
+
+    fn checked(config: Self) -> Result<Self, ConfigError> {
+        let profile = config.networks.get(&config.network).ok_or_else(|| {
+            ConfigError::Message(format!("No profile for network {}", config.network))
+        })?;
+        if profile.rpc_url(&config.network).is_none() {
+            return Err(ConfigError::Message(format!(
+                "Network {} needs an rpc",
+                config.network
+            )));
+        }
+        let mut packages = HashMap::new();
+        for (name, product) in &config.products {
+            if let Some(other) = packages.insert(&product.package, name) {
+                return Err(ConfigError::Message(format!(
+                    "Products {} and {} are both proven with {}, each needs its own package",
+                    other, name, product.package
+                )));
+            }
+            if let Err(e) = product.heuristic_ids() {
+                return Err(ConfigError::Message(format!("Product {}: {}", name, e)));
+            }
+        }
+        Ok(config)
     }
 }
 
@@ -42,6 +291,129 @@ impl From<&str> for Config {
             .build()
             .unwrap();
 
-        s.try_deserialize().unwrap()
+        Self::checked(s.try_deserialize().unwrap()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(network: &str) -> Result<Config, ConfigError> {
+        let secret = near_crypto::SecretKey::from_seed(near_crypto::KeyType::ED25519, "test");
+        let config: Config = toml::from_str(&format!(
+            r#"
+            network = "{}"
+            nargo_workspace_dir = "."
+
+            [networks.testnet]
+            contract_id = "ghostfi.testnet"
+            signer_account = "prover.testnet"
+            secret = "{}"
+
+            [networks.private]
+            contract_id = "ghostfi.test.near"
+            signer_account = "prover.test.near"
+            secret = {{ env = "GHOSTFI_SECRET_KEY" }}
+            "#,
+            network, secret
+        ))
+        .unwrap();
+        Config::checked(config)
+    }
+
+    #[test]
+    fn test_network_profiles() {
+        let config = parse("testnet").unwrap();
+        assert_eq!(config.profile().contract_id.as_str(), "ghostfi.testnet");
+        assert_eq!(config.profile().signer_account.as_str(), "prover.testnet");
+        assert_eq!(config.rpc(), "https://rpc.testnet.near.org");
+        assert_eq!(
+            config.profile().social_contract_id(&config.network),
+            Some("v1.social08.testnet".parse().unwrap())
+        );
+
+        // Unknown networks need an rpc
+        assert!(parse("private").is_err());
+        assert!(parse("mainnet").is_err());
+    }
+
+    #[test]
+    fn test_server_config() {
+        let config = parse("testnet").unwrap();
+        assert_eq!(config.server, ServerConfig::default());
+        assert!(config.server.allows_any_origin());
+
+        let server: ServerConfig = toml::from_str(
+            r#"
+            listen = "127.0.0.1:8443"
+            cors_origins = ["https://near.org"]
+            tls = { cert = "cert.pem", key = "key.pem" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(server.listen, "127.0.0.1:8443".parse().unwrap());
+        assert!(!server.allows_any_origin());
+        assert_eq!(server.body_limit, ServerConfig::default().body_limit);
+        assert_eq!(server.tls.unwrap().key, PathBuf::from("key.pem"));
+    }
+
+    #[test]
+    fn test_products() {
+        let config = parse("testnet").unwrap();
+        assert_eq!(config.product(None).unwrap().package, "apply");
+        assert!(config.product(Some("mortgage")).is_err());
+        assert_eq!(config.packages(), vec!["apply", "register"]);
+        assert_eq!(config.product_of("apply"), None);
+
+        let mortgage = Product {
+            package: "mortgage".into(),
+            heuristics: vec![],
+        };
+        let config = Config {
+            products: HashMap::from([("mortgage".to_string(), mortgage.clone())]),
+            ..config
+        };
+        // Without a default product, requests must name one
+        assert!(config.product(None).is_err());
+        assert_eq!(config.product(Some("mortgage")).unwrap(), &mortgage);
+        assert_eq!(config.product_of("mortgage"), Some("mortgage".into()));
+
+        let mut products = config.products.clone();
+        products.insert("bridge".into(), mortgage.clone());
+        assert!(Config::checked(Config {
+            products,
+            ..config.clone()
+        })
+        .is_err());
+        let unknown = Product {
+            heuristics: vec!["Identity".into()],
+            ..mortgage
+        };
+        let products = HashMap::from([("mortgage".to_string(), unknown)]);
+        assert!(Config::checked(Config { products, ..config }).is_err());
+
+        let product: Product = toml::from_str(
+            r#"
+            package = "apply"
+            heuristics = ["Simple"]
+            "#,
+        )
+        .unwrap();
+        let simple = Heuristic::Simple { balance: 1 };
+        assert!(product.check_heuristics(&[simple.clone()]).is_ok());
+        // Required, so applications of nothing but noops are refused
+        assert!(product.check_heuristics(&[]).is_err());
+        assert!(product.check_heuristic_ids(&[1, 0, 0, 0]).is_ok());
+        assert!(product.check_heuristic_ids(&[0, 0, 0, 0]).is_err());
+        assert!(product.check_heuristic_ids(&[1, 0, 3, 0]).is_err());
+        assert_eq!(product.heuristic_ids().unwrap(), vec![1]);
+
+        let product = Product {
+            heuristics: vec!["Other".into()],
+            ..product
+        };
+        assert!(product.check_heuristics(&[simple]).is_err());
+        assert!(product.heuristic_ids().is_err());
     }
 }
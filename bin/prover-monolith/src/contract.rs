@@ -1,56 +1,185 @@
-use crate::{config::Config, Proof, Result};
+use crate::{config::Config, groth16::VerificationKey, social::SocialProfile, Proof, Result};
 use near_account_id::AccountId;
-use near_crypto::{InMemorySigner, SecretKey};
+use near_crypto::InMemorySigner;
+use near_fetch::ops::Function;
+use near_primitives::views::FinalExecutionStatus;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
-use std::str::FromStr;
+use serde_with::DisplayFromStr;
+
+/// Aggregates over the lender's registrations and loans
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Totals {
+    #[serde_as(as = "DisplayFromStr")]
+    pub users: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub verified_users: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub loaned: u128,
+}
+
+/// The reasons the contract refuses to release a loan, mirroring `LoanError`
+/// in the contract by its message prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoanRejection {
+    Forbidden,
+    NotRegistered,
+    NotVerified,
+    ExceedsMaxLoan,
+    ExceedsUserCap,
+    ExceedsExposureCap,
+    NoVerificationKey,
+    InvalidPublicInputs,
+    InvalidProof,
+    StaleProof,
+    AlreadyAttested,
+    ProofAlreadyUsed,
+    CircuitVersionTooLong,
+    ProductTooLong,
+    MissingHeuristic,
+}
+
+impl LoanRejection {
+    const CODES: [(&'static str, LoanRejection); 15] = [
+        ("ERR_FORBIDDEN", LoanRejection::Forbidden),
+        ("ERR_NOT_REGISTERED", LoanRejection::NotRegistered),
+        ("ERR_NOT_VERIFIED", LoanRejection::NotVerified),
+        ("ERR_MAX_LOAN", LoanRejection::ExceedsMaxLoan),
+        ("ERR_USER_CAP", LoanRejection::ExceedsUserCap),
+        ("ERR_EXPOSURE_CAP", LoanRejection::ExceedsExposureCap),
+        ("ERR_NO_VERIFICATION_KEY", LoanRejection::NoVerificationKey),
+        (
+            "ERR_INVALID_PUBLIC_INPUTS",
+            LoanRejection::InvalidPublicInputs,
+        ),
+        ("ERR_INVALID_PROOF", LoanRejection::InvalidProof),
+        ("ERR_STALE_PROOF", LoanRejection::StaleProof),
+        ("ERR_ALREADY_ATTESTED", LoanRejection::AlreadyAttested),
+        ("ERR_PROOF_USED", LoanRejection::ProofAlreadyUsed),
+        ("ERR_CIRCUIT_VERSION", LoanRejection::CircuitVersionTooLong),
+        ("ERR_PRODUCT", LoanRejection::ProductTooLong),
+        ("ERR_MISSING_HEURISTIC", LoanRejection::MissingHeuristic),
+    ];
+
+    /// Find the rejection in a failed execution message
+    pub fn from_failure(msg: &str) -> Option<Self> {
+        Self::CODES
+            .iter()
+            .find(|(code, _)| msg.contains(code))
+            .map(|(_, rejection)| *rejection)
+    }
+}
+
+impl std::fmt::Display for LoanRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msg = match self {
+            LoanRejection::Forbidden => "the prover is not allowed to release loans",
+            LoanRejection::NotRegistered => "the user is not registered",
+            LoanRejection::NotVerified => "the user has not proven the registration criteria",
+            LoanRejection::ExceedsMaxLoan => "the loan exceeds the maximum loan",
+            LoanRejection::ExceedsUserCap => "the loan exceeds the user's outstanding cap",
+            LoanRejection::ExceedsExposureCap => "the loan exceeds the lender's exposure cap",
+            LoanRejection::NoVerificationKey => "onchain verification is not enabled",
+            LoanRejection::InvalidPublicInputs => "the public inputs don't match the borrower",
+            LoanRejection::InvalidProof => "the proof failed onchain verification",
+            LoanRejection::StaleProof => "the proof was not made today or yesterday",
+            LoanRejection::AlreadyAttested => "this prover already attested to the proof",
+            LoanRejection::ProofAlreadyUsed => "the proof has already released a loan",
+            LoanRejection::CircuitVersionTooLong => "the circuit version is too long",
+            LoanRejection::ProductTooLong => "the product name is too long",
+            LoanRejection::MissingHeuristic => "the proof doesn't prove a required heuristic",
+        };
+        write!(f, "Loan rejected, {}", msg)
+    }
+}
+
+impl std::error::Error for LoanRejection {}
+
+/// An outstanding loan as returned by `get_loans`
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Loan {
+    #[serde_as(as = "DisplayFromStr")]
+    pub id: u64,
+    #[serde_as(as = "DisplayFromStr")]
+    pub amount: u128,
+    /// The version of the circuit whose proof released the loan
+    pub circuit_version: Option<String>,
+    /// The loan product, `None` is the default product
+    #[serde(default)]
+    pub product: Option<String>,
+}
+
+/// Loan caps passed to `initialize`, `None` is uncapped
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LoanLimits {
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub max_loan: Option<u128>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub max_outstanding: Option<u128>,
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    pub max_exposure: Option<u128>,
+}
+
+/// The storage deposit required to register, as returned by `storage_balance_bounds`
+#[serde_with::serde_as]
+#[derive(Deserialize)]
+struct StorageBalanceBounds {
+    #[serde_as(as = "DisplayFromStr")]
+    min: u128,
+}
 
 #[derive(Clone)]
 pub struct Client {
     contract: AccountId,
     client: near_fetch::Client,
     signer: InMemorySigner,
+    /// Where registration criteria are read from
+    social: Option<AccountId>,
 }
 
 impl Client {
     pub fn new(config: &Config) -> Self {
+        Self::try_new(config).expect("Failed to create signer")
+    }
+
+    pub fn try_new(config: &Config) -> Result<Self> {
         log::debug!("Initialising client, config {:?}", config);
 
-        let signer = || -> Result<InMemorySigner> {
-            Ok(near_crypto::InMemorySigner::from_secret_key(
-                config.account.clone(),
-                config.secret.clone(),
-            ))
-        };
+        let profile = config.profile();
+        let signer =
+            InMemorySigner::from_secret_key(profile.signer_account.clone(), profile.secret.load()?);
+        Ok(Self::with_signer(config, signer))
+    }
 
-        let client = near_fetch::Client::new(&config.rpc);
+    /// A client for `config` with an already loaded `signer`, which must be
+    /// for the profile's signer account
+    pub fn with_signer(config: &Config, signer: InMemorySigner) -> Self {
+        let profile = config.profile();
+        let client = near_fetch::Client::new(&config.rpc());
         Self {
-            signer: signer().expect("Failed to create signer"),
-            contract: config.account.parse().unwrap(),
+            signer,
+            contract: profile.contract_id.clone(),
             client,
+            social: profile.social_contract_id(&config.network),
         }
     }
 
-    /// Interacts with the contract to inform the lender that the loan has been
-    /// verified successfully
-    pub async fn verified(&self, proof: Proof) -> Result<bool> {
-        let account_id = proof
-            .account_id
-            .as_ref()
-            .and_then(|x| x.parse::<AccountId>().ok())
-            .ok_or_else(|| eyre::eyre!("Invalid account id"))?;
+    /// The prover's key, also used to sign what it attests offchain
+    pub fn signer(&self) -> &InMemorySigner {
+        &self.signer
+    }
 
-        let keys = self.client.view_access_keys(&account_id).await?;
-        assert!(
-            keys.keys.iter().any(|x| {
-                log::debug!(
-                    "Comparing pk {:?} with {:?}",
-                    x.public_key,
-                    proof.public_key
-                );
-                x.public_key.to_string() == proof.public_key
-            }),
-            "Key is not registered for this account"
-        );
+    /// Interacts with the contract to attest that the loan has been verified
+    /// successfully, this is true once the attestation is accepted even if the
+    /// lender is still waiting on other provers to release the loan.
+    pub async fn verified(&self, proof: Proof) -> Result<bool> {
+        let account_id = self
+            .account_for_key(proof.account_id.as_ref(), &proof.public_key)
+            .await?;
+        log::debug!("Releasing loan to {}", account_id);
 
         let f = self
             .client
@@ -61,18 +190,282 @@ impl Client {
 
         // TODO: get account from onchain for pk
 
-        match f {
-            Ok(_) => Ok(true),
-            Err(err) => {
-                log::error!("{}", err);
-                Ok(false)
+        let failure = match f {
+            Ok(outcome) => match outcome.status {
+                FinalExecutionStatus::SuccessValue(value) => {
+                    // The loan is only released once enough provers have attested
+                    if !serde_json::from_slice::<bool>(&value).unwrap_or_default() {
+                        log::info!("Attestation recorded, awaiting other provers");
+                    }
+                    return Ok(true);
+                }
+                FinalExecutionStatus::Failure(err) => err.to_string(),
+                status => format!("{:?}", status),
+            },
+            Err(err) => err.to_string(),
+        };
+        log::error!("{}", failure);
+        match LoanRejection::from_failure(&failure) {
+            Some(rejection) => Err(rejection.into()),
+            None => Ok(false),
+        }
+    }
+
+    /// Interacts with the contract to inform the lender that the user has
+    /// proven the registration criteria
+    pub async fn register_verified(&self, user: &AccountId) -> Result<bool> {
+        let outcome = self
+            .client
+            .call(&self.signer, &self.contract, "register_verified")
+            .args_json(json!({ "user": user }))
+            .transact()
+            .await?;
+
+        match outcome.status {
+            // False when the user isn't registered, or was already verified
+            FinalExecutionStatus::SuccessValue(value) => Ok(serde_json::from_slice(&value)?),
+            FinalExecutionStatus::Failure(err) => {
+                Err(eyre::eyre!("Verifying {} failed: {}", user, err))
             }
+            status => Err(eyre::eyre!("Verifying {} failed: {:?}", user, status)),
+        }
+    }
+
+    /// Register `user` with the lender, the storage deposit is paid by the prover account.
+    /// Unless the prover registers itself, the contract must have added it with
+    /// `add_registrar` first.
+    pub async fn register(&self, user: &AccountId) -> Result<bool> {
+        if &self.signer.account_id != user {
+            eyre::ensure!(
+                self.is_registrar(&self.signer.account_id).await?,
+                "{} isn't a registrar of {}, the contract account must call `add_registrar` for it first",
+                self.signer.account_id,
+                self.contract
+            );
+        }
+        let bounds: StorageBalanceBounds = self.view("storage_balance_bounds", json!({})).await?;
+        let outcome = self
+            .client
+            .call(&self.signer, &self.contract, "register")
+            .args_json(json!({ "user": user }))
+            .deposit(bounds.min)
+            .transact()
+            .await?;
+
+        match outcome.status {
+            FinalExecutionStatus::SuccessValue(value) => Ok(serde_json::from_slice(&value)?),
+            status => Err(eyre::eyre!("Registration failed: {:?}", status)),
+        }
+    }
+
+    /// Deploy the lender and initialise it with `prover`, only the contract
+    /// account can deploy to itself so it must also be the signer.
+    pub async fn deploy(
+        &self,
+        wasm: Vec<u8>,
+        prover: &AccountId,
+        limits: LoanLimits,
+    ) -> Result<()> {
+        eyre::ensure!(
+            self.signer.account_id == self.contract,
+            "Deploying requires signing as the contract account {}",
+            self.contract
+        );
+        let initialize =
+            Function::new("initialize").args_json(json!({ "prover": prover, "limits": limits }));
+        let outcome = self
+            .client
+            .batch(&self.signer, &self.contract)
+            .deploy(&wasm)
+            .call(initialize)
+            .transact()
+            .await?;
+
+        match outcome.status {
+            FinalExecutionStatus::SuccessValue(_) => Ok(()),
+            status => Err(eyre::eyre!("Deployment failed: {:?}", status)),
+        }
+    }
+
+    /// Enable onchain verification with a Groth16 key for the `apply` circuit,
+    /// only the contract account can set it
+    pub async fn set_verification_key(&self, key: &VerificationKey) -> Result<()> {
+        eyre::ensure!(
+            self.signer.account_id == self.contract,
+            "Setting the verification key requires signing as the contract account {}",
+            self.contract
+        );
+        let outcome = self
+            .client
+            .call(&self.signer, &self.contract, "set_verification_key")
+            .args_json(json!({ "verification_key": key }))
+            .transact()
+            .await?;
+
+        match outcome.status {
+            FinalExecutionStatus::SuccessValue(_) => Ok(()),
+            status => Err(eyre::eyre!(
+                "Setting the verification key failed: {:?}",
+                status
+            )),
+        }
+    }
+
+    /// Set the registrar key onchain verified proofs check attested identities
+    /// with, the `x || y` coordinates of `registrar.attestation_key`
+    pub async fn set_registrar_key(&self, key: &[u8; 64]) -> Result<()> {
+        #[serde_with::serde_as]
+        #[derive(Serialize)]
+        struct Args<'a> {
+            #[serde_as(as = "serde_with::base64::Base64")]
+            registrar_key: &'a [u8; 64],
+        }
+
+        eyre::ensure!(
+            self.signer.account_id == self.contract,
+            "Setting the registrar key requires signing as the contract account {}",
+            self.contract
+        );
+        let outcome = self
+            .client
+            .call(&self.signer, &self.contract, "set_registrar_key")
+            .args_json(Args { registrar_key: key })
+            .transact()
+            .await?;
+
+        match outcome.status {
+            FinalExecutionStatus::SuccessValue(_) => Ok(()),
+            status => Err(eyre::eyre!(
+                "Setting the registrar key failed: {:?}",
+                status
+            )),
+        }
+    }
+
+    /// Set the heuristic ids every onchain verified proof must prove
+    pub async fn set_required_heuristics(&self, heuristics: &[u8]) -> Result<()> {
+        eyre::ensure!(
+            self.signer.account_id == self.contract,
+            "Setting the required heuristics requires signing as the contract account {}",
+            self.contract
+        );
+        let outcome = self
+            .client
+            .call(&self.signer, &self.contract, "set_required_heuristics")
+            .args_json(json!({ "heuristics": heuristics }))
+            .transact()
+            .await?;
+
+        match outcome.status {
+            FinalExecutionStatus::SuccessValue(_) => Ok(()),
+            status => Err(eyre::eyre!(
+                "Setting the required heuristics failed: {:?}",
+                status
+            )),
         }
     }
+
+    /// Read the near.social activity of `account` from the social contract
+    pub async fn social_profile(&self, account: &AccountId) -> Result<SocialProfile> {
+        let social = self
+            .social
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("No near.social contract configured for this network"))?;
+        let data: serde_json::Value = self
+            .client
+            .view(social, "get")
+            .args_json(json!({ "keys": SocialProfile::keys(account.as_str()) }))
+            .await?
+            .json()?;
+        Ok(SocialProfile::from_social_data(account.as_str(), &data))
+    }
+
+    /// Ensure the proven public key is an access key of the account the caller
+    /// wants to act on
+    pub async fn account_for_key(
+        &self,
+        account_id: Option<&String>,
+        public_key: &str,
+    ) -> Result<AccountId> {
+        let account_id = account_id
+            .and_then(|x| x.parse::<AccountId>().ok())
+            .ok_or_else(|| eyre::eyre!("Invalid account id"))?;
+
+        let keys = self.client.view_access_keys(&account_id).await?;
+        eyre::ensure!(
+            keys.keys.iter().any(|x| {
+                log::debug!("Comparing pk {:?} with {:?}", x.public_key, public_key);
+                x.public_key.to_string() == public_key
+            }),
+            "Key is not registered for this account"
+        );
+        Ok(account_id)
+    }
+
+    pub async fn is_registered(&self, user: &AccountId) -> Result<bool> {
+        self.view("is_registered", json!({ "user": user })).await
+    }
+
+    pub async fn is_verified(&self, user: &AccountId) -> Result<bool> {
+        self.view("is_verified", json!({ "user": user })).await
+    }
+
+    pub async fn is_registrar(&self, account: &AccountId) -> Result<bool> {
+        self.view("is_registrar", json!({ "account": account }))
+            .await
+    }
+
+    /// A page of the loans taken by `user`
+    pub async fn get_loans(
+        &self,
+        user: &AccountId,
+        from_index: u64,
+        limit: u64,
+    ) -> Result<Option<Vec<Loan>>> {
+        self.view(
+            "get_loans",
+            json!({
+                "user": user,
+                "from_index": from_index.to_string(),
+                "limit": limit.to_string(),
+            }),
+        )
+        .await
+    }
+
+    /// A page of the registered users
+    pub async fn get_users(&self, from_index: u64, limit: u64) -> Result<Vec<AccountId>> {
+        self.view(
+            "get_users",
+            json!({
+                "from_index": from_index.to_string(),
+                "limit": limit.to_string(),
+            }),
+        )
+        .await
+    }
+
+    pub async fn get_totals(&self) -> Result<Totals> {
+        self.view("get_totals", json!({})).await
+    }
+
+    async fn view<T: DeserializeOwned>(&self, method: &str, args: serde_json::Value) -> Result<T> {
+        log::debug!("Viewing {} with {}", method, args);
+        let result = self
+            .client
+            .view(&self.contract, method)
+            .args_json(args)
+            .await?;
+        Ok(result.json()?)
+    }
+
     fn build_verified_loan_call(proof: Proof) -> Result<serde_json::Value> {
         let json = json!({
             "user": proof.account_id,
-            "amount": format!("{}", proof.requested_amount)
+            "amount": format!("{}", proof.requested_amount),
+            "proof_hash": bs58::encode(proof.hash()).into_string(),
+            "circuit_version": proof.circuit_version,
+            "product": proof.product,
         });
         log::debug!("Json {}", serde_json::to_string_pretty(&json)?);
 
@@ -94,11 +487,83 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_proof_hash() {
+        let json = std::fs::read_to_string("fixtures/proof.json").unwrap();
+        let mut proof: Proof = serde_json::from_str(&json).unwrap();
+        let unversioned = proof.hash();
+        proof.circuit_version = Some("v1".into());
+        assert_ne!(proof.hash(), unversioned);
+    }
+
+    #[test]
+    fn test_view_deserialization() {
+        let totals: Totals =
+            serde_json::from_str(r#"{"users":"3","verified_users":"2","loaned":"12"}"#).unwrap();
+        assert_eq!(
+            totals,
+            Totals {
+                users: 3,
+                verified_users: 2,
+                loaned: 12
+            }
+        );
+
+        let loans: Option<Vec<Loan>> = serde_json::from_str(
+            r#"[
+                {"id":"0","amount":"1","circuit_version":"v1","product":"mortgage"},
+                {"id":"3","amount":"2","circuit_version":null}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            loans,
+            Some(vec![
+                Loan {
+                    id: 0,
+                    amount: 1,
+                    circuit_version: Some("v1".into()),
+                    product: Some("mortgage".into())
+                },
+                Loan {
+                    id: 3,
+                    amount: 2,
+                    circuit_version: None,
+                    product: None
+                }
+            ])
+        );
+        let loans: Option<Vec<Loan>> = serde_json::from_str("null").unwrap();
+        assert_eq!(loans, None);
+    }
+
+    #[test]
+    fn test_loan_rejection() {
+        let msg = "Smart contract panicked: ERR_USER_CAP: User has 10 outstanding, the cap is 15";
+        assert_eq!(
+            LoanRejection::from_failure(msg),
+            Some(LoanRejection::ExceedsUserCap)
+        );
+        let msg = "Smart contract panicked: ERR_STALE_PROOF: Proof was made before yesterday";
+        assert_eq!(
+            LoanRejection::from_failure(msg),
+            Some(LoanRejection::StaleProof)
+        );
+        assert_eq!(
+            LoanRejection::from_failure("Exceeded the prepaid gas"),
+            None
+        );
+    }
 
     #[test]
-    fn test_sk() {
-        let sk = SecretKey::from_random(near_crypto::KeyType::ED25519);
-        println!("{}", sk.to_string());
-        
+    fn test_loan_limits_serialization() {
+        let limits = LoanLimits {
+            max_loan: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&limits).unwrap(),
+            json!({ "max_loan": "100", "max_outstanding": null, "max_exposure": null })
+        );
     }
 }
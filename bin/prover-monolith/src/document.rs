@@ -0,0 +1,451 @@
+//! Identity documents submitted for registration. The image is read in memory
+//! by the registrar and dropped once the claims are extracted, it's never
+//! written to disk and what was read from it is never logged.
+use crate::secret::SecretSource;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use near_crypto::{KeyType, PublicKey, Signature, Signer};
+use registrar::attestation::{Attestation, Attester};
+use registrar::identity::{Date, Identity};
+use registrar::{Registrar, RegistrarError, Verdict};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// How documents are read, the `registrar` section of the config
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RegistrarConfig {
+    /// The Tesseract models, defaults to those bundled with the registrar crate
+    pub tessdata: Option<PathBuf>,
+    /// Fields read with less OCR confidence than this, 0 to 100, are refused
+    pub min_confidence: Option<f32>,
+    /// Refuse documents no forgery check applies to, images that were never
+    /// compressed as a JPEG have no grid to check
+    pub require_forgery_check: bool,
+    /// The secp256k1 key attestations are signed with. Its public key is a
+    /// public input of the `apply` circuit, proofs made with another are refused.
+    pub attestation_key: Option<SecretSource>,
+}
+
+impl Default for RegistrarConfig {
+    fn default() -> Self {
+        Self {
+            tessdata: None,
+            min_confidence: None,
+            require_forgery_check: true,
+            attestation_key: None,
+        }
+    }
+}
+
+impl RegistrarConfig {
+    pub fn registrar(&self) -> Registrar {
+        let mut registrar = match &self.tessdata {
+            Some(tessdata) => Registrar::new(tessdata),
+            None => Registrar::default(),
+        };
+        if let Some(min_confidence) = self.min_confidence {
+            registrar = registrar.min_confidence(min_confidence);
+        }
+        registrar.forgery_policy(registrar::ForgeryPolicy {
+            require_check: self.require_forgery_check,
+            ..Default::default()
+        })
+    }
+
+    /// Signs attestations, `None` when no key is configured
+    pub fn attester(&self) -> eyre::Result<Option<Attester>> {
+        let key = match &self.attestation_key {
+            Some(source) => source.load()?,
+            None => return Ok(None),
+        };
+        eyre::ensure!(
+            key.key_type() == KeyType::SECP256K1,
+            "The attestation key must be secp256k1"
+        );
+        // Displayed as `secp256k1:<base58 secret>`
+        let encoded = key.to_string();
+        let secret = bs58::decode(encoded.trim_start_matches("secp256k1:")).into_vec()?;
+        Ok(Some(Attester::new(&secret)?))
+    }
+
+    /// The uncompressed `x || y` key proofs must attest identities with, `None`
+    /// when no attestation key is configured
+    pub fn registrar_key(&self) -> eyre::Result<Option<[u8; 64]>> {
+        Ok(self.attester()?.map(|x| x.public_key()))
+    }
+}
+
+/// What the registrar read from a document
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Claims {
+    /// The MRZ layout, `Td1`, `Td2` or `Td3`
+    pub format: String,
+    /// ICAO 9303 alpha-3 code
+    pub nationality: String,
+    /// `YYYYMMDD`
+    pub birth_date: u32,
+    /// `YYYYMMDD`
+    pub expiry_date: u32,
+}
+
+impl Claims {
+    pub fn new(format: registrar::mrz::Format, identity: &Identity) -> Self {
+        Self {
+            format: format!("{:?}", format),
+            nationality: identity.nationality.clone(),
+            birth_date: identity.birth_date.as_number(),
+            expiry_date: identity.expiry_date.as_number(),
+        }
+    }
+}
+
+/// The registrar's attestation as the holder keeps it, proven over with
+/// `Heuristic::AttestedIdentity` by the holder's key
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AttestedClaims {
+    pub claims: Claims,
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub salt: [u8; 32],
+    /// secp256k1 `r || s`
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub signature: [u8; 64],
+}
+
+impl AttestedClaims {
+    pub fn new(claims: Claims, attestation: &Attestation) -> Self {
+        Self {
+            claims,
+            salt: attestation.salt,
+            signature: attestation.signature,
+        }
+    }
+}
+
+/// Claims signed by the prover for the holder of a NEAR key, the signature is
+/// over [`SignedClaims::message`]. The attestation is issued when the prover
+/// has an attestation key.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SignedClaims {
+    pub claims: Claims,
+    /// The key of whoever submitted the document
+    pub holder: PublicKey,
+    /// When the document was read, in seconds since the unix epoch
+    pub issued_at: u64,
+    pub signer: PublicKey,
+    pub signature: Signature,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<AttestedClaims>,
+}
+
+impl SignedClaims {
+    pub fn sign(claims: Claims, holder: PublicKey, issued_at: u64, signer: &impl Signer) -> Self {
+        let message = Self::message(&claims, &holder, issued_at);
+        Self {
+            signature: signer.sign(&message),
+            signer: signer.public_key(),
+            claims,
+            holder,
+            issued_at,
+            attestation: None,
+        }
+    }
+
+    /// The digest signed, every field in a fixed order so it doesn't depend on
+    /// how the claims were serialised: the format and nationality length
+    /// prefixed, the dates and issue time big endian, then the holder's key type
+    /// and key
+    pub fn message(claims: &Claims, holder: &PublicKey, issued_at: u64) -> [u8; 32] {
+        use blake2::{Blake2s256, Digest};
+        let mut hasher = Blake2s256::new();
+        for field in [&claims.format, &claims.nationality] {
+            hasher.update((field.len() as u32).to_be_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hasher.update(claims.birth_date.to_be_bytes());
+        hasher.update(claims.expiry_date.to_be_bytes());
+        hasher.update(issued_at.to_be_bytes());
+        hasher.update([holder.key_type() as u8]);
+        hasher.update(holder.key_data());
+        hasher.finalize().into()
+    }
+
+    pub fn verify(&self) -> bool {
+        let message = Self::message(&self.claims, &self.holder, self.issued_at);
+        self.signature.verify(&message, &self.signer)
+    }
+}
+
+#[derive(Debug)]
+pub enum DocumentError {
+    /// The form had no `document` field or couldn't be read
+    BadRequest(String),
+    /// The registrar refused the document
+    Registrar(RegistrarError),
+    /// The MRZ read but a field was too hard to read to be trusted
+    Unverified(Verdict),
+    /// The dates on the document don't parse
+    InvalidDates,
+    /// The document expired, `YYYYMMDD`
+    Expired(u32),
+    Internal(String),
+}
+
+impl std::fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DocumentError::BadRequest(e) => write!(f, "Bad request: {}", e),
+            DocumentError::Registrar(e) => write!(f, "{}", e),
+            DocumentError::Unverified(verdict) => {
+                write!(f, "The document couldn't be verified: {:?}", verdict)
+            }
+            DocumentError::InvalidDates => write!(f, "The document's dates are invalid"),
+            DocumentError::Expired(date) => write!(f, "The document expired on {}", date),
+            DocumentError::Internal(e) => write!(f, "Internal error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DocumentError {}
+
+impl DocumentError {
+    /// The error code returned, also all that's logged of a refusal
+    pub fn code(&self) -> &'static str {
+        match self {
+            DocumentError::BadRequest(_) => "ERR_BAD_REQUEST",
+            DocumentError::Registrar(e) => match e {
+                RegistrarError::UnreadableImage(_) | RegistrarError::UnsupportedFormat(_) => {
+                    "ERR_UNSUPPORTED_DOCUMENT"
+                }
+                RegistrarError::InvalidMrz { .. } => "ERR_INVALID_MRZ",
+                RegistrarError::Forged(_) => "ERR_FORGED_DOCUMENT",
+                RegistrarError::Ocr(_) | RegistrarError::Chip(_) => "ERR_REGISTRAR",
+            },
+            DocumentError::Unverified(_) => "ERR_UNVERIFIED_DOCUMENT",
+            DocumentError::InvalidDates => "ERR_INVALID_DATES",
+            DocumentError::Expired(_) => "ERR_EXPIRED_DOCUMENT",
+            DocumentError::Internal(_) => "ERR_INTERNAL",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            DocumentError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            DocumentError::Registrar(
+                RegistrarError::UnreadableImage(_) | RegistrarError::UnsupportedFormat(_),
+            ) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            DocumentError::Registrar(RegistrarError::Ocr(_)) | DocumentError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl From<RegistrarError> for DocumentError {
+    fn from(e: RegistrarError) -> Self {
+        DocumentError::Registrar(e)
+    }
+}
+
+/// The message is returned to the user who submitted the document, it may
+/// quote the MRZ
+impl IntoResponse for DocumentError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({
+            "error": self.code(),
+            "message": self.to_string(),
+        });
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+/// Run OCR, the forgery check and MRZ validation over a document, refusing
+/// documents expired before `today`
+pub fn extract(
+    registrar: &Registrar,
+    document: &[u8],
+    today: Date,
+) -> Result<(Claims, Identity), DocumentError> {
+    let result = registrar.extract_mrz(document)?;
+    if !result.validation.is_accepted() {
+        return Err(DocumentError::Unverified(result.validation.verdict));
+    }
+    let identity = result.identity().ok_or(DocumentError::InvalidDates)?;
+    if identity.is_expired(today) {
+        return Err(DocumentError::Expired(identity.expiry_date.as_number()));
+    }
+    Ok((Claims::new(result.format, &identity), identity))
+}
+
+/// Check the holder controls `holder`, `signature` must be theirs over the
+/// sha256 of the uploaded document so it can't be replayed for another upload
+pub fn check_holder(
+    holder: &PublicKey,
+    signature: &Signature,
+    upload: &[u8],
+) -> Result<(), DocumentError> {
+    let hash = near_primitives::hash::hash(upload);
+    if signature.verify(hash.as_ref(), holder) {
+        Ok(())
+    } else {
+        Err(DocumentError::BadRequest("Invalid signature".to_string()))
+    }
+}
+
+/// Attest `identity` to the holder of an ed25519 NEAR key with a fresh salt
+pub fn attest(
+    attester: &Attester,
+    identity: Identity,
+    holder: &PublicKey,
+) -> Result<Attestation, DocumentError> {
+    use rand_chacha::rand_core::{RngCore, SeedableRng};
+
+    let holder: [u8; 32] = match holder.key_type() {
+        KeyType::ED25519 => holder
+            .key_data()
+            .try_into()
+            .expect("ed25519 keys are 32 bytes"),
+        _ => {
+            return Err(DocumentError::BadRequest(
+                "The key must be ed25519".to_string(),
+            ))
+        }
+    };
+    let mut salt = [0; 32];
+    rand_chacha::ChaCha20Rng::from_entropy().fill_bytes(&mut salt);
+    attester
+        .attest(identity, holder, salt)
+        .map_err(|e| DocumentError::Internal(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_crypto::InMemorySigner;
+
+    fn claims() -> Claims {
+        Claims {
+            format: "Td3".to_string(),
+            nationality: "UTO".to_string(),
+            birth_date: 19740812,
+            expiry_date: 20120415,
+        }
+    }
+
+    #[test]
+    fn test_signed_claims() {
+        let signer =
+            InMemorySigner::from_seed("prover.testnet".parse().unwrap(), KeyType::ED25519, "test");
+        let holder = near_crypto::SecretKey::from_seed(KeyType::ED25519, "holder").public_key();
+        let signed = SignedClaims::sign(claims(), holder.clone(), 1_700_000_000, &signer);
+        assert!(signed.verify());
+
+        // The signature doesn't depend on the JSON
+        let mut json = serde_json::to_value(&signed).unwrap();
+        let reordered = serde_json::json!({
+            "expiry_date": json["claims"]["expiry_date"],
+            "birth_date": json["claims"]["birth_date"],
+            "nationality": json["claims"]["nationality"],
+            "format": json["claims"]["format"],
+        });
+        json["claims"] = reordered;
+        let parsed: SignedClaims = serde_json::from_value(json).unwrap();
+        assert!(parsed.verify());
+
+        let mut forged = signed.clone();
+        forged.claims.birth_date = 19700101;
+        assert!(!forged.verify());
+
+        let mut stolen = signed.clone();
+        stolen.holder = near_crypto::SecretKey::from_seed(KeyType::ED25519, "thief").public_key();
+        assert!(!stolen.verify());
+
+        let mut backdated = signed;
+        backdated.issued_at -= 1;
+        assert!(!backdated.verify());
+    }
+
+    #[test]
+    fn test_error_responses() {
+        let err = DocumentError::Registrar(RegistrarError::UnsupportedFormat("HEIC".into()));
+        assert_eq!(err.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let err = DocumentError::Registrar(RegistrarError::Forged(Default::default()));
+        assert_eq!(err.code(), "ERR_FORGED_DOCUMENT");
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let err = DocumentError::BadRequest("No document".into());
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_extract() {
+        let sample = std::fs::read("../../crates/registrar/assets/sample.jpg").unwrap();
+        let registrar = RegistrarConfig::default().registrar();
+        // The sample expires on 2016-01-31
+        let (claims, _) = extract(&registrar, &sample, Date::from_days(16_000)).unwrap();
+        assert_eq!(claims.format, "Td3");
+
+        let err = extract(&registrar, &sample, Date::today()).unwrap_err();
+        assert_eq!(err.code(), "ERR_EXPIRED_DOCUMENT");
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let err = extract(&registrar, b"not an image", Date::today()).unwrap_err();
+        assert_eq!(err.code(), "ERR_UNSUPPORTED_DOCUMENT");
+    }
+
+    #[test]
+    fn test_check_holder() {
+        let key = near_crypto::SecretKey::from_random(KeyType::ED25519);
+        let upload = b"document";
+        let hash = near_primitives::hash::hash(upload);
+        let signature = key.sign(hash.as_ref());
+        assert!(check_holder(&key.public_key(), &signature, upload).is_ok());
+
+        let err = check_holder(&key.public_key(), &signature, b"another").unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        let other = near_crypto::SecretKey::from_random(KeyType::ED25519).public_key();
+        assert!(check_holder(&other, &signature, upload).is_err());
+        let unhashed = key.sign(upload);
+        assert!(check_holder(&key.public_key(), &unhashed, upload).is_err());
+    }
+
+    #[test]
+    fn test_attest() {
+        let key = near_crypto::SecretKey::from_random(KeyType::SECP256K1);
+        let config = RegistrarConfig {
+            attestation_key: Some(SecretSource::Inline(key.clone())),
+            ..Default::default()
+        };
+        let attester = config.attester().unwrap().unwrap();
+        assert_eq!(&attester.public_key()[..], key.public_key().key_data());
+        assert_eq!(config.registrar_key().unwrap(), Some(attester.public_key()));
+        assert!(RegistrarConfig::default().attester().unwrap().is_none());
+
+        let identity = Identity {
+            nationality: "UTO".to_string(),
+            birth_date: registrar::identity::Date::from_days(1_684),
+            expiry_date: registrar::identity::Date::from_days(15_445),
+        };
+        let holder = near_crypto::SecretKey::from_seed(KeyType::ED25519, "holder").public_key();
+        let attestation = attest(&attester, identity.clone(), &holder).unwrap();
+        assert!(attestation.verify(&attester.public_key()));
+        assert_eq!(&attestation.holder[..], holder.key_data());
+
+        let claims = Claims::new(registrar::mrz::Format::Td3, &identity);
+        assert_eq!(claims.birth_date, 19740812);
+        let attested = AttestedClaims::new(claims, &attestation);
+        let json = serde_json::to_value(&attested).unwrap();
+        assert_eq!(json["salt"].as_str().unwrap().len(), 64);
+        assert_eq!(
+            serde_json::from_value::<AttestedClaims>(json).unwrap(),
+            attested
+        );
+
+        let secp = near_crypto::SecretKey::from_seed(KeyType::SECP256K1, "holder").public_key();
+        assert!(attest(&attester, identity, &secp).is_err());
+    }
+}
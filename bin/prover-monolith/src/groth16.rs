@@ -0,0 +1,211 @@
+//! Groth16 keys and proofs for the lender's onchain verifier. Groth16 backends
+//! for nargo export them in the snarkjs json layout, decimal coordinates with a
+//! projective `z`, which is converted to the little endian points the lender's
+//! `set_verification_key` and `verify_and_loan` take.
+use eyre::Result;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+
+const SCALAR_LEN: usize = 32;
+
+/// A verification key as snarkjs writes it
+#[derive(Clone, Debug, Deserialize)]
+pub struct SnarkjsVerificationKey {
+    pub vk_alpha_1: [String; 3],
+    pub vk_beta_2: [[String; 2]; 3],
+    pub vk_gamma_2: [[String; 2]; 3],
+    pub vk_delta_2: [[String; 2]; 3],
+    #[serde(rename = "IC")]
+    pub ic: Vec<[String; 3]>,
+}
+
+/// A proof as snarkjs writes it
+#[derive(Clone, Debug, Deserialize)]
+pub struct SnarkjsProof {
+    pub pi_a: [String; 3],
+    pub pi_b: [[String; 2]; 3],
+    pub pi_c: [String; 3],
+}
+
+/// `VerificationKey` in the lender
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VerificationKey {
+    #[serde_as(as = "serde_with::base64::Base64")]
+    pub alpha_g1: Vec<u8>,
+    #[serde_as(as = "serde_with::base64::Base64")]
+    pub beta_g2: Vec<u8>,
+    #[serde_as(as = "serde_with::base64::Base64")]
+    pub gamma_g2: Vec<u8>,
+    #[serde_as(as = "serde_with::base64::Base64")]
+    pub delta_g2: Vec<u8>,
+    #[serde_as(as = "Vec<serde_with::base64::Base64>")]
+    pub ic: Vec<Vec<u8>>,
+}
+
+/// `Groth16Proof` in the lender
+#[serde_with::serde_as]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Groth16Proof {
+    #[serde_as(as = "serde_with::base64::Base64")]
+    pub a: Vec<u8>,
+    #[serde_as(as = "serde_with::base64::Base64")]
+    pub b: Vec<u8>,
+    #[serde_as(as = "serde_with::base64::Base64")]
+    pub c: Vec<u8>,
+}
+
+impl TryFrom<&SnarkjsVerificationKey> for VerificationKey {
+    type Error = eyre::Report;
+
+    fn try_from(vk: &SnarkjsVerificationKey) -> Result<Self> {
+        Ok(Self {
+            alpha_g1: g1(&vk.vk_alpha_1)?,
+            beta_g2: g2(&vk.vk_beta_2)?,
+            gamma_g2: g2(&vk.vk_gamma_2)?,
+            delta_g2: g2(&vk.vk_delta_2)?,
+            ic: vk.ic.iter().map(g1).collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl TryFrom<&SnarkjsProof> for Groth16Proof {
+    type Error = eyre::Report;
+
+    fn try_from(proof: &SnarkjsProof) -> Result<Self> {
+        Ok(Self {
+            a: g1(&proof.pi_a)?,
+            b: g2(&proof.pi_b)?,
+            c: g1(&proof.pi_c)?,
+        })
+    }
+}
+
+/// `x || y`, the point at infinity is all zeroes
+fn g1(point: &[String; 3]) -> Result<Vec<u8>> {
+    if is_zero(&point[2])? {
+        return Ok(vec![0; 2 * SCALAR_LEN]);
+    }
+    Ok([scalar(&point[0])?, scalar(&point[1])?].concat())
+}
+
+/// `x.c0 || x.c1 || y.c0 || y.c1`, the point at infinity is all zeroes
+fn g2(point: &[[String; 2]; 3]) -> Result<Vec<u8>> {
+    if is_zero(&point[2][0])? && is_zero(&point[2][1])? {
+        return Ok(vec![0; 4 * SCALAR_LEN]);
+    }
+    let coordinates = [&point[0][0], &point[0][1], &point[1][0], &point[1][1]];
+    Ok(coordinates
+        .into_iter()
+        .map(String::as_str)
+        .map(scalar)
+        .collect::<Result<Vec<_>>>()?
+        .concat())
+}
+
+fn is_zero(decimal: &str) -> Result<bool> {
+    Ok(parse(decimal)? == BigUint::default())
+}
+
+fn parse(decimal: &str) -> Result<BigUint> {
+    BigUint::parse_bytes(decimal.trim().as_bytes(), 10)
+        .ok_or_else(|| eyre::eyre!("{:?} isn't a decimal coordinate", decimal))
+}
+
+/// Little endian, padded to the field size
+fn scalar(decimal: &str) -> Result<Vec<u8>> {
+    let mut bytes = parse(decimal)?.to_bytes_le();
+    eyre::ensure!(
+        bytes.len() <= SCALAR_LEN,
+        "{} is larger than a field element",
+        decimal
+    );
+    bytes.resize(SCALAR_LEN, 0);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The BN254 G2 generator
+    fn g2_generator() -> [[String; 2]; 3] {
+        [
+            [
+                "10857046999023057135944570762232829481370756359578518086990519993285655852781",
+                "11559732032986387107991004021392285783925812861821192530917403151452391805634",
+            ],
+            [
+                "8495653923123431417604973247489272438418190587263600148770280649306958101930",
+                "4082367875863433681332203403145435568316851327593401208105741076214120093531",
+            ],
+            ["1", "0"],
+        ]
+        .map(|x| x.map(String::from))
+    }
+
+    fn g1_generator() -> [String; 3] {
+        ["1", "2", "1"].map(String::from)
+    }
+
+    #[test]
+    fn test_points() {
+        let g1 = g1(&g1_generator()).unwrap();
+        assert_eq!(g1.len(), 64);
+        assert_eq!((g1[0], g1[32]), (1, 2));
+        assert!(g1[1..32].iter().chain(&g1[33..]).all(|x| *x == 0));
+
+        // The same bytes as the generator in the lender's verifier tests
+        assert_eq!(
+            hex::encode(super::g2(&g2_generator()).unwrap()),
+            "edf692d95cbdde46ddda5ef7d422436779445c5e66006a42761e1f12efde0018\
+             c212f3aeb785e49712e7a9353349aaf1255dfb31b7bf60723a480d9293938e19\
+             aa7dfa6601cce64c7bd3430c69e7d1e38f40cb8d8071ab4aeb6d8cdba55ec812\
+             5b9722d1dcdaac55f38eb37033314bbc95330c69ad999eec75f05f58d0890609"
+        );
+
+        let infinity = ["0", "1", "0"].map(String::from);
+        assert_eq!(super::g1(&infinity).unwrap(), vec![0; 64]);
+        assert!(scalar("not a number").is_err());
+        assert!(scalar(&"9".repeat(80)).is_err());
+    }
+
+    #[test]
+    fn test_verification_key() {
+        let vk = SnarkjsVerificationKey {
+            vk_alpha_1: g1_generator(),
+            vk_beta_2: g2_generator(),
+            vk_gamma_2: g2_generator(),
+            vk_delta_2: g2_generator(),
+            ic: vec![g1_generator(); 3],
+        };
+        let key = VerificationKey::try_from(&vk).unwrap();
+        assert_eq!(key.ic.len(), 3);
+        let json = serde_json::to_value(&key).unwrap();
+        // Base64VecU8 in the lender
+        assert_eq!(
+            json["alpha_g1"],
+            "AQAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAACAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=="
+        );
+    }
+
+    /// The snarkjs fixtures convert to the ones the lender verifies with
+    #[test]
+    fn test_fixtures() {
+        fn read<T: serde::de::DeserializeOwned>(path: &str) -> T {
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+        }
+
+        let vk: SnarkjsVerificationKey = read("fixtures/groth16_vk.json");
+        let key = VerificationKey::try_from(&vk).unwrap();
+        assert_eq!(key.ic.len(), 44);
+        let lender: VerificationKey =
+            read("../../contracts/register/fixtures/verification_key.json");
+        assert_eq!(key, lender);
+
+        let proof: SnarkjsProof = read("fixtures/groth16_proof.json");
+        let proof = Groth16Proof::try_from(&proof).unwrap();
+        let lender: Groth16Proof = read("../../contracts/register/fixtures/proof.json");
+        assert_eq!(proof, lender);
+    }
+}
@@ -1,42 +1,93 @@
 use axum::{
-    extract::{Query, State},
+    extract::{DefaultBodyLimit, Multipart, Query, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
     routing::post,
     Json, Router,
 };
+use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
 use eyre::Result;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use tower_http::cors::CorsLayer;
+use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
+    timeout::TimeoutLayer,
+};
 
+pub mod check;
+pub mod circuit;
+pub mod cli;
 pub mod config;
 pub mod contract;
+pub mod document;
+pub mod groth16;
 pub mod prover;
+pub mod reload;
+pub mod secret;
+pub mod social;
 #[tokio::main]
-async fn main() {
+async fn main() -> Result<()> {
     pretty_env_logger::init();
-    let config = config::Config::new().expect("Failed to load config");
+    let cli = cli::Cli::parse();
+    let config = config::Config::new(cli.network.as_deref())?;
+    cli.run(config).await
+}
 
-    let contract_client = Arc::new(contract::Client::new(&config));
+/// Serve the http api, requests are served with whatever is active in `shared`
+pub async fn serve(shared: reload::Shared) -> Result<()> {
+    // Server settings aren't reloaded
+    let config = shared.load().config.clone();
 
     let controller = Router::new()
         // Request to prove a credit application
-        .route("/prove", post(prove).with_state(config.clone()))
+        .route("/prove", post(prove))
         // Prove and then soft verify a credit appllication
-        .route(
-            "/prove/verify",
-            post(prove_verify).with_state((contract_client.clone(), config.clone())),
-        )
+        .route("/prove/verify", post(prove_verify))
         // Verify a proof and release the funds
-        .route(
-            "/verify",
-            post(verify).with_state((contract_client.clone(), config.clone())),
-        )
-        .layer(CorsLayer::permissive());
-
-    axum::Server::bind(&"0.0.0.0:3000".parse().unwrap())
-        .serve(controller.into_make_service())
-        .await
-        .expect("Failed to start server");
+        .route("/verify", post(verify))
+        // Request to prove the registration criteria
+        .route("/register/prove", post(prove_registration))
+        // Verify a registration proof and register the user onchain
+        .route("/register", post(register))
+        // Read an identity document and sign the claims read from it
+        .route("/register/document", post(register_document))
+        .with_state(shared)
+        .layer(cors_layer(&config.server)?)
+        .layer(DefaultBodyLimit::max(config.server.body_limit))
+        .layer(TimeoutLayer::new(config.server.request_timeout()));
+
+    let addr = config.server.listen;
+    match &config.server.tls {
+        Some(tls) => {
+            let rustls = RustlsConfig::from_pem_file(&tls.cert, &tls.key).await?;
+            log::info!("Serving https on {}", addr);
+            axum_server::bind_rustls(addr, rustls)
+                .serve(controller.into_make_service())
+                .await?;
+        }
+        None => {
+            log::info!("Serving http on {}", addr);
+            axum::Server::bind(&addr)
+                .serve(controller.into_make_service())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+fn cors_layer(server: &config::ServerConfig) -> Result<CorsLayer> {
+    if server.allows_any_origin() {
+        return Ok(CorsLayer::permissive());
+    }
+    let origins = server
+        .cors_origins
+        .iter()
+        .map(|x| HeaderValue::from_str(x))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods([Method::POST])
+        .allow_headers([header::CONTENT_TYPE]))
 }
 
 /// A request to prove a credit application
@@ -46,6 +97,19 @@ pub struct ProofRequest {
     public_key: String,
     requested_amount: u64,
     params: Vec<Heuristic>,
+    /// The loan product applied for, see `Config::products`
+    #[serde(default)]
+    product: Option<String>,
+    /// The borrower's `get_nonce` on the lender, only checked when the proof is
+    /// verified onchain
+    #[serde(default)]
+    nonce: u64,
+    /// Set by the prover when proving, never deserialised so a request can't
+    /// pick the date identities are checked against or the registrar's key
+    #[serde(skip)]
+    proving_date: u32,
+    #[serde(skip)]
+    registrar_key: Option<[u8; 64]>,
 }
 
 impl ProofRequest {
@@ -57,7 +121,42 @@ impl ProofRequest {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Heuristic {
-    Simple { balance: u64 },
+    Simple {
+        balance: u64,
+    },
+    /// Identity claims the registrar attested to the applicant's public key,
+    /// the holder must be an adult with an unexpired document. The circuit
+    /// has no heuristic over unattested claims, they'd be self-asserted.
+    AttestedIdentity(document::AttestedClaims),
+}
+
+impl Heuristic {
+    /// Each heuristic's name in product configs and its id in the circuit
+    const IDS: [(&'static str, u8); 2] = [("Simple", 1), ("AttestedIdentity", 4)];
+
+    /// How products name the heuristic in their config
+    pub fn name(&self) -> &'static str {
+        match self {
+            Heuristic::Simple { .. } => "Simple",
+            Heuristic::AttestedIdentity(_) => "AttestedIdentity",
+        }
+    }
+
+    /// The name of the heuristic the circuit knows as `id`
+    pub fn name_of(id: u8) -> Option<&'static str> {
+        Self::IDS
+            .iter()
+            .find(|(_, x)| *x == id)
+            .map(|(name, _)| *name)
+    }
+
+    /// The circuit id of the heuristic products call `name`
+    pub fn id_of(name: &str) -> Option<u8> {
+        Self::IDS
+            .iter()
+            .find(|(x, _)| *x == name)
+            .map(|(_, id)| *id)
+    }
 }
 
 impl Into<u8> for Heuristic {
@@ -67,6 +166,7 @@ impl Into<u8> for Heuristic {
 
         let byte = match self {
             Heuristic::Simple { .. } => 1,
+            Heuristic::AttestedIdentity(_) => 4,
         };
         assert!(
             byte <= HEURISTIC_AMT,
@@ -79,7 +179,7 @@ impl Into<u8> for Heuristic {
 /// A proof of a credit application
 /// This requires account_id to be populated by the caller for the funds to be released onchain.
 ///
-/// We can modify this in the future to support public keys and allow the user to have 
+/// We can modify this in the future to support public keys and allow the user to have
 /// multiple loans against keys, and recover the loan if the key is changed.
 #[serde_with::serde_as]
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -89,25 +189,87 @@ pub struct Proof {
     #[serde(with = "hex")]
     inner: Vec<u8>,
     account_id: Option<String>,
+    /// The version of the circuit that produced the proof
+    #[serde(default)]
+    circuit_version: Option<String>,
+    /// The loan product the proof is for
+    #[serde(default)]
+    product: Option<String>,
+    #[serde(default)]
+    nonce: u64,
+    /// The `YYYYMMDD` date identities were checked against, verifiers refuse
+    /// proofs made before yesterday
+    #[serde(default)]
+    proving_date: u32,
+    /// The `x || y` registrar key attestations were checked with, verifiers
+    /// refuse any but their own
+    #[serde_as(as = "Option<serde_with::hex::Hex>")]
+    #[serde(default)]
+    registrar_key: Option<[u8; 64]>,
+    /// The id of the heuristic proven in each circuit slot, 0 when empty.
+    /// Public, so verifiers can hold the proof to its product's heuristics.
+    #[serde(default)]
+    heuristics: Vec<u8>,
+}
+
+impl Proof {
+    /// Identifies the proof to the contract, every prover attesting to the same
+    /// proof must derive the same hash. The circuit version and product are
+    /// bound in, so a loan can't be attested under others than were verified.
+    pub fn hash(&self) -> [u8; 32] {
+        use blake2::{Blake2s256, Digest};
+        let version = self.circuit_version.as_deref().unwrap_or_default();
+        let product = self.product.as_deref().unwrap_or_default();
+        Blake2s256::new()
+            .chain_update(Blake2s256::digest(&self.inner))
+            .chain_update(Blake2s256::digest(version))
+            .chain_update(product.as_bytes())
+            .finalize()
+            .into()
+    }
+}
+
+/// A request to prove the registration criteria, the prover reads the
+/// near.social profile of `account_id` itself
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RegistrationRequest {
+    public_key: String,
+    /// Must have `public_key` as an access key
+    account_id: String,
+}
+
+/// A proof that the registration criteria were met by the profile of `account_id`
+#[serde_with::serde_as]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RegistrationProof {
+    public_key: String,
+    #[serde(with = "hex")]
+    inner: Vec<u8>,
+    account_id: Option<String>,
+    /// The version of the circuit that produced the proof
+    #[serde(default)]
+    circuit_version: Option<String>,
 }
 
 async fn prove(
-    State(config): State<config::Config>,
+    State(shared): State<reload::Shared>,
     Json(req): Json<ProofRequest>,
 ) -> Json<Option<Proof>> {
-    let cmd = prover::Command::Prove(req);
-    Json(prover::execute(&config, cmd).ok())
+    let active = shared.load_full();
+    Json(active.circuits.prove(&active.config, req).await.ok())
 }
 
 async fn prove_verify(
-    State((client, config)): State<(Arc<contract::Client>, config::Config)>,
+    State(shared): State<reload::Shared>,
     Json(req): Json<ProofRequest>,
 ) -> Json<bool> {
-    let proof = prove(State(config.clone()), Json(req)).await.0;
+    let proof = prove(State(shared.clone()), Json(req)).await.0;
     if let Some(proof) = proof {
         // Ensure we don't submit for soft-approvals
         let no_submit = Submission { submit: false };
-        verify(State((client, config)), Some(Query(no_submit)), Json(proof)).await
+        verify(State(shared), Some(Query(no_submit)), Json(proof))
+            .await
+            .unwrap_or(Json(false))
     } else {
         Json(false)
     }
@@ -126,19 +288,52 @@ impl Default for Submission {
 /// Newtype to implement From<CommandStdout>
 pub struct VerificationResult(bool);
 
+/// Proofs of retired circuits are refused outright rather than failing verification
+impl IntoResponse for circuit::RetiredCircuit {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({
+            "error": "ERR_RETIRED_CIRCUIT",
+            "message": self.to_string(),
+            "active_version": self.active,
+        });
+        (StatusCode::GONE, Json(body)).into_response()
+    }
+}
+
 async fn verify(
-    State((client, config)): State<(Arc<contract::Client>, config::Config)>,
+    State(shared): State<reload::Shared>,
     submission: Option<Query<Submission>>,
-    Json(proof): Json<Proof>,
-) -> Json<bool> {
+    Json(mut proof): Json<Proof>,
+) -> std::result::Result<Json<bool>, circuit::RetiredCircuit> {
+    let active = shared.load_full();
     let cmd = prover::Command::Verify(proof.clone());
-    let executed = prover::execute::<VerificationResult>(&config, cmd);
+    let package = match cmd.package(&active.config) {
+        Ok(package) => package,
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            return Ok(Json(false));
+        }
+    };
+    active
+        .circuits
+        .check_version(package, proof.circuit_version.as_deref())?;
+    // Recorded onchain, so it's the version and product verifying rather than
+    // the claimed ones
+    proof.circuit_version = active.circuits.version(package).map(String::from);
+    proof.product = active.config.product_of(package);
+    let executed = active.circuits.verify(&active.config, proof.clone()).await;
     let verified = match executed {
-        Ok(res) => {
+        Ok(verified) => {
             if submission.unwrap_or_default().submit {
-                client.verified(proof).await.unwrap_or_default()
+                match active.client.verified(proof).await {
+                    Ok(released) => released,
+                    Err(e) => {
+                        log::error!("Loan not released: {}", e);
+                        false
+                    }
+                }
             } else {
-                res.0
+                verified
             }
         }
         Err(e) => {
@@ -146,7 +341,273 @@ async fn verify(
             false
         }
     };
-    Json(verified)
+    Ok(Json(verified))
+}
+
+/// Why a registration was refused
+#[derive(Debug)]
+pub enum RegistrationError {
+    Retired(circuit::RetiredCircuit),
+    /// The public key or account id are malformed, or the key isn't an access
+    /// key of the account
+    BadRequest(String),
+    InvalidProof,
+    /// The account's near.social profile doesn't meet the registration criteria
+    CriteriaNotMet,
+    /// The user must register with the lender before they're verified
+    NotRegistered,
+    Internal(String),
+}
+
+impl std::fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistrationError::Retired(e) => write!(f, "{}", e),
+            RegistrationError::BadRequest(e) => write!(f, "Bad request: {}", e),
+            RegistrationError::InvalidProof => write!(f, "The proof failed verification"),
+            RegistrationError::CriteriaNotMet => {
+                write!(f, "The near.social profile doesn't meet the criteria")
+            }
+            RegistrationError::NotRegistered => write!(f, "The user isn't registered"),
+            RegistrationError::Internal(e) => write!(f, "Internal error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RegistrationError {}
+
+impl RegistrationError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RegistrationError::Retired(_) => "ERR_RETIRED_CIRCUIT",
+            RegistrationError::BadRequest(_) => "ERR_BAD_REQUEST",
+            RegistrationError::InvalidProof => "ERR_INVALID_PROOF",
+            RegistrationError::CriteriaNotMet => "ERR_CRITERIA_NOT_MET",
+            RegistrationError::NotRegistered => "ERR_NOT_REGISTERED",
+            RegistrationError::Internal(_) => "ERR_INTERNAL",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RegistrationError::Retired(_) => StatusCode::GONE,
+            RegistrationError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            RegistrationError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl From<circuit::RetiredCircuit> for RegistrationError {
+    fn from(e: circuit::RetiredCircuit) -> Self {
+        RegistrationError::Retired(e)
+    }
+}
+
+impl IntoResponse for RegistrationError {
+    fn into_response(self) -> Response {
+        match self {
+            RegistrationError::Retired(e) => e.into_response(),
+            e => {
+                let body = serde_json::json!({
+                    "error": e.code(),
+                    "message": e.to_string(),
+                });
+                (e.status(), Json(body)).into_response()
+            }
+        }
+    }
+}
+
+/// Prove the registration criteria with the near.social profile of the account
+/// holding `public_key`, `None` when the profile doesn't meet them
+async fn prove_registration(
+    State(shared): State<reload::Shared>,
+    Json(req): Json<RegistrationRequest>,
+) -> std::result::Result<Json<Option<RegistrationProof>>, RegistrationError> {
+    let active = shared.load_full();
+    let account = active
+        .client
+        .account_for_key(Some(&req.account_id), &req.public_key)
+        .await
+        .map_err(|e| RegistrationError::BadRequest(e.to_string()))?;
+    let profile = match active.client.social_profile(&account).await {
+        Ok(profile) => profile,
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            return Ok(Json(None));
+        }
+    };
+    match active
+        .circuits
+        .prove_registration(&active.config, req, profile)
+        .await
+    {
+        Ok(proof) => Ok(Json(Some(proof))),
+        // Malformed keys are the caller's mistake rather than the prover's
+        Err(e) if e.is::<prover::InvalidPublicKey>() => {
+            Err(RegistrationError::BadRequest(e.to_string()))
+        }
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            Ok(Json(None))
+        }
+    }
+}
+
+/// Verify a registration proof and mark the user verified onchain. The proof's
+/// witnesses are private, so it can be made offline with any profile, and the
+/// account's near.social profile is read again here before it's accepted.
+async fn register(
+    State(shared): State<reload::Shared>,
+    Json(proof): Json<RegistrationProof>,
+) -> std::result::Result<Json<bool>, RegistrationError> {
+    let active = shared.load_full();
+    active.circuits.check_version(
+        prover::REGISTRATION_PACKAGE,
+        proof.circuit_version.as_deref(),
+    )?;
+    let account = active
+        .client
+        .account_for_key(proof.account_id.as_ref(), &proof.public_key)
+        .await
+        .map_err(|e| RegistrationError::BadRequest(e.to_string()))?;
+    let verified = match active
+        .circuits
+        .verify_registration(&active.config, proof)
+        .await
+    {
+        Ok(verified) => verified,
+        Err(e) if e.is::<prover::InvalidPublicKey>() => {
+            return Err(RegistrationError::BadRequest(e.to_string()))
+        }
+        Err(e) => {
+            log::error!("Error: {:?}", e);
+            false
+        }
+    };
+    if !verified {
+        return Err(RegistrationError::InvalidProof);
+    }
+    let profile = active
+        .client
+        .social_profile(&account)
+        .await
+        .map_err(|e| RegistrationError::Internal(e.to_string()))?;
+    if !profile.meets_criteria() {
+        return Err(RegistrationError::CriteriaNotMet);
+    }
+    let registered = active
+        .client
+        .register_verified(&account)
+        .await
+        .map_err(|e| RegistrationError::Internal(e.to_string()))?;
+    // The contract answers false for users it already verified too
+    if registered
+        || active
+            .client
+            .is_verified(&account)
+            .await
+            .map_err(|e| RegistrationError::Internal(e.to_string()))?
+    {
+        Ok(Json(true))
+    } else {
+        Err(RegistrationError::NotRegistered)
+    }
+}
+
+/// Read the identity document in the `document` field of a multipart form,
+/// the image only lives in memory for the length of the request. The claims
+/// are signed for the holder's NEAR key in `public_key`, and attested too when
+/// the registrar has an attestation key. The holder proves control of the key
+/// with `signature`, theirs over the sha256 of the document.
+async fn register_document(
+    State(shared): State<reload::Shared>,
+    mut multipart: Multipart,
+) -> std::result::Result<Json<document::SignedClaims>, document::DocumentError> {
+    use document::DocumentError;
+
+    let mut upload = None;
+    let mut holder = None;
+    let mut signature = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| DocumentError::BadRequest(e.to_string()))?
+    {
+        match field.name() {
+            Some("document") => {
+                let bytes = field
+                    .bytes()
+                    .await
+                    .map_err(|e| DocumentError::BadRequest(e.to_string()))?;
+                upload = Some(bytes);
+            }
+            Some("public_key") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| DocumentError::BadRequest(e.to_string()))?;
+                let key: near_crypto::PublicKey = text
+                    .trim()
+                    .parse()
+                    .map_err(|_| DocumentError::BadRequest("Invalid public key".to_string()))?;
+                holder = Some(key);
+            }
+            Some("signature") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| DocumentError::BadRequest(e.to_string()))?;
+                let sig: near_crypto::Signature = text
+                    .trim()
+                    .parse()
+                    .map_err(|_| DocumentError::BadRequest("Invalid signature".to_string()))?;
+                signature = Some(sig);
+            }
+            _ => {}
+        }
+    }
+    let upload =
+        upload.ok_or_else(|| DocumentError::BadRequest("No document field".to_string()))?;
+    let holder =
+        holder.ok_or_else(|| DocumentError::BadRequest("No public_key field".to_string()))?;
+    let signature =
+        signature.ok_or_else(|| DocumentError::BadRequest("No signature field".to_string()))?;
+    document::check_holder(&holder, &signature, &upload)?;
+
+    let issued_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| DocumentError::Internal(e.to_string()))?
+        .as_secs();
+    let today = registrar::identity::Date::from_days((issued_at / 86_400) as i64);
+    let active = shared.load_full();
+    let registrar = active.config.registrar.registrar();
+    // OCR is slow and blocking
+    let extracted =
+        tokio::task::spawn_blocking(move || document::extract(&registrar, &upload, today))
+            .await
+            .map_err(|e| DocumentError::Internal(e.to_string()))?;
+    let (claims, identity) = extracted.map_err(|e| {
+        log::warn!("Document refused: {}", e.code());
+        e
+    })?;
+    let attester = active
+        .config
+        .registrar
+        .attester()
+        .map_err(|e| DocumentError::Internal(e.to_string()))?;
+    let mut signed = document::SignedClaims::sign(
+        claims.clone(),
+        holder.clone(),
+        issued_at,
+        active.client.signer(),
+    );
+    if let Some(attester) = attester {
+        let attestation = document::attest(&attester, identity, &holder)?;
+        signed.attestation = Some(document::AttestedClaims::new(claims, &attestation));
+    }
+    Ok(Json(signed))
 }
 
 #[cfg(test)]
@@ -166,4 +627,46 @@ mod tests {
         let proof: ProofRequest = serde_json::from_str(&json).unwrap();
         println!("{:?}", proof);
     }
+
+    #[test]
+    fn test_cors_layer() {
+        let mut server = config::ServerConfig::default();
+        assert!(cors_layer(&server).is_ok());
+        server.cors_origins = vec!["https://near.org".into()];
+        assert!(cors_layer(&server).is_ok());
+        server.cors_origins = vec!["https://near.org\n".into()];
+        assert!(cors_layer(&server).is_err());
+    }
+
+    #[test]
+    fn test_registration_request_deserialisation() {
+        let json = std::fs::read_to_string("fixtures/registration.json").unwrap();
+        let req: RegistrationRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(req.account_id, "alice.testnet");
+    }
+
+    #[test]
+    fn test_heuristic_ids() {
+        let simple = Heuristic::Simple { balance: 1 };
+        assert_eq!(Heuristic::id_of(simple.name()), Some(simple.clone().into()));
+        assert_eq!(Heuristic::name_of(1), Some("Simple"));
+        assert_eq!(Heuristic::id_of("AttestedIdentity"), Some(4));
+        // Neither the noop nor the removed self-asserted identity are named
+        assert_eq!(Heuristic::name_of(0), None);
+        assert_eq!(Heuristic::name_of(3), None);
+    }
+
+    #[test]
+    fn test_registration_error_responses() {
+        let err = RegistrationError::BadRequest("Invalid public key".into());
+        assert_eq!(err.into_response().status(), StatusCode::BAD_REQUEST);
+        let err = RegistrationError::CriteriaNotMet;
+        assert_eq!(err.code(), "ERR_CRITERIA_NOT_MET");
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let err = RegistrationError::from(circuit::RetiredCircuit {
+            version: None,
+            active: "abc".into(),
+        });
+        assert_eq!(err.into_response().status(), StatusCode::GONE);
+    }
 }
@@ -1,47 +1,115 @@
 use eyre::Report;
 use near_crypto::PublicKey;
 use serde::{Deserialize, Serialize};
-use tempfile::{tempfile, NamedTempFile, TempDir};
+use tempfile::TempDir;
 
-use crate::{config::Config, Heuristic, Proof, ProofRequest, Result, VerificationResult};
+use crate::{
+    config::Config, social::SocialProfile, Heuristic, Proof, ProofRequest, RegistrationProof,
+    RegistrationRequest, Result, VerificationResult,
+};
+use registrar::identity::Date;
 use std::{
     fs::File,
-    io::{Read, Write},
+    io::Write,
     path::{Path, PathBuf},
-    process::Command as ProcessCommand,
     str::FromStr,
 };
+use tokio::process::Command as ProcessCommand;
+
+/// The heuristic slots of the `apply` circuit, `HEURISTICS` in main.nr
+const HEURISTICS: usize = 4;
+
+/// A request's `public_key` isn't an ed25519 NEAR key, the caller's mistake
+/// rather than the prover's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPublicKey(pub String);
+
+impl std::fmt::Display for InvalidPublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid public key {:?}, expected an ed25519 key",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidPublicKey {}
+
+/// The 32 bytes of an ed25519 key as the circuits take it
+fn key_bytes(public_key: &str) -> std::result::Result<[u8; 32], InvalidPublicKey> {
+    PublicKey::from_str(public_key)
+        .ok()
+        .and_then(|pk| pk.key_data().try_into().ok())
+        .ok_or_else(|| InvalidPublicKey(public_key.to_string()))
+}
+
+/// How many days before today a proof may have been made, so proofs made just
+/// before midnight still verify
+pub const MAX_PROOF_AGE_DAYS: i64 = 1;
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 struct InternalProofRequest {
     public_key: [u8; 32],
     requested_amount: String,
+    nonce: String,
+    today: String,
+    registrar: [String; 4],
+    heuristics: [u8; HEURISTICS],
     params: Vec<InternalHeuristic>,
 }
 
-impl From<ProofRequest> for InternalProofRequest {
-    fn from(value: ProofRequest) -> Self {
+/// The circuit slot of each heuristic, a heuristic's slot is its id less one
+/// and empty slots are noops
+fn slots(params: &[Heuristic]) -> Vec<InternalHeuristic> {
+    let mut slots = vec![InternalHeuristic::default(); HEURISTICS];
+    for param in params {
+        let param = InternalHeuristic::from(param.clone());
+        let slot = (param.id - 1) as usize;
+        slots[slot] = param;
+    }
+    slots
+}
+
+/// The registrar key as the circuit takes it, 16 byte halves of `x || y`
+fn registrar_fields(key: Option<[u8; 64]>) -> [String; 4] {
+    match key {
+        Some(key) => std::array::from_fn(|i| format!("0x{}", hex::encode(&key[i * 16..][..16]))),
+        None => std::array::from_fn(|_| "0".to_string()),
+    }
+}
+
+/// Days since the unix epoch
+fn days_since_epoch() -> i64 {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    (secs / 86_400) as i64
+}
+
+/// Whether `proving_date` is at most [`MAX_PROOF_AGE_DAYS`] before `today`, in
+/// days since the unix epoch
+fn is_recent(proving_date: u32, today: i64) -> bool {
+    (0..=MAX_PROOF_AGE_DAYS).any(|age| Date::from_days(today - age).as_number() == proving_date)
+}
+
+impl TryFrom<ProofRequest> for InternalProofRequest {
+    type Error = InvalidPublicKey;
+
+    fn try_from(value: ProofRequest) -> std::result::Result<Self, Self::Error> {
         log::debug!("Params: {:?}", value);
-        let pk: PublicKey = PublicKey::from_str(&value.public_key).expect("Invalid public key");
-        let mut params = vec![InternalHeuristic::default(); 2];
-
-        let mut internal_heuristics: Vec<InternalHeuristic> = value
-            .params
-            .into_iter()
-            .map(InternalHeuristic::from)
-            .collect();
-        internal_heuristics.sort_by(|x, y| x.id.cmp(&y.id));
-
-        for param in internal_heuristics {
-            let id = param.id - 1;
-            params[id as usize] = param;
-        }
+        let public_key = key_bytes(&value.public_key)?;
+        let params = slots(&value.params);
 
-        InternalProofRequest {
-            public_key: pk.key_data().try_into().unwrap(),
+        Ok(InternalProofRequest {
+            public_key,
             requested_amount: value.requested_amount.to_string(),
+            nonce: value.nonce.to_string(),
+            today: value.proving_date.to_string(),
+            registrar: registrar_fields(value.registrar_key),
+            heuristics: std::array::from_fn(|i| params[i].id),
             params,
-        }
+        })
     }
 }
 
@@ -74,8 +142,23 @@ impl From<Heuristic> for InternalHeuristic {
     fn from(value: Heuristic) -> Self {
         let mut params = Self::sparse_params();
 
-        match value {
+        match &value {
             Heuristic::Simple { balance } => params[0] = balance.to_string(),
+            Heuristic::AttestedIdentity(attested) => {
+                // The circuit checks age and expiry against the proving date
+                let claims = &attested.claims;
+                params[0] = registrar::identity::country_code(&claims.nationality).to_string();
+                params[1] = claims.birth_date.to_string();
+                params[2] = claims.expiry_date.to_string();
+                // The salt and signature as 16 byte halves, they don't fit a field whole
+                let halves = attested
+                    .salt
+                    .chunks(16)
+                    .chain(attested.signature.chunks(16));
+                for (param, half) in params[3..].iter_mut().zip(halves) {
+                    *param = format!("0x{}", hex::encode(half));
+                }
+            }
         }
 
         InternalHeuristic {
@@ -89,101 +172,247 @@ impl From<Heuristic> for InternalHeuristic {
 pub struct InternalVerificationRequest {
     public_key: [u8; 32],
     requested_amount: String,
+    nonce: String,
+    today: String,
+    registrar: [String; 4],
+    heuristics: [u8; HEURISTICS],
 }
 
-impl From<Proof> for InternalVerificationRequest {
-    fn from(value: Proof) -> Self {
-        let pk: PublicKey = PublicKey::from_str(&value.public_key).expect("Invalid public key");
-        InternalVerificationRequest {
-            public_key: pk.key_data().try_into().unwrap(),
+impl TryFrom<Proof> for InternalVerificationRequest {
+    type Error = InvalidPublicKey;
+
+    fn try_from(value: Proof) -> std::result::Result<Self, Self::Error> {
+        Ok(InternalVerificationRequest {
+            public_key: key_bytes(&value.public_key)?,
             requested_amount: value.requested_amount.to_string(),
-        }
+            nonce: value.nonce.to_string(),
+            today: value.proving_date.to_string(),
+            registrar: registrar_fields(value.registrar_key),
+            // Checked to fill every slot before verifying
+            heuristics: std::array::from_fn(|i| value.heuristics.get(i).copied().unwrap_or(0)),
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct InternalRegistrationRequest {
+    public_key: [u8; 32],
+    avatar: bool,
+    posts: String,
+    username: bool,
+}
+
+impl TryFrom<(RegistrationRequest, SocialProfile)> for InternalRegistrationRequest {
+    type Error = InvalidPublicKey;
+
+    fn try_from(
+        (value, social): (RegistrationRequest, SocialProfile),
+    ) -> std::result::Result<Self, Self::Error> {
+        Ok(InternalRegistrationRequest {
+            public_key: key_bytes(&value.public_key)?,
+            avatar: social.avatar,
+            posts: social.posts.to_string(),
+            username: social.username,
+        })
     }
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct InternalRegistrationVerification {
+    public_key: [u8; 32],
+}
+
+impl TryFrom<RegistrationProof> for InternalRegistrationVerification {
+    type Error = InvalidPublicKey;
+
+    fn try_from(value: RegistrationProof) -> std::result::Result<Self, Self::Error> {
+        Ok(InternalRegistrationVerification {
+            public_key: key_bytes(&value.public_key)?,
+        })
+    }
+}
+
+/// The Nargo package proving the registration criteria
+pub const REGISTRATION_PACKAGE: &str = "register";
+
 pub enum Command {
     Prove(ProofRequest),
     Verify(Proof),
+    /// With the profile the prover read from near.social
+    ProveRegistration(RegistrationRequest, SocialProfile),
+    VerifyRegistration(RegistrationProof),
+}
+
+impl Command {
+    /// The nargo package in the workspace that backs this command, credit
+    /// applications use the package of the product applied for
+    pub fn package<'a>(&'a self, config: &'a Config) -> Result<&'a str> {
+        let product = match self {
+            Command::Prove(req) => req.product.as_deref(),
+            Command::Verify(proof) => proof.product.as_deref(),
+            Command::ProveRegistration(..) | Command::VerifyRegistration(_) => {
+                return Ok(REGISTRATION_PACKAGE)
+            }
+        };
+        Ok(&config.product(product)?.package)
+    }
+
+    fn is_prove(&self) -> bool {
+        matches!(self, Command::Prove(_) | Command::ProveRegistration(..))
+    }
+
+    /// Stamp a credit application with `today` and the configured registrar
+    /// key, the public inputs the prover rather than the applicant decides. A
+    /// proof is refused unless it was made with both, or yesterday.
+    fn stamped(self, config: &Config, today: i64) -> Result<Self> {
+        let registrar_key = config.registrar.registrar_key()?;
+        match self {
+            Command::Prove(mut req) => {
+                let attested = req
+                    .params
+                    .iter()
+                    .any(|x| matches!(x, Heuristic::AttestedIdentity(_)));
+                eyre::ensure!(
+                    !attested || registrar_key.is_some(),
+                    "No attestation key is configured to check attested identities with"
+                );
+                req.proving_date = Date::from_days(today).as_number();
+                req.registrar_key = registrar_key;
+                Ok(Command::Prove(req))
+            }
+            Command::Verify(proof) => {
+                eyre::ensure!(
+                    proof.heuristics.len() == HEURISTICS,
+                    "The proof has {} heuristic slots rather than {}",
+                    proof.heuristics.len(),
+                    HEURISTICS
+                );
+                config
+                    .product(proof.product.as_deref())?
+                    .check_heuristic_ids(&proof.heuristics)?;
+                eyre::ensure!(
+                    proof.registrar_key == registrar_key,
+                    "The proof wasn't made with this prover's registrar key"
+                );
+                eyre::ensure!(
+                    is_recent(proof.proving_date, today),
+                    "The proof was made on {}, it's stale",
+                    proof.proving_date
+                );
+                Ok(Command::Verify(proof))
+            }
+            command => Ok(command),
+        }
+    }
 }
 
 fn bootstrap_command<'process>(
     command: &Command,
+    package: &str,
     base_command: &'process mut ProcessCommand,
     path: &Path,
 ) -> &'process mut ProcessCommand {
-    let action = match command {
-        Command::Prove(_) => "prove",
-        Command::Verify(_) => "verify",
-    };
-    let file_arg = match command {
-        Command::Prove(_) => "--prover-name",
-        Command::Verify(_) => "--verifier-name",
+    let (action, file_arg) = if command.is_prove() {
+        ("prove", "--prover-name")
+    } else {
+        ("verify", "--verifier-name")
     };
     base_command
         .arg(action)
         .arg("--package")
-        .arg("apply")
+        .arg(package)
         .arg(file_arg)
         .arg(path)
 }
 
-pub fn execute<'a, T: TryFrom<(&'a Config, String, Command), Error = Report>>(
+pub async fn execute<'a, T: TryFrom<(&'a Config, String, Command), Error = Report>>(
     config: &'a Config,
     command: Command,
 ) -> Result<T> {
     // This is to be sure that tempdir doesn't get forcibly dropped
     let temp_dir = tempfile::tempdir()?;
-    execute_inner(config, command, &temp_dir)
+    execute_inner(config, command, &temp_dir).await
 }
 
 /// Execute a call to nargo.
 ///
 /// This process approach is a bit of a hack for the purpose of speed. Ideally
 /// we would just integrate with the barretenberg backend directly.
-pub fn execute_inner<'a, T: TryFrom<(&'a Config, String, Command), Error = Report>>(
+///
+/// nargo runs without blocking the runtime and is killed once it outlives the
+/// request timeout, or when the request is dropped. It runs in a workspace of
+/// its own under `temp_dir`, so concurrent requests never share a proof file.
+/// The result is converted from the hex proof nargo wrote, empty when verifying.
+pub async fn execute_inner<'a, T: TryFrom<(&'a Config, String, Command), Error = Report>>(
     config: &'a Config,
     command: Command,
     temp_dir: &TempDir,
 ) -> Result<T> {
+    let command = command.stamped(config, days_since_epoch())?;
+    let workspace = request_workspace(&config.nargo_workspace_dir, temp_dir)?;
     let path = temp_dir.path().join("Params.toml");
     let mut file = File::create(&path)?;
     log::debug!("Created file: {:?}", path);
 
+    let package = command.package(config)?.to_string();
     let mut process = ProcessCommand::new("nargo");
-    let process = bootstrap_command(&command, &mut process, &path);
+    let process = bootstrap_command(&command, &package, &mut process, &path);
     log::debug!("Built process {:?}", process);
 
     // Check if we need to flush or not
     match &command {
         Command::Prove(req) => {
-            let internal = InternalProofRequest::from(req.clone());
+            config
+                .product(req.product.as_deref())?
+                .check_heuristics(&req.params)?;
+            let internal = InternalProofRequest::try_from(req.clone())?;
             let toml_str = toml::to_string_pretty(&internal)?;
             log::debug!("Proof TOML: {}", toml_str);
             file.write_all(toml_str.as_bytes())?;
         }
         Command::Verify(req) => {
-            let internal = InternalVerificationRequest::from(req.clone());
+            let internal = InternalVerificationRequest::try_from(req.clone())?;
             let toml_str = toml::to_string_pretty(&internal)?;
             log::debug!("Verify TOML: {}", toml_str);
             file.write_all(toml_str.as_bytes())?;
-
-            let mut proof_file = proof_file(config, false)?;
-            proof_file.write_all(&hex::encode(&req.inner).as_bytes())?;
+            std::fs::write(proof_path(&workspace, &package), hex::encode(&req.inner))?;
+        }
+        Command::ProveRegistration(req, social) => {
+            let internal = InternalRegistrationRequest::try_from((req.clone(), social.clone()))?;
+            let toml_str = toml::to_string_pretty(&internal)?;
+            log::debug!("Registration proof TOML: {}", toml_str);
+            file.write_all(toml_str.as_bytes())?;
+        }
+        Command::VerifyRegistration(req) => {
+            let internal = InternalRegistrationVerification::try_from(req.clone())?;
+            let toml_str = toml::to_string_pretty(&internal)?;
+            log::debug!("Registration verify TOML: {}", toml_str);
+            file.write_all(toml_str.as_bytes())?;
+            std::fs::write(proof_path(&workspace, &package), hex::encode(&req.inner))?;
         }
     }
 
+    if let Some(backend) = &config.nargo_backend {
+        process.env("NARGO_BACKEND_PATH", backend);
+    }
     log::debug!("Executing {:?}", process);
-    let result = process
-        .current_dir(&config.nargo_workspace_dir)
-        .spawn()?
-        .wait_with_output()?;
+    let child = process.current_dir(&workspace).kill_on_drop(true).spawn()?;
+    let timeout = config.server.request_timeout();
+    let result = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| eyre::eyre!("nargo was killed after {:?}", timeout))??;
 
     log::debug!("Command result: {:?}", result);
 
     if result.status.success() {
         let stdout = String::from_utf8_lossy(&result.stdout);
         log::info!("Output {}", stdout);
-        (config, stdout.to_string(), command).try_into()
+        let proof_hex = if command.is_prove() {
+            std::fs::read_to_string(proof_path(&workspace, &package))?
+        } else {
+            String::new()
+        };
+        (config, proof_hex, command).try_into()
     } else {
         let stderr = String::from_utf8_lossy(&result.stderr);
         log::error!("{}", stderr);
@@ -195,23 +424,63 @@ impl<'a> TryFrom<(&'a Config, String, Command)> for Proof {
     type Error = Report;
 
     fn try_from(value: (&Config, String, Command)) -> Result<Self> {
-        let (config, _, cmd) = value;
+        let (_, proof_hex, cmd) = value;
+
+        let (public_key, requested_amount, product, nonce, proving_date, registrar_key, heuristics) =
+            match cmd {
+                Command::Prove(r) => (
+                    r.public_key,
+                    r.requested_amount,
+                    r.product,
+                    r.nonce,
+                    r.proving_date,
+                    r.registrar_key,
+                    slots(&r.params).iter().map(|x| x.id).collect(),
+                ),
+                Command::Verify(r) => (
+                    r.public_key,
+                    r.requested_amount,
+                    r.product,
+                    r.nonce,
+                    r.proving_date,
+                    r.registrar_key,
+                    r.heuristics,
+                ),
+                _ => return Err(eyre::eyre!("Not a credit application")),
+            };
+
+        Ok(Proof {
+            requested_amount,
+            public_key,
+            inner: hex::decode(proof_hex)?,
+            account_id: None,
+            circuit_version: None,
+            product,
+            nonce,
+            proving_date,
+            registrar_key,
+            heuristics,
+        })
+    }
+}
 
-        let mut proof_file = proof_file(config, true)?;
+impl<'a> TryFrom<(&'a Config, String, Command)> for RegistrationProof {
+    type Error = Report;
 
-        let mut proof_hex = String::new();
-        proof_file.read_to_string(&mut proof_hex)?;
+    fn try_from(value: (&Config, String, Command)) -> Result<Self> {
+        let (_, proof_hex, cmd) = value;
 
-        let (public_key, requested_amount) = match cmd {
-            Command::Prove(r) => (r.public_key, r.requested_amount),
-            Command::Verify(r) => (r.public_key, r.requested_amount),
+        let (public_key, account_id) = match cmd {
+            Command::ProveRegistration(r, _) => (r.public_key, Some(r.account_id)),
+            Command::VerifyRegistration(r) => (r.public_key, r.account_id),
+            _ => return Err(eyre::eyre!("Not a registration")),
         };
 
-        Ok(Proof {
-            requested_amount,
+        Ok(RegistrationProof {
             public_key,
             inner: hex::decode(proof_hex)?,
-            account_id: None,
+            account_id,
+            circuit_version: None,
         })
     }
 }
@@ -223,24 +492,29 @@ impl<'a> TryFrom<(&'a Config, String, Command)> for VerificationResult {
     }
 }
 
-fn proof_dir(config: &Config) -> PathBuf {
-    config
-        .nargo_workspace_dir
-        .join("proofs")
-        .join("apply.proof")
+/// Where nargo reads and writes the proofs of a workspace
+const PROOFS_DIR: &str = "proofs";
+
+/// A workspace for a single request under `temp_dir`, linking everything in
+/// `workspace` but its proofs
+fn request_workspace(workspace: &Path, temp_dir: &TempDir) -> Result<PathBuf> {
+    let workspace = workspace.canonicalize()?;
+    let dir = temp_dir.path().join("workspace");
+    std::fs::create_dir(&dir)?;
+    for entry in std::fs::read_dir(&workspace)? {
+        let entry = entry?;
+        if entry.file_name() != PROOFS_DIR {
+            std::os::unix::fs::symlink(entry.path(), dir.join(entry.file_name()))?;
+        }
+    }
+    std::fs::create_dir(dir.join(PROOFS_DIR))?;
+    Ok(dir)
 }
 
-fn proof_file(config: &Config, read_only: bool) -> Result<File> {
-    let proof_path = proof_dir(config);
-
-    #[cfg(test)]
-    let proof_path = PathBuf::from("../../proofs/apply.proof");
-    let file = if read_only {
-        File::open(proof_path)
-    } else {
-        File::create(proof_path)
-    }?;
-    Ok(file)
+fn proof_path(workspace: &Path, package: &str) -> PathBuf {
+    workspace
+        .join(PROOFS_DIR)
+        .join(format!("{}.proof", package))
 }
 
 #[cfg(test)]
@@ -253,14 +527,98 @@ mod tests {
         crate::config::Config::from("../../config")
     }
 
+    fn social() -> SocialProfile {
+        SocialProfile {
+            avatar: true,
+            posts: 1,
+            username: true,
+        }
+    }
+
     #[test]
-    fn proof_dir_from_config() {
+    fn test_request_workspace() {
+        let workspace = tempfile::tempdir().unwrap();
+        std::fs::write(workspace.path().join("Nargo.toml"), "[workspace]").unwrap();
+        std::fs::create_dir(workspace.path().join(PROOFS_DIR)).unwrap();
+        std::fs::write(proof_path(workspace.path(), "apply"), "shared").unwrap();
+
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let first = request_workspace(workspace.path(), &first).unwrap();
+        let second = request_workspace(workspace.path(), &second).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(first.join("Nargo.toml")).unwrap(),
+            "[workspace]"
+        );
+        // Each request has its own proofs, none of the workspace's
+        assert!(!proof_path(&first, "apply").exists());
+        std::fs::write(proof_path(&first, "apply"), "first").unwrap();
+        std::fs::write(proof_path(&second, "apply"), "second").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(proof_path(&first, "apply")).unwrap(),
+            "first"
+        );
+        assert_eq!(
+            std::fs::read_to_string(proof_path(workspace.path(), "apply")).unwrap(),
+            "shared"
+        );
+    }
+
+    #[test]
+    fn test_command_package() {
         let config = get_config();
-        let dir = proof_dir(&config);
+        let json = std::fs::read_to_string("fixtures/registration.json").unwrap();
+        let req: RegistrationRequest = serde_json::from_str(&json).unwrap();
+        let cmd = Command::ProveRegistration(req, social());
+        assert_eq!(cmd.package(&config).unwrap(), "register");
+        assert!(cmd.is_prove());
+
+        let json = std::fs::read_to_string("fixtures/simple.json").unwrap();
+        let req: ProofRequest = serde_json::from_str(&json).unwrap();
         assert_eq!(
-            dir,
-            PathBuf::from("/home/common/projects/GhostFi/proofs/apply.proof")
+            Command::Prove(req.clone()).package(&config).unwrap(),
+            "apply"
         );
+
+        let req = ProofRequest {
+            product: Some("mortgage".into()),
+            ..req
+        };
+        assert!(Command::Prove(req).package(&config).is_err());
+    }
+
+    #[test]
+    fn test_convert_registration_to_inner() {
+        let json = std::fs::read_to_string("fixtures/registration.json").unwrap();
+        let req: RegistrationRequest = serde_json::from_str(&json).unwrap();
+        let key = PublicKey::from_str(&req.public_key).unwrap();
+        let inner = InternalRegistrationRequest::try_from((req, social())).unwrap();
+        assert_eq!(inner.public_key.as_slice(), key.key_data());
+        assert!(inner.avatar);
+        assert!(inner.username);
+        assert_eq!(inner.posts, "1");
+
+        let toml = toml::to_string_pretty(&inner).unwrap();
+        let value: toml::Value = toml::from_str(&toml).unwrap();
+        assert_eq!(value["posts"].as_str(), Some("1"));
+        assert_eq!(value["avatar"].as_bool(), Some(true));
+        assert_eq!(value["public_key"].as_array().map(Vec::len), Some(32));
+    }
+
+    #[test]
+    fn test_invalid_public_key() {
+        let json = std::fs::read_to_string("fixtures/registration.json").unwrap();
+        let req: RegistrationRequest = serde_json::from_str(&json).unwrap();
+        let secp = SecretKey::from_seed(near_crypto::KeyType::SECP256K1, "holder").public_key();
+        for public_key in ["not a key".to_string(), secp.to_string()] {
+            let bad = RegistrationRequest {
+                public_key: public_key.clone(),
+                ..req.clone()
+            };
+            let err = InternalRegistrationRequest::try_from((bad, social())).unwrap_err();
+            assert_eq!(err, InvalidPublicKey(public_key));
+        }
+        let report = eyre::Report::from(key_bytes("not a key").unwrap_err());
+        assert!(report.downcast_ref::<InvalidPublicKey>().is_some());
     }
 
     #[test]
@@ -274,6 +632,100 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_identity_not_deserialised() {
+        let json =
+            r#"{"Identity":{"nationality":"UTO","birth_date":19740812,"expiry_date":20120415}}"#;
+        assert!(serde_json::from_str::<Heuristic>(json).is_err());
+        let json = r#"{"Simple":{"balance":25}}"#;
+        assert!(serde_json::from_str::<Heuristic>(json).is_ok());
+    }
+
+    #[test]
+    fn test_attested_identity_params() {
+        let attested = crate::document::AttestedClaims {
+            claims: crate::document::Claims {
+                format: "Td3".to_string(),
+                nationality: "UTO".to_string(),
+                birth_date: 19740812,
+                expiry_date: 20120415,
+            },
+            salt: std::array::from_fn(|i| i as u8),
+            signature: [0xab; 64],
+        };
+        let h = InternalHeuristic::from(Heuristic::AttestedIdentity(attested));
+        assert_eq!(h.id, 4);
+        assert_eq!(h.params[0], 0x55544F.to_string());
+        assert_eq!(h.params[2], "20120415");
+        assert_eq!(h.params[3], "0x000102030405060708090a0b0c0d0e0f");
+        assert_eq!(h.params[4], "0x101112131415161718191a1b1c1d1e1f");
+        assert_eq!(h.params[8], format!("0x{}", "ab".repeat(16)));
+        assert_eq!(h.params[9], "0");
+    }
+
+    #[test]
+    fn test_registrar_fields() {
+        assert_eq!(registrar_fields(None), ["0", "0", "0", "0"]);
+        let key = std::array::from_fn(|i| i as u8);
+        let fields = registrar_fields(Some(key));
+        assert_eq!(fields[0], "0x000102030405060708090a0b0c0d0e0f");
+        assert_eq!(fields[3], "0x303132333435363738393a3b3c3d3e3f");
+    }
+
+    #[test]
+    fn test_is_recent() {
+        // 2023-12-08
+        let today = 19699;
+        assert!(is_recent(20231208, today));
+        assert!(is_recent(20231207, today));
+        assert!(!is_recent(20231206, today));
+        assert!(!is_recent(20231209, today));
+        assert!(!is_recent(0, today));
+    }
+
+    #[test]
+    fn test_stamped() {
+        let config = get_config();
+        let registrar_key = config.registrar.registrar_key().unwrap();
+        let json = std::fs::read_to_string("fixtures/simple.json").unwrap();
+        let req: ProofRequest = serde_json::from_str(&json).unwrap();
+        let req = match Command::Prove(req).stamped(&config, 19699).unwrap() {
+            Command::Prove(req) => req,
+            _ => panic!("Not a credit application"),
+        };
+        assert_eq!(req.proving_date, 20231208);
+        assert_eq!(req.registrar_key, registrar_key);
+
+        let json = std::fs::read_to_string("fixtures/proof.json").unwrap();
+        let proof: Proof = serde_json::from_str(&json).unwrap();
+        let proof = Proof {
+            proving_date: 20231207,
+            registrar_key,
+            heuristics: vec![1, 0, 0, 0],
+            ..proof
+        };
+        let fresh = Command::Verify(proof.clone()).stamped(&config, 19699);
+        assert!(fresh.is_ok());
+        let stale = Command::Verify(proof.clone()).stamped(&config, 19700);
+        assert!(stale.is_err());
+        let other = Proof {
+            registrar_key: Some([1; 64]),
+            ..proof.clone()
+        };
+        assert!(Command::Verify(other).stamped(&config, 19699).is_err());
+        // The default product requires `Simple`, a proof of noops is refused
+        let noops = Proof {
+            heuristics: vec![0; HEURISTICS],
+            ..proof.clone()
+        };
+        assert!(Command::Verify(noops).stamped(&config, 19699).is_err());
+        let unslotted = Proof {
+            heuristics: vec![1],
+            ..proof
+        };
+        assert!(Command::Verify(unslotted).stamped(&config, 19699).is_err());
+    }
+
     #[test]
     fn test_bootstrap_command() {
         let json = std::fs::read_to_string("fixtures/simple.json").unwrap();
@@ -285,25 +737,25 @@ mod tests {
         // );
     }
 
-    #[test]
-    fn test_prove_e2e() {
+    #[tokio::test]
+    async fn test_prove_e2e() {
         let _ = pretty_env_logger::try_init();
         let config = get_config();
         println!("{:?}", config);
         let json = std::fs::read_to_string("fixtures/simple.json").unwrap();
         let proof: ProofRequest = serde_json::from_str(&json).unwrap();
-        let proof: Proof = execute(&config, Command::Prove(proof)).unwrap();
+        let proof: Proof = execute(&config, Command::Prove(proof)).await.unwrap();
         println!("{:?}", serde_json::to_string_pretty(&proof).unwrap());
     }
 
-    #[test]
-    fn test_verify_e2e() {
+    #[tokio::test]
+    async fn test_verify_e2e() {
         let _ = pretty_env_logger::try_init();
         let config = get_config();
         println!("{:?}", config);
         let json = std::fs::read_to_string("fixtures/proof.json").unwrap();
         let proof: Proof = serde_json::from_str(&json).unwrap();
-        let proven: VerificationResult = execute(&config, Command::Verify(proof)).unwrap();
+        let proven: VerificationResult = execute(&config, Command::Verify(proof)).await.unwrap();
         println!("Verified: {:?}", proven.0);
     }
 
@@ -311,14 +763,17 @@ mod tests {
     fn test_convert_to_inner() {
         let json = std::fs::read_to_string("fixtures/simple.json").unwrap();
         let proof: ProofRequest = serde_json::from_str(&json).unwrap();
-        let inner = InternalProofRequest::from(proof);
+        let inner = InternalProofRequest::try_from(proof).unwrap();
         let toml = toml::to_string_pretty(&inner).unwrap();
         println!("{}", toml);
         let toml = std::fs::read_to_string("../../circuits/apply/Prover.toml").unwrap();
         let deser_inner = toml::from_str::<InternalProofRequest>(&toml).unwrap();
         assert_eq!(inner.public_key, deser_inner.public_key);
         assert_eq!(inner.requested_amount, deser_inner.requested_amount);
+        assert_eq!(inner.nonce, deser_inner.nonce);
         assert_eq!(inner.params[0], deser_inner.params[0]);
+        assert_eq!(inner.heuristics, [1, 0, 0, 0]);
+        assert_eq!(deser_inner.heuristics, [1, 2, 0, 0]);
     }
 
     #[test]
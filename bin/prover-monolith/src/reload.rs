@@ -0,0 +1,128 @@
+//! Hot reloading, the config files and compiled circuits are watched and a
+//! reload is swapped in atomically so a new circuit release doesn't need a
+//! restart. The listen address and TLS settings are only read at startup.
+use crate::{circuit::Circuits, config::Config, contract, Result};
+use arc_swap::ArcSwap;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::Arc;
+
+/// The config files `Config::new` reads
+const CONFIG_FILES: [&str; 2] = ["config.toml", "local.toml"];
+
+/// Everything a request is served with, replaced as a whole on reload
+pub struct Active {
+    pub config: Config,
+    pub client: contract::Client,
+    pub circuits: Circuits,
+}
+
+pub type Shared = Arc<ArcSwap<Active>>;
+
+impl Active {
+    pub fn new(config: Config) -> Result<Self> {
+        Ok(Self {
+            circuits: Circuits::load(&config.nargo_workspace_dir, &config.packages())?,
+            client: contract::Client::try_new(&config)?,
+            config,
+        })
+    }
+
+    /// Replace the config, the signer is kept while its account and secret are
+    /// unchanged so a keystore is only decrypted once
+    fn reload(&self, config: Config) -> Result<Self> {
+        let (current, next) = (self.config.profile(), config.profile());
+        let client =
+            if current.signer_account == next.signer_account && current.secret == next.secret {
+                contract::Client::with_signer(&config, self.client.signer().clone())
+            } else {
+                contract::Client::try_new(&config)?
+            };
+        Ok(Self {
+            circuits: Circuits::load(&config.nargo_workspace_dir, &config.packages())?,
+            client,
+            config,
+        })
+    }
+}
+
+/// Watch the config files and circuit artifacts, a failed reload keeps the
+/// current config. The watcher stops when dropped.
+pub fn watch(shared: Shared, network: Option<String>) -> Result<RecommendedWatcher> {
+    let artifacts = shared.load().config.nargo_workspace_dir.join("target");
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let current = shared.load_full();
+        let event = match event {
+            Ok(event) if is_reload(&event, &current.config.packages()) => event,
+            Ok(_) => return,
+            Err(e) => {
+                log::error!("Watch error: {}", e);
+                return;
+            }
+        };
+        log::debug!("Reloading on {:?}", event);
+
+        let active = Config::new(network.as_deref())
+            .map_err(eyre::Report::from)
+            .and_then(|config| current.reload(config));
+        match active {
+            Ok(active) => {
+                log::info!("Reloaded, circuits {:?}", active.circuits);
+                shared.store(Arc::new(active));
+            }
+            Err(e) => log::error!("Reload failed, keeping the current config: {:?}", e),
+        }
+    })?;
+
+    watcher.watch(Path::new("."), RecursiveMode::NonRecursive)?;
+    std::fs::create_dir_all(&artifacts)?;
+    watcher.watch(&artifacts, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// Changes to a config file or the artifact of one of `packages`
+fn is_reload(event: &Event, packages: &[&str]) -> bool {
+    if matches!(event.kind, EventKind::Access(_)) {
+        return false;
+    }
+    event.paths.iter().any(|path| {
+        let Some(name) = path.file_name().and_then(|x| x.to_str()) else {
+            return false;
+        };
+        let in_target = path
+            .parent()
+            .and_then(Path::file_name)
+            .is_some_and(|x| x == "target");
+        let artifact = in_target
+            && packages
+                .iter()
+                .any(|package| name == format!("{}.json", package));
+        CONFIG_FILES.contains(&name) || artifact
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, ModifyKind};
+
+    #[test]
+    fn test_is_reload() {
+        let modify =
+            |path: &str| Event::new(EventKind::Modify(ModifyKind::Any)).add_path(path.into());
+        let packages = ["apply", "register"];
+        assert!(is_reload(&modify("./config.toml"), &packages));
+        assert!(is_reload(&modify("/ghostfi/target/apply.json"), &packages));
+        assert!(!is_reload(&modify("./Cargo.toml"), &packages));
+        // Only the artifacts of the configured packages
+        assert!(!is_reload(
+            &modify("/ghostfi/target/biguint.json"),
+            &packages
+        ));
+        assert!(!is_reload(&modify("/ghostfi/apply.json"), &packages));
+        assert!(!is_reload(&modify("./package.json"), &packages));
+
+        let access = Event::new(EventKind::Access(AccessKind::Any)).add_path("config.toml".into());
+        assert!(!is_reload(&access, &packages));
+    }
+}
@@ -0,0 +1,186 @@
+//! Where the prover's signing key comes from, so it doesn't have to sit in
+//! plaintext in `config.toml`.
+use eyre::{eyre, Result, WrapErr};
+use near_crypto::SecretKey;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The passphrase variable used when a keystore doesn't name one
+pub const DEFAULT_PASSPHRASE_ENV: &str = "GHOSTFI_KEYSTORE_PASSPHRASE";
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum SecretSource {
+    /// The key itself, only suitable for local development
+    Inline(SecretKey),
+    /// A NEAR CLI credentials file, e.g. `~/.near-credentials/testnet/gfi.testnet.json`
+    CredentialsFile { credentials_file: PathBuf },
+    /// An environment variable holding the key
+    Env { env: String },
+    /// An encrypted keystore, the passphrase is read from `passphrase_env`
+    Keystore {
+        keystore: PathBuf,
+        passphrase_env: Option<String>,
+    },
+}
+
+impl std::fmt::Debug for SecretSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretSource::Inline(_) => write!(f, "Inline(<redacted>)"),
+            SecretSource::CredentialsFile { credentials_file } => f
+                .debug_struct("CredentialsFile")
+                .field("credentials_file", credentials_file)
+                .finish(),
+            SecretSource::Env { env } => f.debug_struct("Env").field("env", env).finish(),
+            SecretSource::Keystore {
+                keystore,
+                passphrase_env,
+            } => f
+                .debug_struct("Keystore")
+                .field("keystore", keystore)
+                .field("passphrase_env", passphrase_env)
+                .finish(),
+        }
+    }
+}
+
+/// The parts of a NEAR CLI credentials file we need, nearcore's key files name it `secret_key`
+#[derive(Deserialize)]
+struct Credentials {
+    #[serde(alias = "secret_key")]
+    private_key: SecretKey,
+}
+
+impl SecretSource {
+    pub fn load(&self) -> Result<SecretKey> {
+        match self {
+            SecretSource::Inline(key) => Ok(key.clone()),
+            SecretSource::CredentialsFile { credentials_file } => {
+                let path = expand_home(credentials_file);
+                let json = std::fs::read_to_string(&path)
+                    .wrap_err_with(|| format!("Failed to read credentials {:?}", path))?;
+                let credentials: Credentials = serde_json::from_str(&json)?;
+                Ok(credentials.private_key)
+            }
+            SecretSource::Env { env } => {
+                let key = std::env::var(env).wrap_err_with(|| format!("{} is not set", env))?;
+                Ok(key.trim().parse()?)
+            }
+            SecretSource::Keystore {
+                keystore,
+                passphrase_env,
+            } => {
+                let passphrase = passphrase(passphrase_env.as_deref())?;
+                let key = eth_keystore::decrypt_key(expand_home(keystore), passphrase)
+                    .map_err(|e| eyre!("Failed to decrypt keystore {:?}: {}", keystore, e))?;
+                Ok(String::from_utf8(key)?.parse()?)
+            }
+        }
+    }
+}
+
+/// Encrypt `key` into a keystore in `dir` named `name`, returns the keystore path
+pub fn encrypt(
+    key: &SecretKey,
+    dir: &Path,
+    name: &str,
+    passphrase_env: Option<&str>,
+) -> Result<PathBuf> {
+    use rand_chacha::rand_core::SeedableRng;
+
+    let passphrase = passphrase(passphrase_env)?;
+    let mut rng = rand_chacha::ChaCha20Rng::from_entropy();
+    eth_keystore::encrypt_key(dir, &mut rng, key.to_string(), passphrase, Some(name))
+        .map_err(|e| eyre!("Failed to encrypt keystore: {}", e))?;
+    Ok(dir.join(name))
+}
+
+fn passphrase(passphrase_env: Option<&str>) -> Result<String> {
+    let env = passphrase_env.unwrap_or(DEFAULT_PASSPHRASE_ENV);
+    std::env::var(env).wrap_err_with(|| format!("{} is not set", env))
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_crypto::KeyType;
+
+    fn key() -> SecretKey {
+        SecretKey::from_seed(KeyType::ED25519, "test")
+    }
+
+    #[test]
+    fn test_redacted() {
+        let inline = SecretSource::Inline(key());
+        let debug = format!("{:?}", inline);
+        assert_eq!(debug, "Inline(<redacted>)");
+        assert!(!debug.contains(&key().to_string()));
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let source: SecretSource = serde_json::from_value(serde_json::json!(key())).unwrap();
+        assert!(matches!(source, SecretSource::Inline(_)));
+
+        let source: SecretSource = toml::from_str(r#"env = "GHOSTFI_SECRET_KEY""#).unwrap();
+        assert!(matches!(source, SecretSource::Env { .. }));
+
+        let source: SecretSource = toml::from_str(r#"keystore = "key.json""#).unwrap();
+        assert!(matches!(
+            source,
+            SecretSource::Keystore {
+                passphrase_env: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_credentials_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gfi.testnet.json");
+        let json = serde_json::json!({
+            "account_id": "gfi.testnet",
+            "public_key": key().public_key(),
+            "private_key": key(),
+        });
+        std::fs::write(&path, json.to_string()).unwrap();
+
+        let source = SecretSource::CredentialsFile {
+            credentials_file: path,
+        };
+        assert_eq!(source.load().unwrap(), key());
+    }
+
+    #[test]
+    fn test_env_and_keystore() {
+        std::env::set_var("GHOSTFI_TEST_SECRET_KEY", key().to_string());
+        let source = SecretSource::Env {
+            env: "GHOSTFI_TEST_SECRET_KEY".into(),
+        };
+        assert_eq!(source.load().unwrap(), key());
+
+        let dir = tempfile::tempdir().unwrap();
+        std::env::set_var("GHOSTFI_TEST_PASSPHRASE", "hunter2");
+        let path = encrypt(
+            &key(),
+            dir.path(),
+            "key.json",
+            Some("GHOSTFI_TEST_PASSPHRASE"),
+        )
+        .unwrap();
+        let source = SecretSource::Keystore {
+            keystore: path,
+            passphrase_env: Some("GHOSTFI_TEST_PASSPHRASE".into()),
+        };
+        assert_eq!(source.load().unwrap(), key());
+    }
+}
@@ -0,0 +1,122 @@
+//! The near.social activity behind the registration criteria, the prover reads
+//! it from the social contract itself so applicants can't report their own.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The near.social activity required to register with the lender
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SocialProfile {
+    pub avatar: bool,
+    pub posts: u64,
+    pub username: bool,
+}
+
+impl SocialProfile {
+    /// The keys of `account` to read with the social contract's `get`
+    pub fn keys(account: &str) -> Vec<String> {
+        vec![
+            format!("{}/profile/**", account),
+            format!("{}/post/main", account),
+        ]
+    }
+
+    /// From what `get` answered for [`SocialProfile::keys`]. The social contract
+    /// only keeps an account's latest post, so `posts` is at most one.
+    pub fn from_social_data(account: &str, data: &Value) -> Self {
+        let data = &data[account];
+        let profile = &data["profile"];
+        let avatar = match &profile["image"] {
+            Value::Object(image) => image.values().any(is_set),
+            _ => false,
+        };
+        Self {
+            avatar,
+            posts: is_set(&data["post"]["main"]) as u64,
+            username: is_set(&profile["name"]),
+        }
+    }
+}
+
+impl SocialProfile {
+    /// The criteria of the `register` circuit, checked again on the profile
+    /// when a proof is submitted since the proof only shows they held for
+    /// whatever profile the prover was given
+    pub fn meets_criteria(&self) -> bool {
+        self.avatar && self.posts > 0 && self.username
+    }
+}
+
+fn is_set(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::String(x) => !x.trim().is_empty(),
+        Value::Object(x) => !x.is_empty(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_from_social_data() {
+        let data = json!({
+            "alice.testnet": {
+                "profile": {
+                    "name": "Alice",
+                    "image": { "ipfs_cid": "bafkreiavatar" }
+                },
+                "post": { "main": "{\"type\":\"md\",\"text\":\"gm\"}" }
+            }
+        });
+        assert_eq!(
+            SocialProfile::from_social_data("alice.testnet", &data),
+            SocialProfile {
+                avatar: true,
+                posts: 1,
+                username: true
+            }
+        );
+
+        let data = json!({ "bob.testnet": { "profile": { "name": " ", "image": {} } } });
+        assert_eq!(
+            SocialProfile::from_social_data("bob.testnet", &data),
+            SocialProfile {
+                avatar: false,
+                posts: 0,
+                username: false
+            }
+        );
+        // Nothing is answered for accounts that never used near.social
+        assert_eq!(
+            SocialProfile::from_social_data("carol.testnet", &json!({})),
+            SocialProfile {
+                avatar: false,
+                posts: 0,
+                username: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_meets_criteria() {
+        let profile = SocialProfile {
+            avatar: true,
+            posts: 1,
+            username: true,
+        };
+        assert!(profile.meets_criteria());
+        let no_posts = SocialProfile {
+            posts: 0,
+            ..profile.clone()
+        };
+        assert!(!no_posts.meets_criteria());
+        let no_avatar = SocialProfile {
+            avatar: false,
+            ..profile
+        };
+        assert!(!no_avatar.meets_criteria());
+    }
+}
@@ -0,0 +1,375 @@
+//! M-of-N prover attestations, a loan is only released once `threshold` distinct
+//! provers have attested to the same proof so one compromised key can't drain
+//! the lender.
+use crate::views::page;
+use crate::{Contract, ContractExt, Loan, LoanError};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::{env, log, near_bindgen, require, AccountId, CryptoHash};
+
+/// How long a pending attestation waits for the rest of the provers, in
+/// nanoseconds, before it is dropped
+pub const ATTESTATION_TTL: u64 = 86_400 * 1_000_000_000;
+
+/// Attestations collected so far for a single proof, user and loan
+#[derive(BorshDeserialize, BorshSerialize, Debug, PartialEq, Eq)]
+pub struct Attestation {
+    pub proof_hash: CryptoHash,
+    pub user: AccountId,
+    pub loan: Loan,
+    pub provers: Vec<AccountId>,
+    /// The block timestamp of the first attestation
+    pub started: u64,
+}
+
+impl Attestation {
+    /// Pending attestations are keyed by everything attested to, so a prover
+    /// attesting to another user or loan for a proof can't block the rest
+    fn key(proof_hash: &CryptoHash, user: &AccountId, loan: &Loan) -> CryptoHash {
+        let bytes = [
+            proof_hash.to_vec(),
+            user.try_to_vec().unwrap(),
+            loan.try_to_vec().unwrap(),
+        ]
+        .concat();
+        env::sha256_array(&bytes)
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        now.saturating_sub(self.started) >= ATTESTATION_TTL
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Replace the prover set, pending attestations are dropped since they may
+    /// have come from provers that are no longer trusted.
+    #[private]
+    pub fn set_provers(&mut self, provers: Vec<AccountId>, threshold: u64) {
+        require!(
+            threshold > 0 && threshold as usize <= provers.len(),
+            "Threshold must be between 1 and the number of provers"
+        );
+        self.provers.clear();
+        for prover in provers {
+            self.provers.insert(&prover);
+        }
+        self.threshold = threshold;
+        self.attestations.clear();
+    }
+
+    pub fn get_provers(&self) -> (Vec<AccountId>, u64) {
+        (self.provers.to_vec(), self.threshold)
+    }
+
+    /// Drop the expired pending attestations in a page of them, and those whose
+    /// proof released a loan with other provers. Anyone may call this, returns
+    /// how many were dropped.
+    pub fn prune_attestations(&mut self, from_index: Option<U64>, limit: Option<U64>) -> U64 {
+        let (from_index, limit) = page(from_index, limit);
+        let now = env::block_timestamp();
+        let stale: Vec<CryptoHash> = self
+            .attestations
+            .iter()
+            .skip(from_index)
+            .take(limit)
+            .filter(|(_, x)| x.is_expired(now) || self.released.contains(&x.proof_hash))
+            .map(|(key, _)| key)
+            .collect();
+        for key in &stale {
+            self.attestations.remove(key);
+        }
+        U64(stale.len() as u64)
+    }
+}
+
+impl Contract {
+    pub(crate) fn is_prover(&self, account: &AccountId) -> bool {
+        self.provers.contains(account)
+    }
+
+    /// Record `prover`'s attestation that `proof_hash` proves `loan` to `user`.
+    /// Returns true once the threshold is met and the loan is released.
+    pub(crate) fn attest(
+        &mut self,
+        prover: AccountId,
+        proof_hash: CryptoHash,
+        user: AccountId,
+        loan: Loan,
+    ) -> Result<bool, LoanError> {
+        if self.released.contains(&proof_hash) {
+            return Err(LoanError::ProofAlreadyUsed);
+        }
+
+        let key = Attestation::key(&proof_hash, &user, &loan);
+        let now = env::block_timestamp();
+        let mut attestation = match self.attestations.get(&key) {
+            Some(attestation) if !attestation.is_expired(now) => attestation,
+            _ => Attestation {
+                proof_hash,
+                user: user.clone(),
+                loan: loan.clone(),
+                provers: Vec::new(),
+                started: now,
+            },
+        };
+        if attestation.provers.contains(&prover) {
+            return Err(LoanError::AlreadyAttested);
+        }
+        attestation.provers.push(prover);
+
+        if (attestation.provers.len() as u64) < self.threshold {
+            log!(format!(
+                "{} of {} attestations for loan to {}",
+                attestation.provers.len(),
+                self.threshold,
+                user
+            ));
+            self.attestations.insert(&key, &attestation);
+            return Ok(false);
+        }
+
+        self.attestations.remove(&key);
+        self.released.insert(&proof_hash);
+        self.internal_loan(user, loan)?;
+        Ok(true)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::json_types::U128;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    /// A 2-of-3 lender with a verified user
+    fn setup(user: &AccountId) -> Contract {
+        let mut contract = Contract::initialize(accounts(1), None);
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_provers(vec![accounts(1), accounts(2), accounts(3)], 2);
+
+        let deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(get_context(user.clone()).attached_deposit(deposit).build());
+        assert!(contract.register(user.clone()));
+        testing_env!(get_context(accounts(1)).build());
+        assert!(contract.register_verified(user.clone()));
+        contract
+    }
+
+    #[test]
+    fn test_threshold() {
+        let user = accounts(4);
+        let mut contract = setup(&user);
+
+        testing_env!(get_context(accounts(1)).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, None),
+            Ok(false)
+        );
+        assert_eq!(contract.loan_amounts(&user), Some(vec![]));
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, None),
+            Err(LoanError::AlreadyAttested)
+        );
+
+        testing_env!(get_context(accounts(2)).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, None),
+            Ok(true)
+        );
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(10)]));
+
+        testing_env!(get_context(accounts(3)).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, None),
+            Err(LoanError::ProofAlreadyUsed)
+        );
+    }
+
+    #[test]
+    fn test_front_run() {
+        let user = accounts(4);
+        let mut contract = setup(&user);
+
+        // A compromised prover attests first, to another user and amount
+        testing_env!(get_context(accounts(1)).build());
+        assert_eq!(
+            contract.verified_loan(accounts(5), U128(100), [1; 32].into(), None, None),
+            Ok(false)
+        );
+
+        testing_env!(get_context(accounts(2)).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, None),
+            Ok(false)
+        );
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(100), [1; 32].into(), None, None),
+            Ok(false)
+        );
+        testing_env!(get_context(accounts(3)).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, None),
+            Ok(true)
+        );
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(10)]));
+
+        // The other pending attestations are dead now the proof is spent
+        assert_eq!(contract.prune_attestations(None, None), U64(2));
+        assert!(contract.attestations.is_empty());
+    }
+
+    #[test]
+    fn test_expired() {
+        let user = accounts(4);
+        let mut contract = setup(&user);
+
+        testing_env!(get_context(accounts(1)).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, None),
+            Ok(false)
+        );
+        assert_eq!(contract.prune_attestations(None, None), U64(0));
+
+        // A day later the first attestation no longer counts
+        let at = |prover, days| {
+            get_context(accounts(prover))
+                .block_timestamp(days * ATTESTATION_TTL)
+                .build()
+        };
+        testing_env!(at(2, 1));
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, None),
+            Ok(false)
+        );
+        assert_eq!(contract.loan_amounts(&user), Some(vec![]));
+        testing_env!(at(1, 1));
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, None),
+            Ok(true)
+        );
+
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(20), [2; 32].into(), None, None),
+            Ok(false)
+        );
+        testing_env!(at(3, 2));
+        assert_eq!(contract.prune_attestations(None, None), U64(1));
+        assert!(contract.attestations.is_empty());
+    }
+
+    #[test]
+    fn test_circuit_version_mismatch() {
+        let user = accounts(4);
+        let mut contract = setup(&user);
+
+        testing_env!(get_context(accounts(1)).build());
+        assert_eq!(
+            contract.verified_loan(
+                user.clone(),
+                U128(10),
+                [1; 32].into(),
+                Some("v1".into()),
+                None
+            ),
+            Ok(false)
+        );
+
+        testing_env!(get_context(accounts(2)).build());
+        assert_eq!(
+            contract.verified_loan(
+                user.clone(),
+                U128(10),
+                [1; 32].into(),
+                Some("v2".into()),
+                None
+            ),
+            Ok(false)
+        );
+        assert_eq!(
+            contract.verified_loan(
+                user.clone(),
+                U128(10),
+                [1; 32].into(),
+                Some("v1".into()),
+                None
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            contract.registered_loans.get(&user).unwrap()[0].circuit_version,
+            Some("v1".into())
+        );
+    }
+
+    #[test]
+    fn test_circuit_version_too_long() {
+        let user = accounts(4);
+        let mut contract = setup(&user);
+
+        testing_env!(get_context(accounts(1)).build());
+        let version = "a".repeat(crate::MAX_CIRCUIT_VERSION_LEN + 1);
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), Some(version), None),
+            Err(LoanError::CircuitVersionTooLong { max: 64 })
+        );
+        let version = "a".repeat(crate::MAX_CIRCUIT_VERSION_LEN);
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), Some(version), None),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn test_product() {
+        let user = accounts(4);
+        let mut contract = setup(&user);
+        let product = || Some("small".to_string());
+
+        testing_env!(get_context(accounts(1)).build());
+        let long = Some("a".repeat(crate::MAX_PRODUCT_LEN + 1));
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, long),
+            Err(LoanError::ProductTooLong { max: 64 })
+        );
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, product()),
+            Ok(false)
+        );
+
+        testing_env!(get_context(accounts(2)).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, None),
+            Ok(false)
+        );
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(10), [1; 32].into(), None, product()),
+            Ok(true)
+        );
+        assert_eq!(
+            contract.registered_loans.get(&user).unwrap()[0].product,
+            product()
+        );
+        assert_eq!(contract.product_loaned.get(&"small".into()), Some(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "Threshold must be between")]
+    fn test_set_provers_invalid_threshold() {
+        let mut contract = Contract::initialize(accounts(1), None);
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_provers(vec![accounts(1)], 2);
+    }
+}
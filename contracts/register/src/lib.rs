@@ -1,37 +1,199 @@
+use near_contract_standards::storage_management::StorageManagement;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::UnorderedMap;
-use near_sdk::json_types::U128;
-use near_sdk::{env, log, near_bindgen, AccountId, Balance, PanicOnDefault, Promise};
+use near_sdk::collections::{LazyOption, LookupMap, LookupSet, UnorderedMap, UnorderedSet};
+use near_sdk::json_types::{Base58CryptoHash, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    assert_one_yocto, env, log, near_bindgen, AccountId, Balance, CryptoHash, PanicOnDefault,
+    Promise, StorageUsage,
+};
+use std::fmt;
+
+mod attestation;
+mod limits;
+mod storage;
+mod token;
+mod verifier;
+mod views;
+
+pub use attestation::Attestation;
+pub use limits::{LoanError, LoanLimits};
+pub use token::LoanAsset;
+pub use verifier::{Groth16Proof, VerificationKey};
+pub use views::Totals;
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
-    pub registered_loans: UnorderedMap<AccountId, Vec<U128>>, // public_key -> loan amounts
-    pub prover: AccountId,
+    pub registered_loans: UnorderedMap<AccountId, Vec<Loan>>, // public_key -> loans
+    /// Accounts trusted to verify proofs offchain
+    pub provers: UnorderedSet<AccountId>,
+    /// The number of distinct provers that must attest to a proof to release a loan
+    pub threshold: u64,
+    /// Pending attestations by the hash of their proof hash, user and loan
+    pub attestations: UnorderedMap<CryptoHash, Attestation>,
+    /// Proof hashes that have already released a loan
+    pub released: LookupSet<CryptoHash>,
+    /// Accounts that may register users other than themselves
+    pub registrars: UnorderedSet<AccountId>,
+    /// Registered users that the prover has verified as meeting the registration criteria
+    pub verified_users: UnorderedSet<AccountId>,
+    /// The storage a single registration occupies, paid for by the registering account
+    pub account_storage_usage: StorageUsage,
+    /// The sum of every outstanding loan, in the same units as the loan amounts
+    pub total_loaned: Balance,
+    pub limits: LoanLimits,
+    pub asset: LoanAsset,
+    /// Set to allow loans against an onchain verified proof, without the prover
+    pub verification_key: LazyOption<VerificationKey>,
+    /// The id the next released loan is given
+    pub next_loan_id: u64,
+    /// The nonce each user's next onchain verified proof is made with, kept
+    /// after unregistering so spent proofs stay spent
+    pub nonces: LookupMap<AccountId, u64>,
+    /// Caps on each loan product on top of `limits`, see `set_product_limits`
+    pub product_limits: UnorderedMap<String, LoanLimits>,
+    /// The sum of the outstanding loans of each product
+    pub product_loaned: LookupMap<String, Balance>,
+    /// The `x || y` secp256k1 key of the registrar attesting identities, a
+    /// public input of onchain verified proofs
+    pub registrar_key: LazyOption<Vec<u8>>,
+    /// The heuristic ids every onchain verified proof must be public over
+    pub required_heuristics: Vec<u8>,
+    /// The registrar that paid each registration it made for another account,
+    /// the storage deposit is refunded to it on unregistering
+    pub depositors: LookupMap<AccountId, AccountId>,
+    /// Token loans whose transfer hasn't resolved yet, they can't be repaid
+    /// until it has since a failed transfer reverts the loan
+    pub pending_disbursements: LookupSet<u64>,
+}
+
+/// The longest circuit version recorded with a loan, a hex blake2 hash
+pub const MAX_CIRCUIT_VERSION_LEN: usize = 64;
+/// The longest loan product name
+pub const MAX_PRODUCT_LEN: usize = 64;
+
+/// An outstanding loan
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Loan {
+    /// Unique across users, assigned when the loan is released
+    pub id: U64,
+    pub amount: U128,
+    /// The version of the circuit whose proof released the loan, if known
+    pub circuit_version: Option<String>,
+    /// The loan product the proof was for, `None` is the default product
+    pub product: Option<String>,
+}
+
+impl Loan {
+    pub fn new(amount: U128, circuit_version: Option<String>) -> Self {
+        Self {
+            id: U64(0),
+            amount,
+            circuit_version,
+            product: None,
+        }
+    }
+
+    pub fn with_product(mut self, product: Option<String>) -> Self {
+        self.product = product;
+        self
+    }
+}
+
+/// Reasons a registration can be refused
+#[derive(Debug, PartialEq, Eq)]
+pub enum RegistrationError {
+    Forbidden,
+    AlreadyRegistered,
+    InsufficientDeposit { required: Balance },
+    NotRegistered,
+    OutstandingLoans,
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistrationError::Forbidden => write!(f, "Forbidden"),
+            RegistrationError::AlreadyRegistered => write!(f, "User is already registered"),
+            RegistrationError::InsufficientDeposit { required } => {
+                write!(
+                    f,
+                    "Attached deposit is less than the {} required for storage",
+                    required
+                )
+            }
+            RegistrationError::NotRegistered => write!(f, "User is not registered"),
+            RegistrationError::OutstandingLoans => write!(f, "User has outstanding loans"),
+        }
+    }
 }
 
 #[near_bindgen]
 impl Contract {
+    /// Starts with `prover` as the only prover, see `set_provers` for M-of-N
     #[init]
-    pub fn initialize(prover: AccountId) -> Self {
-        return Self {
+    pub fn initialize(prover: AccountId, limits: Option<LoanLimits>) -> Self {
+        let mut this = Self {
             registered_loans: UnorderedMap::new(b"s".to_vec()),
-            prover,
+            provers: UnorderedSet::new(b"p".to_vec()),
+            threshold: 1,
+            attestations: UnorderedMap::new(b"a".to_vec()),
+            released: LookupSet::new(b"u".to_vec()),
+            registrars: UnorderedSet::new(b"r".to_vec()),
+            verified_users: UnorderedSet::new(b"v".to_vec()),
+            account_storage_usage: 0,
+            total_loaned: 0,
+            limits: limits.unwrap_or_default(),
+            asset: LoanAsset::default(),
+            verification_key: LazyOption::new(b"k".to_vec(), None),
+            next_loan_id: 0,
+            nonces: LookupMap::new(b"n".to_vec()),
+            product_limits: UnorderedMap::new(b"l".to_vec()),
+            product_loaned: LookupMap::new(b"o".to_vec()),
+            registrar_key: LazyOption::new(b"t".to_vec(), None),
+            required_heuristics: Vec::new(),
+            depositors: LookupMap::new(b"e".to_vec()),
+            pending_disbursements: LookupSet::new(b"d".to_vec()),
         };
+        this.provers.insert(&prover);
+        this.measure_account_storage_usage();
+        this
     }
 
+    /// Register `user`, the caller must be `user` or an authorized registrar and
+    /// attach at least `storage_balance_bounds().min`. Any excess is refunded.
+    #[payable]
     pub fn register(&mut self, user: AccountId) -> bool {
-        if self.is_user_registered(&user) {
-            log!(format!("User is already registered"));
-            return false;
+        match self.internal_register(&user) {
+            Ok(()) => true,
+            Err(e) => {
+                log!(format!("{}", e));
+                refund(env::predecessor_account_id(), env::attached_deposit());
+                false
+            }
         }
-        self.registered_loans.insert(&user, &Vec::new());
+    }
 
-        true
+    /// Remove the caller's registration and refund the storage deposit to
+    /// whoever paid it, only possible once the caller has no outstanding loans.
+    #[payable]
+    pub fn unregister(&mut self) -> bool {
+        assert_one_yocto();
+        match self.internal_unregister(&env::predecessor_account_id()) {
+            Ok(()) => true,
+            Err(e) => {
+                log!(format!("{}", e));
+                false
+            }
+        }
     }
 
-    pub fn verified_loan(&mut self, user: AccountId, amount: U128) -> bool {
-        if env::predecessor_account_id() != self.prover {
+    /// Called by any prover once `user` has proven the registration criteria,
+    /// only verified users can take loans.
+    pub fn register_verified(&mut self, user: AccountId) -> bool {
+        if !self.is_prover(&env::predecessor_account_id()) {
             log!(format!("Forbidden"));
             return false;
         }
@@ -40,14 +202,52 @@ impl Contract {
             return false;
         }
 
-        let mut loans = self.registered_loans.get(&user).unwrap();
-        loans.push(amount);
-        self.registered_loans.insert(&user, &loans);
+        self.verified_users.insert(&user)
+    }
+
+    #[private]
+    pub fn add_registrar(&mut self, registrar: AccountId) -> bool {
+        self.registrars.insert(&registrar)
+    }
 
-        let token_amt = near_sdk::ONE_NEAR * amount.0;
-        Promise::new(user).transfer(token_amt);
+    #[private]
+    pub fn remove_registrar(&mut self, registrar: AccountId) -> bool {
+        self.registrars.remove(&registrar)
+    }
 
-        true
+    /// Attest that `proof_hash`, produced by `circuit_version` of the circuit of
+    /// `product`, proves a loan of `amount` to `user`. The loan is released and
+    /// `true` returned once `threshold` distinct provers have attested. Refused
+    /// with a `LoanError` if the caller isn't a prover, the user isn't verified
+    /// or a limit is exceeded.
+    #[handle_result]
+    pub fn verified_loan(
+        &mut self,
+        user: AccountId,
+        amount: U128,
+        proof_hash: Base58CryptoHash,
+        circuit_version: Option<String>,
+        product: Option<String>,
+    ) -> Result<bool, LoanError> {
+        let prover = env::predecessor_account_id();
+        if !self.is_prover(&prover) {
+            return Err(LoanError::Forbidden);
+        }
+        if circuit_version
+            .as_ref()
+            .is_some_and(|x| x.len() > MAX_CIRCUIT_VERSION_LEN)
+        {
+            return Err(LoanError::CircuitVersionTooLong {
+                max: MAX_CIRCUIT_VERSION_LEN as u32,
+            });
+        }
+        if product.as_ref().is_some_and(|x| x.len() > MAX_PRODUCT_LEN) {
+            return Err(LoanError::ProductTooLong {
+                max: MAX_PRODUCT_LEN as u32,
+            });
+        }
+        let loan = Loan::new(amount, circuit_version).with_product(product);
+        self.attest(prover, proof_hash.into(), user, loan)
     }
 
     fn is_user_registered(&self, user: &AccountId) -> bool {
@@ -58,11 +258,122 @@ impl Contract {
     }
 }
 
+impl Contract {
+    /// Record and disburse a loan once the caller has been authorised
+    pub(crate) fn internal_loan(
+        &mut self,
+        user: AccountId,
+        mut loan: Loan,
+    ) -> Result<(), LoanError> {
+        let mut loans = self
+            .registered_loans
+            .get(&user)
+            .ok_or(LoanError::NotRegistered)?;
+        if !self.verified_users.contains(&user) {
+            return Err(LoanError::NotVerified);
+        }
+        self.check_limits(&loans, loan.amount)?;
+        if let Some(product) = &loan.product {
+            self.check_product_limits(&loans, product, loan.amount)?;
+            let loaned = self.product_loaned.get(product).unwrap_or_default();
+            self.product_loaned
+                .insert(product, &(loaned + loan.amount.0));
+        }
+
+        loan.id = U64(self.next_loan_id);
+        self.next_loan_id += 1;
+        let (id, amount) = (loan.id, loan.amount);
+        loans.push(loan);
+        self.registered_loans.insert(&user, &loans);
+        self.total_loaned += amount.0;
+
+        self.disburse(user, id, amount);
+
+        Ok(())
+    }
+
+    /// Measure the storage of the longest possible account id with no loans,
+    /// registered by a registrar, this is what we charge for a registration.
+    fn measure_account_storage_usage(&mut self) {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.registered_loans.insert(&tmp_account_id, &Vec::new());
+        self.verified_users.insert(&tmp_account_id);
+        self.depositors.insert(&tmp_account_id, &tmp_account_id);
+        self.account_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.registered_loans.remove(&tmp_account_id);
+        self.verified_users.remove(&tmp_account_id);
+        self.depositors.remove(&tmp_account_id);
+    }
+
+    #[cfg(all(test, not(target_arch = "wasm32")))]
+    pub(crate) fn loan_amounts(&self, user: &AccountId) -> Option<Vec<U128>> {
+        self.registered_loans
+            .get(user)
+            .map(|loans| loans.iter().map(|x| x.amount).collect())
+    }
+
+    fn internal_register(&mut self, user: &AccountId) -> Result<(), RegistrationError> {
+        let caller = env::predecessor_account_id();
+        if &caller != user && !self.registrars.contains(&caller) {
+            return Err(RegistrationError::Forbidden);
+        }
+        if self.is_user_registered(user) {
+            return Err(RegistrationError::AlreadyRegistered);
+        }
+
+        let required = self.storage_balance_bounds().min.0;
+        let deposit = env::attached_deposit();
+        if deposit < required {
+            return Err(RegistrationError::InsufficientDeposit { required });
+        }
+
+        self.registered_loans.insert(user, &Vec::new());
+        if &caller != user {
+            self.depositors.insert(user, &caller);
+        }
+        refund(caller, deposit - required);
+        Ok(())
+    }
+
+    fn internal_unregister(&mut self, user: &AccountId) -> Result<(), RegistrationError> {
+        let loans = self
+            .registered_loans
+            .get(user)
+            .ok_or(RegistrationError::NotRegistered)?;
+        if !loans.is_empty() {
+            return Err(RegistrationError::OutstandingLoans);
+        }
+
+        self.registered_loans.remove(user);
+        self.verified_users.remove(user);
+        let deposit = self.storage_balance_bounds().min.0;
+        // The attached yocto is always the user's
+        match self.depositors.remove(user) {
+            Some(depositor) => {
+                Promise::new(depositor).transfer(deposit);
+                Promise::new(user.clone()).transfer(1);
+            }
+            None => {
+                Promise::new(user.clone()).transfer(deposit + 1);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn refund(account: AccountId, amount: Balance) {
+    if amount > 0 {
+        Promise::new(account).transfer(amount);
+    }
+}
+
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use std::str::FromStr;
 
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
     use near_sdk::testing_env;
 
     use super::*;
@@ -76,6 +387,22 @@ mod tests {
         builder
     }
 
+    /// Register `user` as themselves with the minimum storage deposit
+    fn register_as(contract: &mut Contract, user: &AccountId) -> bool {
+        let mut context = get_context(user.clone());
+        testing_env!(context
+            .attached_deposit(contract.storage_balance_bounds().min.0)
+            .build());
+        contract.register(user.clone())
+    }
+
+    /// Register `user` and have the prover verify them
+    fn register_verified_as(contract: &mut Contract, user: &AccountId) {
+        assert_eq!(register_as(contract, user), true);
+        testing_env!(get_context(accounts(1)).build());
+        assert_eq!(contract.register_verified(user.clone()), true);
+    }
+
     #[test]
     #[should_panic(expected = "The contract is not initialized")]
     fn test_default() {
@@ -89,20 +416,21 @@ mod tests {
         let mut context = get_context(accounts(1));
         testing_env!(context.build());
         let prover = AccountId::from_str("prover.near").unwrap();
-        let contract = Contract::initialize(prover.clone());
+        let contract = Contract::initialize(prover.clone(), None);
         testing_env!(context.is_view(true).build());
         assert_eq!(contract.registered_loans.is_empty(), true);
-        assert_eq!(contract.prover, prover)
+        assert_eq!(contract.get_provers(), (vec![prover], 1));
+        assert!(contract.account_storage_usage > 0);
     }
 
     #[test]
     fn test_register_new_user() {
         let prover = AccountId::from_str("prover.near").unwrap();
         let new_user = AccountId::from_str("user.near").unwrap();
-        let mut contract = Contract::initialize(prover);
-        assert_eq!(contract.register(new_user.clone()), true);
+        let mut contract = Contract::initialize(prover, None);
+        assert_eq!(register_as(&mut contract, &new_user), true);
         assert_eq!(contract.registered_loans.len(), 1);
-        assert_eq!(contract.registered_loans.get(&new_user), Some(Vec::new()));
+        assert_eq!(contract.loan_amounts(&new_user), Some(Vec::new()));
     }
 
     #[test]
@@ -110,31 +438,133 @@ mod tests {
         let prover = AccountId::from_str("prover.near").unwrap();
         let new_user = AccountId::from_str("user.near").unwrap();
 
-        let mut contract = Contract::initialize(prover);
-        assert_eq!(contract.register(new_user.clone()), true);
+        let mut contract = Contract::initialize(prover, None);
+        assert_eq!(register_as(&mut contract, &new_user), true);
         assert_eq!(contract.registered_loans.len(), 1);
-        assert_eq!(contract.registered_loans.get(&new_user), Some(Vec::new()));
+        assert_eq!(contract.loan_amounts(&new_user), Some(Vec::new()));
 
-        assert_eq!(contract.register(new_user.clone()), false);
+        assert_eq!(register_as(&mut contract, &new_user), false);
         assert_eq!(contract.registered_loans.len(), 1);
-        assert_eq!(contract.registered_loans.get(&new_user), Some(Vec::new()));
+        assert_eq!(contract.loan_amounts(&new_user), Some(Vec::new()));
     }
 
     #[test]
-    fn test_verified_loan() {
-        let prover = accounts(1);
-        let context = get_context(prover.clone());
-        testing_env!(context.build());
+    fn test_register_without_deposit() {
+        let prover = AccountId::from_str("prover.near").unwrap();
+        let new_user = AccountId::from_str("user.near").unwrap();
+        testing_env!(get_context(new_user.clone()).build());
+
+        let mut contract = Contract::initialize(prover, None);
+        assert_eq!(contract.register(new_user.clone()), false);
+        assert_eq!(contract.registered_loans.len(), 0);
+    }
+
+    #[test]
+    fn test_register_other_user() {
+        let prover = AccountId::from_str("prover.near").unwrap();
+        let new_user = AccountId::from_str("user.near").unwrap();
+        let mut contract = Contract::initialize(prover, None);
+        let deposit = contract.storage_balance_bounds().min.0;
+
+        testing_env!(get_context(accounts(2)).attached_deposit(deposit).build());
+        assert_eq!(contract.register(new_user.clone()), false);
+        assert_eq!(contract.registered_loans.len(), 0);
+
+        assert_eq!(contract.is_registrar(accounts(2)), false);
+        testing_env!(get_context(accounts(0)).build());
+        assert_eq!(contract.add_registrar(accounts(2)), true);
+        assert_eq!(contract.is_registrar(accounts(2)), true);
 
+        testing_env!(get_context(accounts(2)).attached_deposit(deposit).build());
+        assert_eq!(contract.register(new_user.clone()), true);
+        assert_eq!(contract.loan_amounts(&new_user), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_unregister_refunds_registrar() {
+        let registrar = accounts(2);
         let user = AccountId::from_str("user.near").unwrap();
-        let mut contract = Contract::initialize(prover);
+        let mut contract = Contract::initialize(accounts(1), None);
+        let deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(get_context(accounts(0)).build());
+        contract.add_registrar(registrar.clone());
+        testing_env!(get_context(registrar.clone())
+            .attached_deposit(deposit)
+            .build());
         assert_eq!(contract.register(user.clone()), true);
+        assert_eq!(contract.depositors.get(&user), Some(registrar.clone()));
+
+        testing_env!(get_context(user.clone()).attached_deposit(1).build());
+        assert_eq!(contract.unregister(), true);
+        let transfers: Vec<(AccountId, Balance)> = get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| {
+                let receiver = receipt.receiver_id;
+                receipt
+                    .actions
+                    .into_iter()
+                    .filter_map(move |action| match action {
+                        VmAction::Transfer { deposit } => Some((receiver.clone(), deposit)),
+                        _ => None,
+                    })
+            })
+            .collect();
+        assert_eq!(transfers, vec![(registrar, deposit), (user.clone(), 1)]);
+        assert_eq!(contract.depositors.get(&user), None);
+
+        // Registering themselves, the whole refund is the user's
+        testing_env!(get_context(user.clone()).attached_deposit(deposit).build());
+        assert_eq!(contract.register(user.clone()), true);
+        assert_eq!(contract.depositors.get(&user), None);
+    }
+
+    #[test]
+    fn test_unregister() {
+        let prover = AccountId::from_str("prover.near").unwrap();
+        let user = AccountId::from_str("user.near").unwrap();
+        let mut contract = Contract::initialize(prover, None);
+        assert_eq!(register_as(&mut contract, &user), true);
+
+        testing_env!(get_context(user.clone()).attached_deposit(1).build());
+        assert_eq!(contract.unregister(), true);
+        assert_eq!(contract.registered_loans.len(), 0);
+        assert_eq!(contract.unregister(), false);
+    }
+
+    #[test]
+    fn test_unregister_outstanding_loans() {
+        let prover = accounts(1);
+        let user = AccountId::from_str("user.near").unwrap();
+        let mut contract = Contract::initialize(prover.clone(), None);
+        register_verified_as(&mut contract, &user);
+
+        testing_env!(get_context(prover).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(123), [1; 32].into(), None, None),
+            Ok(true)
+        );
+
+        testing_env!(get_context(user.clone()).attached_deposit(1).build());
+        assert_eq!(contract.unregister(), false);
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(123)]));
+    }
+
+    #[test]
+    fn test_verified_loan() {
+        let prover = accounts(1);
+        let user = AccountId::from_str("user.near").unwrap();
+        let mut contract = Contract::initialize(prover.clone(), None);
+        register_verified_as(&mut contract, &user);
         assert_eq!(contract.registered_loans.len(), 1);
-        assert_eq!(contract.registered_loans.get(&user), Some(Vec::new()));
+        assert_eq!(contract.loan_amounts(&user), Some(Vec::new()));
 
-        assert_eq!(contract.verified_loan(user.clone(), U128(123)), true);
+        testing_env!(get_context(prover).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(123), [1; 32].into(), None, None),
+            Ok(true)
+        );
         assert_eq!(contract.registered_loans.len(), 1);
-        assert_eq!(contract.registered_loans.get(&user), Some(vec![U128(123)]));
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(123)]));
     }
 
     #[test]
@@ -144,24 +574,75 @@ mod tests {
         testing_env!(context.build());
 
         let user = AccountId::from_str("user.near").unwrap();
-        let mut contract = Contract::initialize(prover);
+        let mut contract = Contract::initialize(prover, None);
 
-        assert_eq!(contract.verified_loan(user.clone(), U128(123)), false);
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(123), [1; 32].into(), None, None),
+            Err(LoanError::NotRegistered)
+        );
         assert_eq!(contract.registered_loans.len(), 0);
     }
 
     #[test]
-    fn test_verified_loan_wrong_caller() {
+    fn test_verified_loan_unverified_user() {
         let prover = accounts(1);
-        let context = get_context(accounts(0));
-        testing_env!(context.build());
+        let user = AccountId::from_str("user.near").unwrap();
+        let mut contract = Contract::initialize(prover.clone(), None);
+        assert_eq!(register_as(&mut contract, &user), true);
+
+        testing_env!(get_context(prover).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(123), [1; 32].into(), None, None),
+            Err(LoanError::NotVerified)
+        );
+        assert_eq!(contract.loan_amounts(&user), Some(Vec::new()));
+    }
 
+    #[test]
+    fn test_register_verified_wrong_caller() {
+        let prover = accounts(1);
         let user = AccountId::from_str("user.near").unwrap();
-        let mut contract = Contract::initialize(prover);
+        let mut contract = Contract::initialize(prover, None);
+        assert_eq!(register_as(&mut contract, &user), true);
 
-        assert_eq!(contract.register(user.clone()), true);
+        testing_env!(get_context(user.clone()).build());
+        assert_eq!(contract.register_verified(user.clone()), false);
+        assert_eq!(contract.verified_users.contains(&user), false);
+    }
+
+    #[test]
+    fn test_verified_loan_wrong_caller() {
+        let prover = accounts(1);
+        let user = AccountId::from_str("user.near").unwrap();
+        let mut contract = Contract::initialize(prover, None);
 
-        assert_eq!(contract.verified_loan(user.clone(), U128(123)), false);
+        register_verified_as(&mut contract, &user);
+
+        testing_env!(get_context(accounts(0)).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(123), [1; 32].into(), None, None),
+            Err(LoanError::Forbidden)
+        );
         assert_eq!(contract.registered_loans.len(), 1);
     }
+
+    #[test]
+    fn test_verified_loan_over_limit() {
+        let prover = accounts(1);
+        let user = AccountId::from_str("user.near").unwrap();
+        let limits = LoanLimits {
+            max_loan: Some(U128(100)),
+            ..Default::default()
+        };
+        let mut contract = Contract::initialize(prover.clone(), Some(limits));
+        register_verified_as(&mut contract, &user);
+
+        testing_env!(get_context(prover).build());
+        assert_eq!(
+            contract.verified_loan(user.clone(), U128(123), [1; 32].into(), None, None),
+            Err(LoanError::ExceedsMaxLoan { max: U128(100) })
+        );
+        assert_eq!(contract.loan_amounts(&user), Some(Vec::new()));
+        assert_eq!(contract.total_loaned, 0);
+    }
 }
@@ -0,0 +1,319 @@
+//! Caps on how much can be lent, checked before any funds are released so a
+//! single proof flow can't drain the lender.
+use crate::{Contract, ContractExt, Loan};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, FunctionError};
+use std::fmt;
+
+/// Loan caps, in the same units as the loan amounts. `None` means uncapped.
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LoanLimits {
+    /// The largest single loan
+    pub max_loan: Option<U128>,
+    /// The most a single user may have outstanding
+    pub max_outstanding: Option<U128>,
+    /// The most the lender may have outstanding across all users
+    pub max_exposure: Option<U128>,
+}
+
+/// Reasons a loan is refused, the prefix of each message is stable so the
+/// prover can tell them apart.
+#[derive(Debug, PartialEq, Eq, FunctionError)]
+pub enum LoanError {
+    Forbidden,
+    NotRegistered,
+    NotVerified,
+    ExceedsMaxLoan { max: U128 },
+    ExceedsUserCap { outstanding: U128, max: U128 },
+    ExceedsExposureCap { exposure: U128, max: U128 },
+    NoVerificationKey,
+    InvalidPublicInputs,
+    InvalidProof,
+    StaleProof,
+    AlreadyAttested,
+    ProofAlreadyUsed,
+    CircuitVersionTooLong { max: u32 },
+    ProductTooLong { max: u32 },
+    MissingHeuristic { id: u8 },
+}
+
+impl fmt::Display for LoanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoanError::Forbidden => write!(f, "ERR_FORBIDDEN: Forbidden"),
+            LoanError::NotRegistered => write!(f, "ERR_NOT_REGISTERED: User is not registered"),
+            LoanError::NotVerified => write!(
+                f,
+                "ERR_NOT_VERIFIED: User has not proven the registration criteria"
+            ),
+            LoanError::ExceedsMaxLoan { max } => {
+                write!(f, "ERR_MAX_LOAN: Loan exceeds the maximum of {}", max.0)
+            }
+            LoanError::ExceedsUserCap { outstanding, max } => write!(
+                f,
+                "ERR_USER_CAP: User has {} outstanding, the cap is {}",
+                outstanding.0, max.0
+            ),
+            LoanError::ExceedsExposureCap { exposure, max } => write!(
+                f,
+                "ERR_EXPOSURE_CAP: Lender has {} outstanding, the cap is {}",
+                exposure.0, max.0
+            ),
+            LoanError::NoVerificationKey => write!(
+                f,
+                "ERR_NO_VERIFICATION_KEY: Onchain verification is not enabled"
+            ),
+            LoanError::InvalidPublicInputs => write!(
+                f,
+                "ERR_INVALID_PUBLIC_INPUTS: Public inputs don't match the signer and amount"
+            ),
+            LoanError::InvalidProof => write!(f, "ERR_INVALID_PROOF: Proof failed verification"),
+            LoanError::StaleProof => write!(
+                f,
+                "ERR_STALE_PROOF: Proof was made before yesterday or in the future"
+            ),
+            LoanError::AlreadyAttested => {
+                write!(
+                    f,
+                    "ERR_ALREADY_ATTESTED: Prover already attested to this proof"
+                )
+            }
+            LoanError::ProofAlreadyUsed => {
+                write!(f, "ERR_PROOF_USED: Proof has already released a loan")
+            }
+            LoanError::CircuitVersionTooLong { max } => write!(
+                f,
+                "ERR_CIRCUIT_VERSION: Circuit versions are at most {} bytes",
+                max
+            ),
+            LoanError::ProductTooLong { max } => {
+                write!(f, "ERR_PRODUCT: Products are at most {} bytes", max)
+            }
+            LoanError::MissingHeuristic { id } => write!(
+                f,
+                "ERR_MISSING_HEURISTIC: Proof doesn't prove required heuristic {}",
+                id
+            ),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    #[private]
+    pub fn set_limits(&mut self, limits: LoanLimits) {
+        self.limits = limits;
+    }
+
+    pub fn get_limits(&self) -> LoanLimits {
+        self.limits.clone()
+    }
+
+    /// Cap the loans of `product` on top of the lender's limits, `None` removes
+    /// the caps. The user cap only counts the user's loans of the product.
+    #[private]
+    pub fn set_product_limits(&mut self, product: String, limits: Option<LoanLimits>) {
+        match limits {
+            Some(limits) => self.product_limits.insert(&product, &limits),
+            None => self.product_limits.remove(&product),
+        };
+    }
+
+    pub fn get_product_limits(&self, product: String) -> Option<LoanLimits> {
+        self.product_limits.get(&product)
+    }
+}
+
+impl Contract {
+    /// Check a new loan of `amount` against the limits given the user's existing loans
+    pub(crate) fn check_limits(&self, loans: &[Loan], amount: U128) -> Result<(), LoanError> {
+        check(&self.limits, loans, self.total_loaned, amount)
+    }
+
+    /// Check a new loan of `product` against the product's limits, given the
+    /// user's existing loans of every product
+    pub(crate) fn check_product_limits(
+        &self,
+        loans: &[Loan],
+        product: &str,
+        amount: U128,
+    ) -> Result<(), LoanError> {
+        let limits = match self.product_limits.get(&product.to_string()) {
+            Some(limits) => limits,
+            None => return Ok(()),
+        };
+        let loans: Vec<Loan> = loans
+            .iter()
+            .filter(|x| x.product.as_deref() == Some(product))
+            .cloned()
+            .collect();
+        let exposure = self
+            .product_loaned
+            .get(&product.to_string())
+            .unwrap_or_default();
+        check(&limits, &loans, exposure, amount)
+    }
+
+    /// `amount` of a loan of `product` is no longer outstanding
+    pub(crate) fn release_product(&mut self, product: Option<&String>, amount: u128) {
+        if let Some(product) = product {
+            let loaned = self.product_loaned.get(product).unwrap_or_default();
+            self.product_loaned
+                .insert(product, &loaned.saturating_sub(amount));
+        }
+    }
+}
+
+fn check(
+    limits: &LoanLimits,
+    loans: &[Loan],
+    exposure: u128,
+    amount: U128,
+) -> Result<(), LoanError> {
+    if let Some(max) = limits.max_loan {
+        if amount.0 > max.0 {
+            return Err(LoanError::ExceedsMaxLoan { max });
+        }
+    }
+    if let Some(max) = limits.max_outstanding {
+        let outstanding: u128 = loans.iter().map(|x| x.amount.0).sum();
+        if outstanding + amount.0 > max.0 {
+            return Err(LoanError::ExceedsUserCap {
+                outstanding: outstanding.into(),
+                max,
+            });
+        }
+    }
+    if let Some(max) = limits.max_exposure {
+        if exposure + amount.0 > max.0 {
+            return Err(LoanError::ExceedsExposureCap {
+                exposure: exposure.into(),
+                max,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+
+    use super::*;
+
+    fn loans(amounts: &[u128]) -> Vec<Loan> {
+        amounts.iter().map(|x| Loan::new(U128(*x), None)).collect()
+    }
+
+    fn limits(max_loan: u128, max_outstanding: u128, max_exposure: u128) -> LoanLimits {
+        LoanLimits {
+            max_loan: Some(U128(max_loan)),
+            max_outstanding: Some(U128(max_outstanding)),
+            max_exposure: Some(U128(max_exposure)),
+        }
+    }
+
+    #[test]
+    fn test_uncapped() {
+        let contract = Contract::initialize(accounts(1), None);
+        assert_eq!(
+            contract.check_limits(&loans(&[u64::MAX as u128]), U128(1)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_max_loan() {
+        let contract = Contract::initialize(accounts(1), Some(limits(10, 100, 1000)));
+        assert_eq!(contract.check_limits(&[], U128(10)), Ok(()));
+        assert_eq!(
+            contract.check_limits(&[], U128(11)),
+            Err(LoanError::ExceedsMaxLoan { max: U128(10) })
+        );
+    }
+
+    #[test]
+    fn test_user_cap() {
+        let contract = Contract::initialize(accounts(1), Some(limits(10, 15, 1000)));
+        assert_eq!(contract.check_limits(&loans(&[5]), U128(10)), Ok(()));
+        assert_eq!(
+            contract.check_limits(&loans(&[10]), U128(10)),
+            Err(LoanError::ExceedsUserCap {
+                outstanding: U128(10),
+                max: U128(15)
+            })
+        );
+    }
+
+    #[test]
+    fn test_product_limits() {
+        let mut contract = Contract::initialize(accounts(1), Some(limits(10, 100, 1000)));
+        contract.set_product_limits("small".into(), Some(limits(5, 8, 20)));
+        assert_eq!(
+            contract.check_product_limits(&[], "large", U128(10)),
+            Ok(())
+        );
+        assert_eq!(
+            contract.check_product_limits(&[], "small", U128(6)),
+            Err(LoanError::ExceedsMaxLoan { max: U128(5) })
+        );
+
+        // Only loans of the product count towards its caps
+        let mut loans = loans(&[5, 50]);
+        loans[0].product = Some("small".into());
+        assert_eq!(
+            contract.check_product_limits(&loans, "small", U128(3)),
+            Ok(())
+        );
+        assert_eq!(
+            contract.check_product_limits(&loans, "small", U128(4)),
+            Err(LoanError::ExceedsUserCap {
+                outstanding: U128(5),
+                max: U128(8)
+            })
+        );
+
+        contract.product_loaned.insert(&"small".into(), &18);
+        assert_eq!(
+            contract.check_product_limits(&[], "small", U128(3)),
+            Err(LoanError::ExceedsExposureCap {
+                exposure: U128(18),
+                max: U128(20)
+            })
+        );
+        contract.release_product(Some(&"small".into()), 10);
+        assert_eq!(contract.check_product_limits(&[], "small", U128(3)), Ok(()));
+
+        contract.set_product_limits("small".into(), None);
+        assert_eq!(contract.get_product_limits("small".into()), None);
+    }
+
+    #[test]
+    fn test_exposure_cap() {
+        let mut contract = Contract::initialize(accounts(1), Some(limits(10, 15, 20)));
+        contract.total_loaned = 15;
+        assert_eq!(contract.check_limits(&[], U128(5)), Ok(()));
+        assert_eq!(
+            contract.check_limits(&[], U128(6)),
+            Err(LoanError::ExceedsExposureCap {
+                exposure: U128(15),
+                max: U128(20)
+            })
+        );
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert!(LoanError::Forbidden
+            .to_string()
+            .starts_with("ERR_FORBIDDEN"));
+        assert!(LoanError::ExceedsMaxLoan { max: U128(1) }
+            .to_string()
+            .starts_with("ERR_MAX_LOAN"));
+    }
+}
@@ -0,0 +1,144 @@
+//! NEP-145 storage management, registration is the only thing we charge storage for
+//! so the balance bounds are fixed and nothing is ever available to withdraw.
+use crate::{Contract, ContractExt, RegistrationError};
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::json_types::U128;
+use near_sdk::{assert_one_yocto, env, log, near_bindgen, AccountId, Balance, Promise};
+
+impl Contract {
+    fn internal_storage_balance_of(&self, account_id: &AccountId) -> Option<StorageBalance> {
+        if self.registered_loans.get(account_id).is_some() {
+            Some(StorageBalance {
+                total: self.storage_balance_bounds().min,
+                available: 0.into(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for Contract {
+    /// Registers `account_id`, or the caller if omitted. The same permissions as
+    /// `register` apply, `registration_only` has no effect since we only charge
+    /// the minimum.
+    #[payable]
+    #[allow(unused_variables)]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        match self.internal_register(&account_id) {
+            Ok(()) => {}
+            Err(RegistrationError::AlreadyRegistered) => {
+                log!("The account is already registered, refunding the deposit");
+                let amount: Balance = env::attached_deposit();
+                if amount > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(amount);
+                }
+            }
+            Err(e) => env::panic_str(&e.to_string()),
+        }
+        self.internal_storage_balance_of(&account_id).unwrap()
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let predecessor_account_id = env::predecessor_account_id();
+        match self.internal_storage_balance_of(&predecessor_account_id) {
+            Some(balance) => match amount {
+                Some(amount) if amount.0 > 0 => {
+                    env::panic_str("The amount is greater than the available storage balance")
+                }
+                _ => balance,
+            },
+            None => env::panic_str(&RegistrationError::NotRegistered.to_string()),
+        }
+    }
+
+    /// Loans can't be burned, so `force` is ignored and unregistering with
+    /// outstanding loans always panics.
+    #[payable]
+    #[allow(unused_variables)]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        match self.internal_unregister(&env::predecessor_account_id()) {
+            Ok(()) => true,
+            Err(RegistrationError::NotRegistered) => false,
+            Err(e) => env::panic_str(&e.to_string()),
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        let required_storage_balance =
+            Balance::from(self.account_storage_usage) * env::storage_byte_cost();
+        StorageBalanceBounds {
+            min: required_storage_balance.into(),
+            max: Some(required_storage_balance.into()),
+        }
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.internal_storage_balance_of(&account_id)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::str::FromStr;
+
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    #[test]
+    fn test_storage_deposit() {
+        let user = AccountId::from_str("user.near").unwrap();
+        let mut contract = Contract::initialize(accounts(1), None);
+        let min = contract.storage_balance_bounds().min;
+
+        testing_env!(get_context(user.clone())
+            .attached_deposit(min.0 * 2)
+            .build());
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.total, min);
+        assert_eq!(balance.available, U128(0));
+        assert!(contract.storage_balance_of(user.clone()).is_some());
+
+        // Re-registering refunds rather than panicking
+        contract.storage_deposit(Some(user), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached deposit is less than")]
+    fn test_storage_deposit_insufficient() {
+        let user = AccountId::from_str("user.near").unwrap();
+        let mut contract = Contract::initialize(accounts(1), None);
+        testing_env!(get_context(user).attached_deposit(1).build());
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    fn test_storage_unregister_not_registered() {
+        let user = AccountId::from_str("user.near").unwrap();
+        let mut contract = Contract::initialize(accounts(1), None);
+        testing_env!(get_context(user).attached_deposit(1).build());
+        assert_eq!(contract.storage_unregister(None), false);
+    }
+}
@@ -0,0 +1,392 @@
+//! Loan disbursement and repayment, loans are paid out in either native NEAR or
+//! a single NEP-141 token configured for the pool.
+use crate::{Contract, ContractExt};
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, ext_contract, is_promise_success, log, near_bindgen, require, AccountId, Balance, Gas,
+    Promise, PromiseOrValue,
+};
+
+const GAS_FOR_STORAGE_DEPOSIT: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_ON_LOAN_DISBURSED: Gas = Gas(10_000_000_000_000);
+/// The most decimals a token may have, one whole unit of it must fit a `u128`
+pub const MAX_DECIMALS: u8 = 38;
+
+#[ext_contract(ext_storage_management)]
+pub trait TokenStorageManagement {
+    fn storage_deposit(&mut self, account_id: Option<AccountId>, registration_only: Option<bool>);
+}
+
+/// What the lender pays loans out in, loan amounts are always in whole units of it
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum LoanAsset {
+    Native,
+    FungibleToken {
+        token: AccountId,
+        /// The token's decimals, as in its `ft_metadata`
+        decimals: u8,
+        /// Attached to `storage_deposit` when registering a borrower with the token
+        storage_deposit: U128,
+    },
+}
+
+impl Default for LoanAsset {
+    fn default() -> Self {
+        LoanAsset::Native
+    }
+}
+
+impl LoanAsset {
+    /// The smallest denomination in one whole unit
+    fn unit(&self) -> Balance {
+        match self {
+            LoanAsset::Native => near_sdk::ONE_NEAR,
+            LoanAsset::FungibleToken { decimals, .. } => 10u128.pow(*decimals as u32),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Change what loans are paid out in, only while nothing is outstanding
+    #[private]
+    pub fn set_asset(&mut self, asset: LoanAsset) {
+        require!(
+            self.total_loaned == 0,
+            "Can't change the loan asset with outstanding loans"
+        );
+        if let LoanAsset::FungibleToken { decimals, .. } = &asset {
+            require!(
+                *decimals <= MAX_DECIMALS,
+                format!("Tokens have at most {} decimals", MAX_DECIMALS)
+            );
+        }
+        self.asset = asset;
+    }
+
+    pub fn get_asset(&self) -> LoanAsset {
+        self.asset.clone()
+    }
+
+    /// Reverts the loan `loan_id` if the token transfer failed, otherwise it
+    /// can be repaid from now on
+    #[private]
+    pub fn on_loan_disbursed(&mut self, user: AccountId, loan_id: U64) -> bool {
+        let pending = self.pending_disbursements.remove(&loan_id.0);
+        if is_promise_success() {
+            return true;
+        }
+        if !pending {
+            return false;
+        }
+
+        log!(format!("Failed to disburse loan {} to {}", loan_id.0, user));
+        if let Some(mut loans) = self.registered_loans.get(&user) {
+            if let Some(i) = loans.iter().position(|x| x.id == loan_id) {
+                // Never repaid while pending, so this is the whole loan
+                let loan = loans.remove(i);
+                self.registered_loans.insert(&user, &loans);
+                self.total_loaned -= loan.amount.0;
+                self.release_product(loan.product.as_ref(), loan.amount.0);
+            }
+        }
+        false
+    }
+}
+
+impl Contract {
+    /// Pay the loan `loan_id` of `amount` whole units of the loan asset out to `user`
+    pub(crate) fn disburse(&mut self, user: AccountId, loan_id: U64, amount: U128) {
+        let token_amt = self.asset.unit() * amount.0;
+        match &self.asset {
+            LoanAsset::Native => {
+                Promise::new(user).transfer(token_amt);
+            }
+            LoanAsset::FungibleToken {
+                token,
+                storage_deposit,
+                ..
+            } => {
+                self.pending_disbursements.insert(&loan_id.0);
+                // Registration only, so this is refunded if the user is already registered
+                ext_storage_management::ext(token.clone())
+                    .with_attached_deposit(storage_deposit.0)
+                    .with_static_gas(GAS_FOR_STORAGE_DEPOSIT)
+                    .storage_deposit(Some(user.clone()), Some(true))
+                    .then(
+                        ext_ft_core::ext(token.clone())
+                            .with_attached_deposit(1)
+                            .with_static_gas(GAS_FOR_FT_TRANSFER)
+                            .ft_transfer(
+                                user.clone(),
+                                token_amt.into(),
+                                Some("GhostFi loan".into()),
+                            ),
+                    )
+                    .then(
+                        Self::ext(env::current_account_id())
+                            .with_static_gas(GAS_FOR_ON_LOAN_DISBURSED)
+                            .on_loan_disbursed(user, loan_id),
+                    );
+            }
+        }
+    }
+
+    /// Repay `user`'s disbursed loans oldest first, returns the units that
+    /// weren't owed
+    pub(crate) fn internal_repay(&mut self, user: &AccountId, units: u128) -> u128 {
+        let mut loans = match self.registered_loans.get(user) {
+            Some(loans) => loans,
+            None => return units,
+        };
+
+        let mut remaining = units;
+        let mut i = 0;
+        while remaining > 0 && i < loans.len() {
+            if self.pending_disbursements.contains(&loans[i].id.0) {
+                i += 1;
+                continue;
+            }
+            let owed = loans[i].amount.0;
+            let repaid = owed.min(remaining);
+            self.release_product(loans[i].product.as_ref(), repaid);
+            remaining -= repaid;
+            if repaid == owed {
+                loans.remove(i);
+            } else {
+                loans[i].amount = U128(owed - repaid);
+            }
+        }
+
+        self.total_loaned -= units - remaining;
+        self.registered_loans.insert(user, &loans);
+        remaining
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+    /// Repayments are made with `ft_transfer_call` of the loan token. `msg` may name
+    /// the borrower being repaid for, otherwise it is the sender. Anything more
+    /// than what's owed is returned.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let accepted = matches!(
+            &self.asset,
+            LoanAsset::FungibleToken { token, .. } if token == &env::predecessor_account_id()
+        );
+        require!(
+            accepted,
+            "ERR_WRONG_TOKEN: Repayments must be in the loan token"
+        );
+
+        let user = if msg.is_empty() {
+            sender_id
+        } else {
+            msg.parse().unwrap_or_else(|_| {
+                env::panic_str("ERR_INVALID_BORROWER: msg must be an account id")
+            })
+        };
+
+        let unit = self.asset.unit();
+        let units = amount.0 / unit;
+        let unused = self.internal_repay(&user, units);
+        log!(format!("Repaid {} for {}", units - unused, user));
+
+        PromiseOrValue::Value(U128(amount.0 - (units - unused) * unit))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::str::FromStr;
+
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, testing_env_with_promise_results, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseResult};
+
+    use super::*;
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    fn token() -> AccountId {
+        AccountId::from_str("usdc.near").unwrap()
+    }
+
+    /// A token pool with `user` owing `loans`, none of them paid out yet
+    fn setup_pending(user: &AccountId, loans: &[u128]) -> Contract {
+        let prover = accounts(1);
+        let mut contract = Contract::initialize(prover.clone(), None);
+
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_asset(LoanAsset::FungibleToken {
+            token: token(),
+            decimals: 6,
+            storage_deposit: U128(1_250_000_000_000_000_000_000),
+        });
+
+        let deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(get_context(user.clone()).attached_deposit(deposit).build());
+        assert!(contract.register(user.clone()));
+        testing_env!(get_context(prover).build());
+        assert!(contract.register_verified(user.clone()));
+        for (i, loan) in loans.iter().enumerate() {
+            let proof_hash = [i as u8; 32].into();
+            assert_eq!(
+                contract.verified_loan(user.clone(), U128(*loan), proof_hash, None, None),
+                Ok(true)
+            );
+        }
+        contract
+    }
+
+    /// A token pool with `user` owing `loans`, all of them paid out
+    fn setup(user: &AccountId, loans: &[u128]) -> Contract {
+        let mut contract = setup_pending(user, loans);
+        testing_env_with_promise_results(
+            get_context(accounts(0)).build(),
+            PromiseResult::Successful(vec![]),
+        );
+        for id in 0..loans.len() {
+            assert!(contract.on_loan_disbursed(user.clone(), U64(id as u64)));
+        }
+        contract
+    }
+
+    #[test]
+    fn test_repay_oldest_first() {
+        let user = accounts(2);
+        let mut contract = setup(&user, &[10, 20]);
+
+        assert_eq!(contract.internal_repay(&user, 15), 0);
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(15)]));
+        assert_eq!(contract.total_loaned, 15);
+
+        assert_eq!(contract.internal_repay(&user, 20), 5);
+        assert_eq!(contract.loan_amounts(&user), Some(vec![]));
+        assert_eq!(contract.total_loaned, 0);
+    }
+
+    #[test]
+    fn test_ft_on_transfer() {
+        let user = accounts(2);
+        let mut contract = setup(&user, &[10]);
+
+        testing_env!(get_context(token()).build());
+        // 4.5 tokens repays 4 and returns the half
+        let unused = contract.ft_on_transfer(user.clone(), U128(4_500_000), "".into());
+        assert!(matches!(unused, PromiseOrValue::Value(U128(500_000))));
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(6)]));
+
+        // Repaying on behalf of the user
+        let unused = contract.ft_on_transfer(accounts(3), U128(10_000_000), user.to_string());
+        assert!(matches!(unused, PromiseOrValue::Value(U128(4_000_000))));
+        assert_eq!(contract.loan_amounts(&user), Some(vec![]));
+    }
+
+    #[test]
+    #[should_panic(expected = "ERR_WRONG_TOKEN")]
+    fn test_ft_on_transfer_wrong_token() {
+        let user = accounts(2);
+        let mut contract = setup(&user, &[10]);
+
+        testing_env!(get_context(accounts(3)).build());
+        contract.ft_on_transfer(user, U128(1_000_000), "".into());
+    }
+
+    #[test]
+    fn test_repay_pending() {
+        let user = accounts(2);
+        let mut contract = setup_pending(&user, &[10, 20]);
+
+        // Nothing is repaid before the transfer resolves
+        testing_env!(get_context(token()).build());
+        let unused = contract.ft_on_transfer(user.clone(), U128(5_000_000), "".into());
+        assert!(matches!(unused, PromiseOrValue::Value(U128(5_000_000))));
+        assert_eq!(contract.total_loaned, 30);
+
+        // Only the disbursed loan is repaid, the failed one is reverted whole
+        testing_env_with_promise_results(
+            get_context(accounts(0)).build(),
+            PromiseResult::Successful(vec![]),
+        );
+        assert!(contract.on_loan_disbursed(user.clone(), U64(1)));
+        assert_eq!(contract.internal_repay(&user, 25), 5);
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(10)]));
+        testing_env_with_promise_results(get_context(accounts(0)).build(), PromiseResult::Failed);
+        assert!(!contract.on_loan_disbursed(user.clone(), U64(0)));
+        assert_eq!(contract.loan_amounts(&user), Some(vec![]));
+        assert_eq!(contract.total_loaned, 0);
+    }
+
+    #[test]
+    fn test_on_loan_disbursed_failure() {
+        let user = accounts(2);
+        let mut contract = setup_pending(&user, &[10, 20]);
+
+        testing_env_with_promise_results(get_context(accounts(0)).build(), PromiseResult::Failed);
+        assert_eq!(contract.on_loan_disbursed(user.clone(), U64(1)), false);
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(10)]));
+        assert_eq!(contract.total_loaned, 10);
+    }
+
+    #[test]
+    fn test_on_loan_disbursed_same_amounts() {
+        let user = accounts(2);
+        let mut contract = setup_pending(&user, &[10, 20, 10]);
+        let ids = |contract: &Contract| -> Vec<U64> {
+            let loans = contract.registered_loans.get(&user).unwrap();
+            loans.iter().map(|x| x.id).collect()
+        };
+        assert_eq!(ids(&contract), vec![U64(0), U64(1), U64(2)]);
+
+        // The first of two loans of the same amount failed
+        testing_env_with_promise_results(get_context(accounts(0)).build(), PromiseResult::Failed);
+        assert_eq!(contract.on_loan_disbursed(user.clone(), U64(0)), false);
+        assert_eq!(ids(&contract), vec![U64(1), U64(2)]);
+        assert_eq!(contract.total_loaned, 30);
+
+        // Already reverted
+        assert_eq!(contract.on_loan_disbursed(user.clone(), U64(0)), false);
+        assert_eq!(contract.total_loaned, 30);
+    }
+
+    #[test]
+    #[should_panic(expected = "outstanding loans")]
+    fn test_set_asset_outstanding() {
+        let user = accounts(2);
+        let mut contract = setup(&user, &[10]);
+
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_asset(LoanAsset::Native);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 38 decimals")]
+    fn test_set_asset_decimals() {
+        let mut contract = Contract::initialize(accounts(1), None);
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_asset(LoanAsset::FungibleToken {
+            token: token(),
+            decimals: 39,
+            storage_deposit: U128(0),
+        });
+    }
+}
@@ -0,0 +1,709 @@
+//! Groth16 verification over BN254 with NEAR's alt_bn128 host functions, so a
+//! loan can be released against a proof without trusting the prover account.
+//!
+//! Points and scalars are little endian as the host functions expect. Proofs must
+//! come from a Groth16 backend for the `apply` circuit, the default nargo backend
+//! produces UltraPlonk proofs which can't be verified here. The prover converts
+//! the backend's key and proofs, see `prover-monolith set-verification-key`.
+use crate::{Contract, ContractExt, Loan, LoanError};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{Base58CryptoHash, Base64VecU8, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{env, near_bindgen, require, sys, AccountId, CurveType};
+
+const G1_LEN: usize = 64;
+const G2_LEN: usize = 128;
+const SCALAR_LEN: usize = 32;
+const REGISTRAR_KEY_LEN: usize = 64;
+/// The heuristic slots of the `apply` circuit, `HEURISTICS` in main.nr
+pub const HEURISTICS: usize = 4;
+/// How many days before the block's a proof may have been made, so proofs made
+/// just before midnight still verify
+pub const MAX_PROOF_AGE_DAYS: u64 = 1;
+const NANOS_PER_DAY: u64 = 86_400 * 1_000_000_000;
+/// The same scratch register the sdk uses for single host calls
+const REGISTER: u64 = u64::MAX - 2;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VerificationKey {
+    pub alpha_g1: Base64VecU8,
+    pub beta_g2: Base64VecU8,
+    pub gamma_g2: Base64VecU8,
+    pub delta_g2: Base64VecU8,
+    /// The constant term followed by one point per public input
+    pub ic: Vec<Base64VecU8>,
+}
+
+impl VerificationKey {
+    fn is_well_formed(&self) -> bool {
+        self.alpha_g1.0.len() == G1_LEN
+            && [&self.beta_g2, &self.gamma_g2, &self.delta_g2]
+                .iter()
+                .all(|x| x.0.len() == G2_LEN)
+            && !self.ic.is_empty()
+            && self.ic.iter().all(|x| x.0.len() == G1_LEN)
+    }
+
+    /// Identifies the circuit the key verifies
+    pub fn version(&self) -> String {
+        let hash = env::sha256_array(&self.try_to_vec().unwrap());
+        String::from(&Base58CryptoHash::from(hash))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Groth16Proof {
+    pub a: Base64VecU8,
+    pub b: Base64VecU8,
+    pub c: Base64VecU8,
+}
+
+impl Groth16Proof {
+    fn is_well_formed(&self) -> bool {
+        self.a.0.len() == G1_LEN && self.b.0.len() == G2_LEN && self.c.0.len() == G1_LEN
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    #[private]
+    pub fn set_verification_key(&mut self, verification_key: VerificationKey) {
+        require!(
+            verification_key.is_well_formed(),
+            "Malformed verification key"
+        );
+        self.verification_key.set(&verification_key);
+    }
+
+    pub fn get_verification_key(&self) -> Option<VerificationKey> {
+        self.verification_key.get()
+    }
+
+    /// Set the registrar key attested identities are checked with, the `x || y`
+    /// coordinates of its secp256k1 key
+    #[private]
+    pub fn set_registrar_key(&mut self, registrar_key: Base64VecU8) {
+        require!(
+            registrar_key.0.len() == REGISTRAR_KEY_LEN,
+            "Malformed registrar key"
+        );
+        self.registrar_key.set(&registrar_key.0);
+    }
+
+    pub fn get_registrar_key(&self) -> Option<Base64VecU8> {
+        self.registrar_key.get().map(Base64VecU8)
+    }
+
+    /// Set the heuristic ids every onchain verified proof must prove, the ids
+    /// of the default product's heuristics
+    #[private]
+    pub fn set_required_heuristics(&mut self, heuristics: Vec<u8>) {
+        require!(
+            heuristics.iter().all(|id| *id != 0),
+            "Heuristic 0 is the empty slot"
+        );
+        self.required_heuristics = heuristics;
+    }
+
+    pub fn get_required_heuristics(&self) -> Vec<u8> {
+        self.required_heuristics.clone()
+    }
+
+    /// The nonce the next proof of `user` must be made with
+    pub fn get_nonce(&self, user: AccountId) -> U64 {
+        self.nonces.get(&user).unwrap_or_default().into()
+    }
+
+    /// Take a loan of `amount` by proving the lending criteria onchain. The proof
+    /// is checked against the public inputs of the `apply` circuit for the ed25519
+    /// key signing this call, `amount` and the caller's nonce, so a proof can't
+    /// be replayed by another account or reused once the nonce moves on. The
+    /// `YYYYMMDD` date it was proven on must be the block's or the day before,
+    /// and attested identities are checked with `get_registrar_key`. `heuristics`
+    /// is the heuristic id proven in each circuit slot, 0 when empty, and must
+    /// include `get_required_heuristics`. The loan's circuit version is the hash
+    /// of the verification key. The signer must still have been verified by a
+    /// prover with `register_verified`, the registration criteria read the
+    /// near.social profile and are only proven to the prover.
+    #[handle_result]
+    pub fn verify_and_loan(
+        &mut self,
+        amount: U128,
+        proof: Groth16Proof,
+        proving_date: u32,
+        heuristics: Vec<u8>,
+    ) -> Result<(), LoanError> {
+        let user = env::predecessor_account_id();
+        if user != env::signer_account_id() {
+            return Err(LoanError::Forbidden);
+        }
+        let today = env::block_timestamp() / NANOS_PER_DAY;
+        if !(0..=MAX_PROOF_AGE_DAYS)
+            .filter_map(|age| today.checked_sub(age))
+            .any(|days| civil_date(days) == proving_date)
+        {
+            return Err(LoanError::StaleProof);
+        }
+        let heuristics: [u8; HEURISTICS] = heuristics
+            .try_into()
+            .map_err(|_| LoanError::InvalidPublicInputs)?;
+        if let Some(id) = self
+            .required_heuristics
+            .iter()
+            .find(|id| !heuristics.contains(id))
+        {
+            return Err(LoanError::MissingHeuristic { id: *id });
+        }
+        let verification_key = self
+            .verification_key
+            .get()
+            .ok_or(LoanError::NoVerificationKey)?;
+
+        let signer_pk = env::signer_account_pk();
+        if signer_pk.curve_type() != CurveType::ED25519 {
+            return Err(LoanError::InvalidPublicInputs);
+        }
+        let public_key: [u8; 32] = signer_pk.as_bytes()[1..]
+            .try_into()
+            .map_err(|_| LoanError::InvalidPublicInputs)?;
+        let nonce = self.nonces.get(&user).unwrap_or_default();
+        // Unset, attested identities can't be proven
+        let registrar_key = self
+            .registrar_key
+            .get()
+            .and_then(|x| x.try_into().ok())
+            .unwrap_or([0; REGISTRAR_KEY_LEN]);
+        let public_inputs = apply_public_inputs(
+            &public_key,
+            amount.0,
+            nonce,
+            proving_date,
+            &registrar_key,
+            &heuristics,
+        );
+
+        if !verify(&verification_key, &proof, &public_inputs) {
+            return Err(LoanError::InvalidProof);
+        }
+        let loan = Loan::new(amount, Some(verification_key.version()));
+        self.internal_loan(user.clone(), loan)?;
+        self.nonces.insert(&user, &(nonce + 1));
+        Ok(())
+    }
+}
+
+/// The `YYYYMMDD` civil date `days` after 1970-01-01
+pub fn civil_date(days: u64) -> u32 {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year * 10_000 + month * 100 + day) as u32
+}
+
+/// The public inputs of the `apply` circuit, each public key byte is its own
+/// field element followed by the requested amount, the nonce, the proving date,
+/// the registrar key as four 16 byte big endian halves and the heuristic ids.
+pub fn apply_public_inputs(
+    public_key: &[u8; 32],
+    amount: u128,
+    nonce: u64,
+    proving_date: u32,
+    registrar_key: &[u8; REGISTRAR_KEY_LEN],
+    heuristics: &[u8; HEURISTICS],
+) -> Vec<[u8; SCALAR_LEN]> {
+    let mut inputs: Vec<[u8; SCALAR_LEN]> = public_key
+        .iter()
+        .map(|byte| {
+            let mut scalar = [0; SCALAR_LEN];
+            scalar[0] = *byte;
+            scalar
+        })
+        .collect();
+
+    let mut scalar = [0; SCALAR_LEN];
+    scalar[..16].copy_from_slice(&amount.to_le_bytes());
+    inputs.push(scalar);
+
+    let mut scalar = [0; SCALAR_LEN];
+    scalar[..8].copy_from_slice(&nonce.to_le_bytes());
+    inputs.push(scalar);
+
+    let mut scalar = [0; SCALAR_LEN];
+    scalar[..4].copy_from_slice(&proving_date.to_le_bytes());
+    inputs.push(scalar);
+
+    for half in registrar_key.chunks(16) {
+        let mut scalar = [0; SCALAR_LEN];
+        scalar[..16].copy_from_slice(half);
+        scalar[..16].reverse();
+        inputs.push(scalar);
+    }
+
+    for id in heuristics {
+        let mut scalar = [0; SCALAR_LEN];
+        scalar[0] = *id;
+        inputs.push(scalar);
+    }
+    inputs
+}
+
+/// Check `e(-A, B) * e(alpha, beta) * e(vk_x, gamma) * e(C, delta) == 1`
+/// where `vk_x = ic[0] + sum(input_i * ic[i + 1])`
+pub fn verify(
+    verification_key: &VerificationKey,
+    proof: &Groth16Proof,
+    public_inputs: &[[u8; SCALAR_LEN]],
+) -> bool {
+    if !verification_key.is_well_formed()
+        || !proof.is_well_formed()
+        || verification_key.ic.len() != public_inputs.len() + 1
+    {
+        return false;
+    }
+
+    let mut one = [0; SCALAR_LEN];
+    one[0] = 1;
+    let mut msm = Vec::with_capacity(verification_key.ic.len() * (G1_LEN + SCALAR_LEN));
+    for (point, scalar) in verification_key
+        .ic
+        .iter()
+        .zip(std::iter::once(&one).chain(public_inputs))
+    {
+        msm.extend_from_slice(&point.0);
+        msm.extend_from_slice(scalar);
+    }
+    let vk_x = g1_multiexp(&msm);
+    let neg_a = g1_negate(&proof.a.0);
+
+    let pairs: [(&[u8], &[u8]); 4] = [
+        (&neg_a, &proof.b.0),
+        (&verification_key.alpha_g1.0, &verification_key.beta_g2.0),
+        (&vk_x, &verification_key.gamma_g2.0),
+        (&proof.c.0, &verification_key.delta_g2.0),
+    ];
+    let mut pairing = Vec::with_capacity(pairs.len() * (G1_LEN + G2_LEN));
+    for (g1, g2) in pairs {
+        pairing.extend_from_slice(g1);
+        pairing.extend_from_slice(g2);
+    }
+    pairing_check(&pairing)
+}
+
+fn g1_multiexp(input: &[u8]) -> Vec<u8> {
+    unsafe { sys::alt_bn128_g1_multiexp(input.len() as _, input.as_ptr() as _, REGISTER) };
+    env::read_register(REGISTER).unwrap_or_else(|| env::abort())
+}
+
+fn g1_negate(point: &[u8]) -> Vec<u8> {
+    // A single (sign, point) term where a sign of 1 negates
+    let mut input = Vec::with_capacity(1 + G1_LEN);
+    input.push(1);
+    input.extend_from_slice(point);
+    unsafe { sys::alt_bn128_g1_sum(input.len() as _, input.as_ptr() as _, REGISTER) };
+    env::read_register(REGISTER).unwrap_or_else(|| env::abort())
+}
+
+fn pairing_check(input: &[u8]) -> bool {
+    unsafe { sys::alt_bn128_pairing_check(input.len() as _, input.as_ptr() as _) == 1 }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, AccountId};
+
+    use super::*;
+
+    const G1: &str = "0100000000000000000000000000000000000000000000000000000000000000\
+                      0200000000000000000000000000000000000000000000000000000000000000";
+    const G2: &str = "edf692d95cbdde46ddda5ef7d422436779445c5e66006a42761e1f12efde0018\
+                      c212f3aeb785e49712e7a9353349aaf1255dfb31b7bf60723a480d9293938e19\
+                      aa7dfa6601cce64c7bd3430c69e7d1e38f40cb8d8071ab4aeb6d8cdba55ec812\
+                      5b9722d1dcdaac55f38eb37033314bbc95330c69ad999eec75f05f58d0890609";
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    fn from_hex(s: &str) -> Base64VecU8 {
+        let s: String = s.split_whitespace().collect();
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect::<Vec<u8>>()
+            .into()
+    }
+
+    fn zero_g1() -> Base64VecU8 {
+        vec![0; G1_LEN].into()
+    }
+
+    /// A degenerate key where every input point is the identity, so
+    /// `A = G1, B = G2, C = 0` satisfies the pairing equation for any inputs.
+    fn trivial_key(inputs: usize) -> VerificationKey {
+        VerificationKey {
+            alpha_g1: from_hex(G1),
+            beta_g2: from_hex(G2),
+            gamma_g2: from_hex(G2),
+            delta_g2: from_hex(G2),
+            ic: vec![zero_g1(); inputs + 1],
+        }
+    }
+
+    /// A key and proof from `scripts/groth16_fixture.py`, for the public inputs
+    /// of `bin/prover-monolith/fixtures/simple.json`
+    fn fixture() -> (VerificationKey, Groth16Proof) {
+        let read = |name: &str| std::fs::read_to_string(format!("fixtures/{}", name)).unwrap();
+        (
+            near_sdk::serde_json::from_str(&read("verification_key.json")).unwrap(),
+            near_sdk::serde_json::from_str(&read("proof.json")).unwrap(),
+        )
+    }
+
+    const FIXTURE_KEY: &str = "ed25519:Ho8a7vHSuGJpSgPuPBXNnz4njYZTBTAbruoTBdcAwvxp";
+
+    /// The ids of a proof of only the simple heuristic
+    fn simple() -> Vec<u8> {
+        vec![1, 0, 0, 0]
+    }
+
+    /// 2023-12-08 at noon
+    const NOON: u64 = 19_699 * NANOS_PER_DAY + NANOS_PER_DAY / 2;
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), 19700101);
+        assert_eq!(civil_date(19_699), 20231208);
+        assert_eq!(civil_date(11_016), 20000229);
+        assert_eq!(civil_date(11_017), 20000301);
+    }
+
+    #[test]
+    fn test_apply_public_inputs() {
+        let registrar_key = std::array::from_fn(|i| i as u8);
+        let inputs = apply_public_inputs(&[7; 32], 300, 2, 20231208, &registrar_key, &[1, 0, 0, 4]);
+        assert_eq!(inputs.len(), 43);
+        assert_eq!(inputs[0][0], 7);
+        assert!(inputs[0][1..].iter().all(|x| *x == 0));
+        assert_eq!(&inputs[32][..2], &[44, 1]);
+        assert_eq!(&inputs[33][..2], &[2, 0]);
+        assert_eq!(&inputs[34][..4], &20231208u32.to_le_bytes());
+        assert_eq!(&inputs[35][..2], &[15, 14]);
+        assert_eq!(inputs[35][15], 0);
+        assert!(inputs[35][16..].iter().all(|x| *x == 0));
+        assert_eq!(&inputs[38][..2], &[63, 62]);
+        assert_eq!(inputs[39][0], 1);
+        assert_eq!(inputs[42][0], 4);
+        assert!(inputs[42][1..].iter().all(|x| *x == 0));
+    }
+
+    #[test]
+    fn test_verify() {
+        let key = trivial_key(43);
+        let inputs = apply_public_inputs(&[7; 32], 300, 0, 20231208, &[0; 64], &[1, 0, 0, 0]);
+        let proof = Groth16Proof {
+            a: from_hex(G1),
+            b: from_hex(G2),
+            c: zero_g1(),
+        };
+        assert!(verify(&key, &proof, &inputs));
+
+        let forged = Groth16Proof {
+            c: from_hex(G1),
+            ..proof
+        };
+        assert!(!verify(&key, &forged, &inputs));
+    }
+
+    #[test]
+    fn test_verify_fixture() {
+        let (key, proof) = fixture();
+        let public_key: near_sdk::PublicKey = FIXTURE_KEY.parse().unwrap();
+        let public_key: [u8; 32] = public_key.as_bytes()[1..].try_into().unwrap();
+        let inputs = |amount, heuristics| {
+            apply_public_inputs(&public_key, amount, 0, 20231208, &[0; 64], &heuristics)
+        };
+        assert!(verify(&key, &proof, &inputs(100, [1, 0, 0, 0])));
+        assert!(!verify(&key, &proof, &inputs(101, [1, 0, 0, 0])));
+        assert!(!verify(&key, &proof, &inputs(100, [0, 0, 0, 0])));
+        let swapped = Groth16Proof {
+            a: proof.c.clone(),
+            c: proof.a.clone(),
+            ..proof
+        };
+        assert!(!verify(&key, &swapped, &inputs(100, [1, 0, 0, 0])));
+    }
+
+    #[test]
+    fn test_verify_malformed() {
+        let key = trivial_key(43);
+        let inputs = apply_public_inputs(&[7; 32], 300, 0, 20231208, &[0; 64], &[1, 0, 0, 0]);
+        let proof = Groth16Proof {
+            a: from_hex(G1),
+            b: from_hex(G1),
+            c: zero_g1(),
+        };
+        assert!(!verify(&key, &proof, &inputs));
+        assert!(!verify(&trivial_key(2), &proof, &inputs));
+    }
+
+    #[test]
+    fn test_verify_and_loan() {
+        let prover = accounts(1);
+        let user = accounts(2);
+        let mut contract = Contract::initialize(prover.clone(), None);
+        let deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(get_context(user.clone()).attached_deposit(deposit).build());
+        assert!(contract.register(user.clone()));
+        testing_env!(get_context(prover).build());
+        assert!(contract.register_verified(user.clone()));
+
+        let proof = Groth16Proof {
+            a: from_hex(G1),
+            b: from_hex(G2),
+            c: zero_g1(),
+        };
+
+        testing_env!(get_context(user.clone()).block_timestamp(NOON).build());
+        assert_eq!(
+            contract.verify_and_loan(U128(10), proof.clone(), 20231208, simple()),
+            Err(LoanError::NoVerificationKey)
+        );
+
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_verification_key(trivial_key(43));
+
+        testing_env!(get_context(user.clone()).block_timestamp(NOON).build());
+        assert_eq!(contract.get_nonce(user.clone()), U64(0));
+        assert_eq!(
+            contract.verify_and_loan(U128(10), proof.clone(), 20231208, simple()),
+            Ok(())
+        );
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(10)]));
+        assert_eq!(
+            contract.registered_loans.get(&user).unwrap()[0].circuit_version,
+            Some(trivial_key(43).version())
+        );
+        assert_eq!(contract.get_nonce(user.clone()), U64(1));
+    }
+
+    #[test]
+    fn test_verify_and_loan_fixture() {
+        let prover = accounts(1);
+        let user = accounts(2);
+        let mut contract = Contract::initialize(prover.clone(), None);
+        let deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(get_context(user.clone()).attached_deposit(deposit).build());
+        assert!(contract.register(user.clone()));
+
+        let (key, proof) = fixture();
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_verification_key(key.clone());
+        contract.set_required_heuristics(vec![1]);
+
+        let context = || {
+            get_context(user.clone())
+                .signer_account_pk(FIXTURE_KEY.parse().unwrap())
+                .block_timestamp(NOON)
+                .build()
+        };
+        testing_env!(context());
+        // A valid proof isn't enough without the prover verifying the registration
+        assert_eq!(
+            contract.verify_and_loan(U128(100), proof.clone(), 20231208, simple()),
+            Err(LoanError::NotVerified)
+        );
+        testing_env!(get_context(prover).build());
+        assert!(contract.register_verified(user.clone()));
+
+        testing_env!(context());
+        assert_eq!(
+            contract.verify_and_loan(U128(90), proof.clone(), 20231208, simple()),
+            Err(LoanError::InvalidProof)
+        );
+        assert_eq!(
+            contract.verify_and_loan(U128(100), proof.clone(), 20231208, simple()),
+            Ok(())
+        );
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(100)]));
+        assert_eq!(
+            contract.registered_loans.get(&user).unwrap()[0].circuit_version,
+            Some(key.version())
+        );
+        // Made for nonce 0
+        assert_eq!(
+            contract.verify_and_loan(U128(100), proof, 20231208, simple()),
+            Err(LoanError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn test_verify_and_loan_stale() {
+        let prover = accounts(1);
+        let user = accounts(2);
+        let mut contract = Contract::initialize(prover.clone(), None);
+        let deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(get_context(user.clone()).attached_deposit(deposit).build());
+        assert!(contract.register(user.clone()));
+        testing_env!(get_context(prover).build());
+        assert!(contract.register_verified(user.clone()));
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_verification_key(trivial_key(43));
+
+        let proof = Groth16Proof {
+            a: from_hex(G1),
+            b: from_hex(G2),
+            c: zero_g1(),
+        };
+        testing_env!(get_context(user.clone()).block_timestamp(NOON).build());
+        for date in [20231206, 20231209, 0] {
+            assert_eq!(
+                contract.verify_and_loan(U128(10), proof.clone(), date, simple()),
+                Err(LoanError::StaleProof)
+            );
+        }
+        // Made just before midnight
+        assert_eq!(
+            contract.verify_and_loan(U128(10), proof, 20231207, simple()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_verify_and_loan_registrar_key() {
+        let prover = accounts(1);
+        let user = accounts(2);
+        let mut contract = Contract::initialize(prover.clone(), None);
+        let deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(get_context(user.clone()).attached_deposit(deposit).build());
+        assert!(contract.register(user.clone()));
+        testing_env!(get_context(prover).build());
+        assert!(contract.register_verified(user.clone()));
+
+        // Only the first registrar half's input point is set, so the proof only
+        // verifies while that half is zero
+        let mut key = trivial_key(43);
+        key.ic[36] = from_hex(G1);
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_verification_key(key);
+        contract.set_registrar_key(vec![1; 64].into());
+        assert_eq!(contract.get_registrar_key(), Some(vec![1; 64].into()));
+
+        let proof = Groth16Proof {
+            a: from_hex(G1),
+            b: from_hex(G2),
+            c: zero_g1(),
+        };
+        testing_env!(get_context(user.clone()).block_timestamp(NOON).build());
+        assert_eq!(
+            contract.verify_and_loan(U128(10), proof, 20231208, simple()),
+            Err(LoanError::InvalidProof)
+        );
+    }
+
+    #[test]
+    fn test_verify_and_loan_required_heuristics() {
+        let prover = accounts(1);
+        let user = accounts(2);
+        let mut contract = Contract::initialize(prover.clone(), None);
+        let deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(get_context(user.clone()).attached_deposit(deposit).build());
+        assert!(contract.register(user.clone()));
+        testing_env!(get_context(prover).build());
+        assert!(contract.register_verified(user.clone()));
+
+        // Only the first heuristic slot's input point is set, so the proof only
+        // verifies while that slot is empty
+        let mut key = trivial_key(43);
+        key.ic[40] = from_hex(G1);
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_verification_key(key);
+        contract.set_required_heuristics(vec![1]);
+        assert_eq!(contract.get_required_heuristics(), vec![1]);
+
+        let proof = Groth16Proof {
+            a: from_hex(G1),
+            b: from_hex(G2),
+            c: zero_g1(),
+        };
+        testing_env!(get_context(user.clone()).block_timestamp(NOON).build());
+        assert_eq!(
+            contract.verify_and_loan(U128(10), proof.clone(), 20231208, vec![0, 0, 0, 4]),
+            Err(LoanError::MissingHeuristic { id: 1 })
+        );
+        assert_eq!(
+            contract.verify_and_loan(U128(10), proof.clone(), 20231208, vec![1]),
+            Err(LoanError::InvalidPublicInputs)
+        );
+        // Claiming the required id doesn't help when the proof isn't over it
+        assert_eq!(
+            contract.verify_and_loan(U128(10), proof, 20231208, vec![4, 1, 0, 0]),
+            Err(LoanError::InvalidProof)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Heuristic 0 is the empty slot")]
+    fn test_set_required_heuristics_noop() {
+        let mut contract = Contract::initialize(accounts(1), None);
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_required_heuristics(vec![1, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Malformed registrar key")]
+    fn test_set_registrar_key_malformed() {
+        let mut contract = Contract::initialize(accounts(1), None);
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_registrar_key(vec![1; 33].into());
+    }
+
+    #[test]
+    fn test_verify_and_loan_replayed() {
+        let prover = accounts(1);
+        let user = accounts(2);
+        let mut contract = Contract::initialize(prover.clone(), None);
+        let deposit = contract.storage_balance_bounds().min.0;
+        testing_env!(get_context(user.clone()).attached_deposit(deposit).build());
+        assert!(contract.register(user.clone()));
+        testing_env!(get_context(prover).build());
+        assert!(contract.register_verified(user.clone()));
+
+        // Only the nonce's input point is set, so the proof only verifies for nonce 0
+        let mut key = trivial_key(43);
+        key.ic[34] = from_hex(G1);
+        testing_env!(get_context(accounts(0)).build());
+        contract.set_verification_key(key);
+
+        testing_env!(get_context(user.clone()).block_timestamp(NOON).build());
+        let fresh = Groth16Proof {
+            a: from_hex(G1),
+            b: from_hex(G2),
+            c: zero_g1(),
+        };
+        assert_eq!(
+            contract.verify_and_loan(U128(10), fresh.clone(), 20231208, simple()),
+            Ok(())
+        );
+        // The nonce moved on, the same proof is refused
+        assert_eq!(
+            contract.verify_and_loan(U128(10), fresh, 20231208, simple()),
+            Err(LoanError::InvalidProof)
+        );
+        assert_eq!(contract.loan_amounts(&user), Some(vec![U128(10)]));
+    }
+}
@@ -0,0 +1,177 @@
+//! Read only views over registrations and loans, lists are paginated so they
+//! stay within the view gas limit as the lender grows.
+use crate::{Contract, ContractExt, Loan};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{near_bindgen, AccountId};
+
+/// The default page size when no limit is given
+const DEFAULT_LIMIT: u64 = 50;
+/// The largest page served, larger limits are clamped to it
+const MAX_LIMIT: u64 = 100;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Totals {
+    pub users: U64,
+    pub verified_users: U64,
+    pub loaned: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn is_registered(&self, user: AccountId) -> bool {
+        self.is_user_registered(&user)
+    }
+
+    pub fn is_verified(&self, user: AccountId) -> bool {
+        self.verified_users.contains(&user)
+    }
+
+    /// Whether `account` may register users other than itself, see `add_registrar`
+    pub fn is_registrar(&self, account: AccountId) -> bool {
+        self.registrars.contains(&account)
+    }
+
+    /// The outstanding loans of `user`, oldest first. `None` if the user isn't registered.
+    pub fn get_loans(
+        &self,
+        user: AccountId,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Option<Vec<Loan>> {
+        let (from_index, limit) = page(from_index, limit);
+        self.registered_loans
+            .get(&user)
+            .map(|loans| loans.into_iter().skip(from_index).take(limit).collect())
+    }
+
+    /// Registered users in insertion order, removals may reorder the tail.
+    pub fn get_users(&self, from_index: Option<U64>, limit: Option<U64>) -> Vec<AccountId> {
+        let (from_index, limit) = page(from_index, limit);
+        self.registered_loans
+            .keys()
+            .skip(from_index)
+            .take(limit)
+            .collect()
+    }
+
+    pub fn get_totals(&self) -> Totals {
+        Totals {
+            users: self.registered_loans.len().into(),
+            verified_users: self.verified_users.len().into(),
+            loaned: self.total_loaned.into(),
+        }
+    }
+}
+
+pub(crate) fn page(from_index: Option<U64>, limit: Option<U64>) -> (usize, usize) {
+    let from_index = from_index.map(|x| x.0).unwrap_or_default();
+    let limit = limit.map(|x| x.0).unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    (from_index as usize, limit as usize)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    fn setup() -> Contract {
+        let prover = accounts(1);
+        let mut contract = Contract::initialize(prover.clone(), None);
+        let deposit = contract.storage_balance_bounds().min.0;
+        for user in [accounts(2), accounts(3), accounts(4)] {
+            testing_env!(get_context(user.clone()).attached_deposit(deposit).build());
+            assert!(contract.register(user.clone()));
+            testing_env!(get_context(prover.clone()).build());
+            assert!(contract.register_verified(user));
+        }
+        contract
+    }
+
+    #[test]
+    fn test_get_users() {
+        let contract = setup();
+        assert_eq!(
+            contract.get_users(None, None),
+            vec![accounts(2), accounts(3), accounts(4)]
+        );
+        assert_eq!(
+            contract.get_users(Some(U64(1)), Some(U64(1))),
+            vec![accounts(3)]
+        );
+        assert!(contract.get_users(Some(U64(3)), None).is_empty());
+    }
+
+    #[test]
+    fn test_page() {
+        assert_eq!(page(None, None), (0, DEFAULT_LIMIT as usize));
+        assert_eq!(page(Some(U64(2)), Some(U64(3))), (2, 3));
+        assert_eq!(page(None, Some(U64(u64::MAX))), (0, MAX_LIMIT as usize));
+    }
+
+    #[test]
+    fn test_get_loans() {
+        let mut contract = setup();
+        for amount in [1, 2, 3] {
+            assert!(contract
+                .verified_loan(
+                    accounts(2),
+                    U128(amount),
+                    [amount as u8; 32].into(),
+                    None,
+                    None
+                )
+                .is_ok());
+        }
+
+        let amounts = |loans: Option<Vec<Loan>>| {
+            loans.map(|loans| loans.iter().map(|x| x.amount).collect::<Vec<_>>())
+        };
+        assert_eq!(
+            amounts(contract.get_loans(accounts(2), None, None)),
+            Some(vec![U128(1), U128(2), U128(3)])
+        );
+        assert_eq!(
+            amounts(contract.get_loans(accounts(2), Some(U64(1)), Some(U64(1)))),
+            Some(vec![U128(2)])
+        );
+        assert_eq!(contract.get_loans(accounts(3), None, None), Some(vec![]));
+        assert_eq!(contract.get_loans(accounts(5), None, None), None);
+    }
+
+    #[test]
+    fn test_get_totals() {
+        let mut contract = setup();
+        assert!(contract
+            .verified_loan(accounts(2), U128(5), [5; 32].into(), None, None)
+            .is_ok());
+        assert!(contract
+            .verified_loan(accounts(3), U128(7), [7; 32].into(), None, None)
+            .is_ok());
+
+        assert_eq!(
+            contract.get_totals(),
+            Totals {
+                users: U64(3),
+                verified_users: U64(3),
+                loaned: U128(12),
+            }
+        );
+        assert!(contract.is_registered(accounts(2)));
+        assert!(contract.is_verified(accounts(2)));
+        assert!(!contract.is_registered(accounts(5)));
+    }
+}
@@ -0,0 +1,204 @@
+//! Attestations that a document was verified, the registrar signs a
+//! commitment to the identity read from it bound to the holder's NEAR key.
+//! Holders keep the attestation and prove over it in the `apply` circuit,
+//! which checks the signature with its secp256k1 ECDSA verifier against the
+//! registrar key given as a public input. Verifiers check that key is theirs.
+//!
+//! The commitment is `sha256(salt || nationality || birth || expiry)`, the
+//! nationality as the big endian [`country_code`] and the dates as big endian
+//! `YYYYMMDD`. The signed message is `sha256(commitment || holder)`.
+use crate::identity::{country_code, Identity};
+use k256::ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier};
+use k256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AttestationError {
+    /// The secret isn't a secp256k1 scalar
+    InvalidKey,
+    Signing(String),
+}
+
+impl std::fmt::Display for AttestationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AttestationError::InvalidKey => write!(f, "Invalid secp256k1 attestation key"),
+            AttestationError::Signing(e) => write!(f, "Attestation signing failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AttestationError {}
+
+/// A registrar's word that the holder of a NEAR key showed a verified
+/// document with this identity
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attestation {
+    pub identity: Identity,
+    /// Blinds the commitment, so it reveals nothing without the identity
+    pub salt: [u8; 32],
+    /// The holder's ed25519 NEAR public key
+    pub holder: [u8; 32],
+    /// `r || s` with a low `s`, over [`Attestation::message`]
+    pub signature: [u8; 64],
+}
+
+impl Attestation {
+    pub fn commitment(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt);
+        hasher.update(country_code(&self.identity.nationality).to_be_bytes());
+        hasher.update(self.identity.birth_date.as_number().to_be_bytes());
+        hasher.update(self.identity.expiry_date.as_number().to_be_bytes());
+        hasher.finalize().into()
+    }
+
+    /// The digest signed, binding the commitment to the holder
+    pub fn message(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.commitment());
+        hasher.update(self.holder);
+        hasher.finalize().into()
+    }
+
+    /// Whether the registrar with the uncompressed `x || y` public key signed it
+    pub fn verify(&self, public_key: &[u8; 64]) -> bool {
+        let mut sec1 = [4u8; 65];
+        sec1[1..].copy_from_slice(public_key);
+        let key = match VerifyingKey::from_sec1_bytes(&sec1) {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
+        let signature = match Signature::from_slice(&self.signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+        // The circuit's verifier only takes low `s`
+        signature.normalize_s().is_none() && key.verify_prehash(&self.message(), &signature).is_ok()
+    }
+}
+
+/// Signs attestations with the registrar's secp256k1 key
+#[derive(Clone)]
+pub struct Attester {
+    key: SigningKey,
+}
+
+impl std::fmt::Debug for Attester {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Attester")
+            .field("public_key", &hex(&self.public_key()))
+            .finish()
+    }
+}
+
+impl Attester {
+    /// From the 32 byte secret scalar
+    pub fn new(secret: &[u8]) -> Result<Self, AttestationError> {
+        let key = SigningKey::from_slice(secret).map_err(|_| AttestationError::InvalidKey)?;
+        Ok(Self { key })
+    }
+
+    /// The uncompressed `x || y` public key, what the circuit verifies with
+    pub fn public_key(&self) -> [u8; 64] {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        let mut key = [0; 64];
+        key.copy_from_slice(&point.as_bytes()[1..]);
+        key
+    }
+
+    /// Attest `identity` for the `holder` NEAR key, `salt` must be random
+    pub fn attest(
+        &self,
+        identity: Identity,
+        holder: [u8; 32],
+        salt: [u8; 32],
+    ) -> Result<Attestation, AttestationError> {
+        let mut attestation = Attestation {
+            identity,
+            salt,
+            holder,
+            signature: [0; 64],
+        };
+        let signature: Signature = self
+            .key
+            .sign_prehash(&attestation.message())
+            .map_err(|e| AttestationError::Signing(e.to_string()))?;
+        let signature = signature.normalize_s().unwrap_or(signature);
+        attestation.signature.copy_from_slice(&signature.to_bytes());
+        Ok(attestation)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::Date;
+
+    fn attester() -> Attester {
+        let key = SigningKey::random(&mut k256::elliptic_curve::rand_core::OsRng);
+        Attester::new(&key.to_bytes()).unwrap()
+    }
+
+    fn identity() -> Identity {
+        Identity {
+            nationality: "UTO".to_string(),
+            birth_date: Date {
+                year: 1974,
+                month: 8,
+                day: 12,
+            },
+            expiry_date: Date {
+                year: 2012,
+                month: 4,
+                day: 15,
+            },
+        }
+    }
+
+    fn holder() -> [u8; 32] {
+        std::array::from_fn(|i| i as u8)
+    }
+
+    #[test]
+    fn test_attest() {
+        let other = attester();
+        let attester = attester();
+        let attestation = attester.attest(identity(), holder(), holder()).unwrap();
+        assert_eq!(
+            hex(&attestation.commitment()),
+            "f044151cea32260a406e7e7d9aad1d2034cf60599a45fcdea8636aa8bc99cc39"
+        );
+        assert_eq!(
+            hex(&attestation.message()),
+            "c49e6b3bfaca11de6c6ec79a241b61fe172f87bec5e1b0303e7f258990cfd75c"
+        );
+        assert!(attestation.verify(&attester.public_key()));
+
+        // Bound to the holder
+        let mut stolen = attestation.clone();
+        stolen.holder[0] ^= 1;
+        assert!(!stolen.verify(&attester.public_key()));
+        let mut older = attestation.clone();
+        older.identity.birth_date.year = 1970;
+        assert!(!older.verify(&attester.public_key()));
+        // Only verifies with the registrar's key
+        assert!(!attestation.verify(&other.public_key()));
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert_eq!(
+            Attester::new(&[0; 32]).unwrap_err(),
+            AttestationError::InvalidKey
+        );
+        assert_eq!(
+            Attester::new(&[1; 16]).unwrap_err(),
+            AttestationError::InvalidKey
+        );
+    }
+}
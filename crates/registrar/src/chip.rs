@@ -0,0 +1,849 @@
+//! Verifying an offline dump of an ePassport chip. The SOD carries the
+//! issuer's signature over the hashes of every data group, so a DG1 that
+//! matches the printed MRZ proves the document was issued rather than edited.
+use cms::{
+    cert::CertificateChoices,
+    content_info::ContentInfo,
+    signed_data::{SignedData, SignerIdentifier},
+};
+use const_oid::ObjectIdentifier;
+use der::{asn1::OctetString, Any, Decode, Encode, Sequence};
+use sha2::Digest as _;
+use spki::{AlgorithmIdentifierOwned, SubjectPublicKeyInfoOwned};
+use std::path::Path;
+use std::time::SystemTime;
+use x509_cert::{ext::pkix::SubjectKeyIdentifier, Certificate};
+
+/// The SOD is an application tagged CMS `SignedData`
+const SOD_TAG: &[u8] = &[0x77];
+const DG1_TAG: &[u8] = &[0x61];
+const MRZ_TAG: &[u8] = &[0x5F, 0x1F];
+
+/// `id-icao-mrtd-security-ldsSecurityObject`, the content type of every SOD
+const LDS_SECURITY_OBJECT: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.23.136.1.1.1");
+const CONTENT_TYPE: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.3");
+const MESSAGE_DIGEST: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.9.4");
+const SUBJECT_KEY_IDENTIFIER: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.5.29.14");
+const RSA_ENCRYPTION: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.1.1");
+const EC_PUBLIC_KEY: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.2.1");
+const SECP256R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.3.1.7");
+const SECP384R1: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.132.0.34");
+
+/// The files read from the chip, hashed as read
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChipDump {
+    /// The MRZ
+    pub dg1: Vec<u8>,
+    /// The facial image, only its hash is checked
+    pub dg2: Option<Vec<u8>>,
+    /// The document security object
+    pub sod: Vec<u8>,
+}
+
+impl ChipDump {
+    /// Read `DG1.bin`, `DG2.bin` and `SOD.bin` (or `EF.SOD`) from a directory
+    pub fn load(dir: &Path) -> Result<Self, ChipError> {
+        let read = |name: &str| std::fs::read(dir.join(name));
+        let io = |name: &str, e: std::io::Error| ChipError::Malformed(format!("{}: {}", name, e));
+        Ok(Self {
+            dg1: read("DG1.bin").map_err(|e| io("DG1.bin", e))?,
+            dg2: read("DG2.bin").ok(),
+            sod: read("SOD.bin")
+                .or_else(|_| read("EF.SOD"))
+                .map_err(|e| io("SOD.bin", e))?,
+        })
+    }
+
+    /// The MRZ stored in DG1, without line breaks
+    pub fn mrz(&self) -> Result<String, ChipError> {
+        let dg1 = tlv_value(&self.dg1, DG1_TAG)?;
+        let mrz = tlv_value(dg1, MRZ_TAG)?;
+        String::from_utf8(mrz.to_vec()).map_err(|e| ChipError::Malformed(e.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ChipError {
+    Malformed(String),
+    /// The signature or hash algorithm isn't one we verify
+    UnsupportedAlgorithm(String),
+    /// A data group doesn't match the hash the issuer signed
+    HashMismatch {
+        data_group: u8,
+    },
+    InvalidSignature(String),
+    /// No CSCA in the store issued the document signer
+    UntrustedSigner(String),
+    /// The document signer or CSCA isn't valid at the verification time
+    NotValid(String),
+    /// DG1 and the printed MRZ differ
+    MrzMismatch {
+        chip: String,
+        printed: String,
+    },
+}
+
+impl std::fmt::Display for ChipError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChipError::Malformed(e) => write!(f, "Malformed chip data: {}", e),
+            ChipError::UnsupportedAlgorithm(e) => write!(f, "Unsupported algorithm {}", e),
+            ChipError::HashMismatch { data_group } => {
+                write!(f, "DG{} doesn't match the signed hash", data_group)
+            }
+            ChipError::InvalidSignature(e) => write!(f, "Invalid signature: {}", e),
+            ChipError::UntrustedSigner(e) => write!(f, "No trusted CSCA issued {}", e),
+            ChipError::NotValid(e) => write!(f, "{} isn't valid at the verification time", e),
+            ChipError::MrzMismatch { chip, printed } => {
+                write!(f, "The chip MRZ {:?} isn't the printed {:?}", chip, printed)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChipError {}
+
+impl From<der::Error> for ChipError {
+    fn from(e: der::Error) -> Self {
+        ChipError::Malformed(e.to_string())
+    }
+}
+
+/// Country signing certificates, the roots of trust for document signers
+#[derive(Clone, Debug, Default)]
+pub struct CscaStore {
+    certificates: Vec<Certificate>,
+}
+
+impl CscaStore {
+    /// Load every `.der`, `.cer` or `.crt` DER certificate in a directory
+    pub fn load(dir: &Path) -> Result<Self, ChipError> {
+        let entries = std::fs::read_dir(dir).map_err(|e| ChipError::Malformed(e.to_string()))?;
+        let mut certificates = vec![];
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let der = matches!(
+                path.extension().and_then(|x| x.to_str()),
+                Some("der" | "cer" | "crt")
+            );
+            if der {
+                let bytes =
+                    std::fs::read(&path).map_err(|e| ChipError::Malformed(e.to_string()))?;
+                certificates.push(Certificate::from_der(&bytes)?);
+            }
+        }
+        Ok(Self { certificates })
+    }
+
+    pub fn new(certificates: Vec<Certificate>) -> Self {
+        Self { certificates }
+    }
+
+    pub fn len(&self) -> usize {
+        self.certificates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.certificates.is_empty()
+    }
+
+    /// The CSCA that signed `certificate`
+    fn issuer_of(&self, certificate: &Certificate) -> Result<&Certificate, ChipError> {
+        let tbs = &certificate.tbs_certificate;
+        let csca = self
+            .certificates
+            .iter()
+            .filter(|x| x.tbs_certificate.subject == tbs.issuer)
+            .find(|csca| {
+                verify_signature(
+                    &csca.tbs_certificate.subject_public_key_info,
+                    &certificate.signature_algorithm,
+                    None,
+                    &tbs.to_der().unwrap_or_default(),
+                    certificate.signature.raw_bytes(),
+                )
+                .is_ok()
+            });
+        csca.ok_or_else(|| ChipError::UntrustedSigner(tbs.subject.to_string()))
+    }
+}
+
+/// A chip whose data groups the issuer signed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChipVerification {
+    /// The MRZ in DG1
+    pub mrz: String,
+    /// The document signer
+    pub signer: String,
+    /// The CSCA that issued the document signer
+    pub csca: String,
+    /// The data groups checked against their signed hashes
+    pub data_groups: Vec<u8>,
+}
+
+/// `LDSSecurityObject`, the content the SOD signs
+#[derive(Clone, Debug, Sequence)]
+struct LdsSecurityObject {
+    version: u8,
+    hash_algorithm: AlgorithmIdentifierOwned,
+    data_group_hash_values: Vec<DataGroupHash>,
+    #[asn1(optional = "true")]
+    lds_version_info: Option<Any>,
+}
+
+#[derive(Clone, Debug, Sequence)]
+struct DataGroupHash {
+    data_group_number: u8,
+    data_group_hash_value: OctetString,
+}
+
+/// Verify the data groups against the SOD, its signature against the document
+/// signer and the document signer against the CSCA store
+pub fn verify(dump: &ChipDump, store: &CscaStore) -> Result<ChipVerification, ChipError> {
+    verify_at(dump, store, SystemTime::now())
+}
+
+/// [`verify`] with the document signer and CSCA checked as valid at `now`
+pub fn verify_at(
+    dump: &ChipDump,
+    store: &CscaStore,
+    now: SystemTime,
+) -> Result<ChipVerification, ChipError> {
+    let content_info = ContentInfo::from_der(tlv_value(&dump.sod, SOD_TAG)?)?;
+    let signed_data = SignedData::from_der(&content_info.content.to_der()?)?;
+
+    // The data group hashes
+    let content_type = signed_data.encap_content_info.econtent_type;
+    if content_type != LDS_SECURITY_OBJECT {
+        return Err(ChipError::Malformed(format!(
+            "The SOD content is a {}, not an LDS security object",
+            content_type
+        )));
+    }
+    let econtent = signed_data
+        .encap_content_info
+        .econtent
+        .as_ref()
+        .ok_or_else(|| ChipError::Malformed("The SOD has no content".into()))?;
+    let econtent = OctetString::from_der(&econtent.to_der()?)?;
+    let lds = LdsSecurityObject::from_der(econtent.as_bytes())?;
+    let digest = Digest::from_oid(&lds.hash_algorithm.oid)?;
+
+    let mut data_groups = vec![];
+    for (number, data) in [(1, Some(&dump.dg1)), (2, dump.dg2.as_ref())] {
+        let data = match data {
+            Some(data) => data,
+            None => continue,
+        };
+        let signed = lds
+            .data_group_hash_values
+            .iter()
+            .find(|x| x.data_group_number == number)
+            .ok_or_else(|| ChipError::Malformed(format!("The SOD has no hash for DG{}", number)))?;
+        if signed.data_group_hash_value.as_bytes() != digest.hash(data) {
+            return Err(ChipError::HashMismatch { data_group: number });
+        }
+        data_groups.push(number);
+    }
+
+    // The document signer's signature over the signed attributes
+    let signer_info = signed_data
+        .signer_infos
+        .0
+        .iter()
+        .next()
+        .ok_or_else(|| ChipError::Malformed("The SOD has no signer".into()))?;
+    let dsc = signed_data
+        .certificates
+        .as_ref()
+        .and_then(|x| {
+            x.0.iter().find_map(|x| match x {
+                CertificateChoices::Certificate(x) if identifies(&signer_info.sid, x) => Some(x),
+                _ => None,
+            })
+        })
+        .ok_or_else(|| ChipError::Malformed("The SOD has no document signer".into()))?;
+    let signer_digest = Digest::from_oid(&signer_info.digest_alg.oid)?;
+    let signed_attrs = signer_info
+        .signed_attrs
+        .as_ref()
+        .ok_or_else(|| ChipError::Malformed("The SOD has no signed attributes".into()))?;
+    let signed_attr = |oid| {
+        signed_attrs
+            .iter()
+            .find(|x| x.oid == oid)
+            .and_then(|x| x.values.iter().next())
+            .ok_or_else(|| ChipError::Malformed(format!("The SOD has no signed {}", oid)))
+    };
+    if signed_attr(CONTENT_TYPE)?.decode_as::<ObjectIdentifier>()? != content_type {
+        return Err(ChipError::Malformed(
+            "The signed content type isn't the SOD's".into(),
+        ));
+    }
+    let message_digest = OctetString::from_der(&signed_attr(MESSAGE_DIGEST)?.to_der()?)?;
+    if message_digest.as_bytes() != signer_digest.hash(econtent.as_bytes()) {
+        return Err(ChipError::InvalidSignature(
+            "The message digest isn't the hash of the content".into(),
+        ));
+    }
+    verify_signature(
+        &dsc.tbs_certificate.subject_public_key_info,
+        &signer_info.signature_algorithm,
+        Some(signer_digest),
+        &signed_attrs.to_der()?,
+        signer_info.signature.as_bytes(),
+    )?;
+
+    let csca = store.issuer_of(dsc)?;
+    check_validity(dsc, now)?;
+    check_validity(csca, now)?;
+    Ok(ChipVerification {
+        mrz: dump.mrz()?,
+        signer: dsc.tbs_certificate.subject.to_string(),
+        csca: csca.tbs_certificate.subject.to_string(),
+        data_groups,
+    })
+}
+
+/// Whether `sid` names `certificate`, by issuer and serial or by subject key
+fn identifies(sid: &SignerIdentifier, certificate: &Certificate) -> bool {
+    let tbs = &certificate.tbs_certificate;
+    match sid {
+        SignerIdentifier::IssuerAndSerialNumber(sid) => {
+            tbs.issuer == sid.issuer && tbs.serial_number == sid.serial_number
+        }
+        SignerIdentifier::SubjectKeyIdentifier(sid) => tbs
+            .extensions
+            .iter()
+            .flatten()
+            .filter(|x| x.extn_id == SUBJECT_KEY_IDENTIFIER)
+            .any(|x| SubjectKeyIdentifier::from_der(x.extn_value.as_bytes()).as_ref() == Ok(sid)),
+    }
+}
+
+fn check_validity(certificate: &Certificate, now: SystemTime) -> Result<(), ChipError> {
+    let tbs = &certificate.tbs_certificate;
+    let validity = &tbs.validity;
+    if now < validity.not_before.to_system_time() || now > validity.not_after.to_system_time() {
+        return Err(ChipError::NotValid(tbs.subject.to_string()));
+    }
+    Ok(())
+}
+
+/// The hash algorithms ICAO 9303 allows
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Digest {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Digest {
+    fn from_oid(oid: &ObjectIdentifier) -> Result<Self, ChipError> {
+        match oid.to_string().as_str() {
+            "1.3.14.3.2.26" => Ok(Digest::Sha1),
+            "2.16.840.1.101.3.4.2.1" => Ok(Digest::Sha256),
+            "2.16.840.1.101.3.4.2.2" => Ok(Digest::Sha384),
+            "2.16.840.1.101.3.4.2.3" => Ok(Digest::Sha512),
+            oid => Err(ChipError::UnsupportedAlgorithm(oid.to_string())),
+        }
+    }
+
+    /// The digest of a `<hash>WithRSAEncryption` or `ecdsa-with-<hash>` signature
+    fn from_signature_oid(oid: &ObjectIdentifier) -> Option<Self> {
+        match oid.to_string().as_str() {
+            "1.2.840.113549.1.1.5" | "1.2.840.10045.4.1" => Some(Digest::Sha1),
+            "1.2.840.113549.1.1.11" | "1.2.840.10045.4.3.2" => Some(Digest::Sha256),
+            "1.2.840.113549.1.1.12" | "1.2.840.10045.4.3.3" => Some(Digest::Sha384),
+            "1.2.840.113549.1.1.13" | "1.2.840.10045.4.3.4" => Some(Digest::Sha512),
+            _ => None,
+        }
+    }
+
+    fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Digest::Sha1 => sha1::Sha1::digest(data).to_vec(),
+            Digest::Sha256 => sha2::Sha256::digest(data).to_vec(),
+            Digest::Sha384 => sha2::Sha384::digest(data).to_vec(),
+            Digest::Sha512 => sha2::Sha512::digest(data).to_vec(),
+        }
+    }
+
+    fn pkcs1v15(&self) -> rsa::Pkcs1v15Sign {
+        match self {
+            Digest::Sha1 => rsa::Pkcs1v15Sign::new::<sha1::Sha1>(),
+            Digest::Sha256 => rsa::Pkcs1v15Sign::new::<sha2::Sha256>(),
+            Digest::Sha384 => rsa::Pkcs1v15Sign::new::<sha2::Sha384>(),
+            Digest::Sha512 => rsa::Pkcs1v15Sign::new::<sha2::Sha512>(),
+        }
+    }
+}
+
+/// Verify an RSA PKCS#1 v1.5 or ECDSA P-256/P-384 signature. A bare
+/// `rsaEncryption` algorithm takes its digest from `digest`.
+fn verify_signature(
+    key: &SubjectPublicKeyInfoOwned,
+    algorithm: &AlgorithmIdentifierOwned,
+    digest: Option<Digest>,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), ChipError> {
+    use p256::ecdsa::signature::hazmat::PrehashVerifier;
+
+    let unsupported = || ChipError::UnsupportedAlgorithm(algorithm.oid.to_string());
+    let digest = match Digest::from_signature_oid(&algorithm.oid) {
+        Some(digest) => digest,
+        None if algorithm.oid == RSA_ENCRYPTION => digest.ok_or_else(unsupported)?,
+        None => return Err(unsupported()),
+    };
+    let prehash = digest.hash(message);
+    let invalid = |e: &dyn std::fmt::Display| ChipError::InvalidSignature(e.to_string());
+    let key_bytes = key.subject_public_key.raw_bytes();
+
+    if key.algorithm.oid == RSA_ENCRYPTION {
+        use rsa::pkcs1::DecodeRsaPublicKey;
+        let key = rsa::RsaPublicKey::from_pkcs1_der(key_bytes).map_err(|e| invalid(&e))?;
+        return key
+            .verify(digest.pkcs1v15(), &prehash, signature)
+            .map_err(|e| invalid(&e));
+    }
+    if key.algorithm.oid != EC_PUBLIC_KEY {
+        return Err(ChipError::UnsupportedAlgorithm(
+            key.algorithm.oid.to_string(),
+        ));
+    }
+
+    let curve = key
+        .algorithm
+        .parameters
+        .as_ref()
+        .and_then(|x| x.decode_as::<ObjectIdentifier>().ok())
+        .ok_or_else(|| ChipError::Malformed("An EC key without a named curve".into()))?;
+    if curve == SECP256R1 {
+        let key = p256::ecdsa::VerifyingKey::from_sec1_bytes(key_bytes).map_err(|e| invalid(&e))?;
+        let signature = p256::ecdsa::Signature::from_der(signature).map_err(|e| invalid(&e))?;
+        key.verify_prehash(&prehash, &signature)
+            .map_err(|e| invalid(&e))
+    } else if curve == SECP384R1 {
+        let key = p384::ecdsa::VerifyingKey::from_sec1_bytes(key_bytes).map_err(|e| invalid(&e))?;
+        let signature = p384::ecdsa::Signature::from_der(signature).map_err(|e| invalid(&e))?;
+        key.verify_prehash(&prehash, &signature)
+            .map_err(|e| invalid(&e))
+    } else {
+        // Brainpool curves are common on European passports
+        Err(ChipError::UnsupportedAlgorithm(curve.to_string()))
+    }
+}
+
+/// The value of the TLV starting with `tag`, trailing bytes are ignored
+fn tlv_value<'a>(data: &'a [u8], tag: &[u8]) -> Result<&'a [u8], ChipError> {
+    let (header, length) = tlv_header(data, tag)?;
+    Ok(&data[header..header + length])
+}
+
+/// The header and value length of a BER TLV
+fn tlv_header(data: &[u8], tag: &[u8]) -> Result<(usize, usize), ChipError> {
+    let malformed = || ChipError::Malformed(format!("Expected a {:02X?} TLV", tag));
+    let rest = data.strip_prefix(tag).ok_or_else(malformed)?;
+    let (length, size) = match rest.first() {
+        Some(&x) if x < 0x80 => (x as usize, 1),
+        Some(&x) if (0x81..=0x83).contains(&x) => {
+            let bytes = (x - 0x80) as usize;
+            let length = rest
+                .get(1..=bytes)
+                .ok_or_else(malformed)?
+                .iter()
+                .fold(0, |length, byte| length << 8 | *byte as usize);
+            (length, 1 + bytes)
+        }
+        _ => return Err(malformed()),
+    };
+    let header = tag.len() + size;
+    if data.len() < header + length {
+        return Err(malformed());
+    }
+    Ok((header, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cms::{
+        cert::{CertificateChoices, IssuerAndSerialNumber},
+        content_info::CmsVersion,
+        signed_data::{CertificateSet, EncapsulatedContentInfo, SignerInfo, SignerInfos},
+    };
+    use der::asn1::{BitString, SetOfVec, UtcTime};
+    use p256::ecdsa::{signature::Signer, DerSignature, SigningKey};
+    use std::str::FromStr;
+    use std::time::{Duration, UNIX_EPOCH};
+    use x509_cert::{
+        attr::Attribute,
+        name::Name,
+        serial_number::SerialNumber,
+        time::{Time, Validity},
+        TbsCertificate, Version,
+    };
+
+    const SIGNED_DATA: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.2");
+    const SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+    const ECDSA_WITH_SHA256: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.2.840.10045.4.3.2");
+    const YEAR: u64 = 365 * 24 * 60 * 60;
+
+    const MRZ: &str =
+        "P<UTOERIKSSON<<ANNA<MARIA<<<<<<<<<<<<<<<<<<<L898902C36UTO7408122F1204159ZE184226B<<<<<10";
+
+    fn dg1() -> Vec<u8> {
+        let mut dg1 = vec![0x61, 0x5B, 0x5F, 0x1F, 0x58];
+        dg1.extend_from_slice(MRZ.as_bytes());
+        dg1
+    }
+
+    #[test]
+    fn test_dg1_mrz() {
+        let dump = ChipDump {
+            dg1: dg1(),
+            ..Default::default()
+        };
+        assert_eq!(dump.mrz().unwrap(), MRZ);
+
+        let truncated = ChipDump {
+            dg1: dg1()[..40].to_vec(),
+            ..Default::default()
+        };
+        assert!(matches!(truncated.mrz(), Err(ChipError::Malformed(_))));
+    }
+
+    #[test]
+    fn test_tlv_long_length() {
+        let mut sod = vec![0x77, 0x82, 0x01, 0x00];
+        sod.extend(vec![0; 0x100]);
+        sod.push(0xFF);
+        assert_eq!(tlv_value(&sod, SOD_TAG).unwrap().len(), 0x100);
+        assert!(tlv_value(&sod[..100], SOD_TAG).is_err());
+        assert!(tlv_value(&sod, DG1_TAG).is_err());
+    }
+
+    #[test]
+    fn test_digest() {
+        let sha256 = ObjectIdentifier::new_unwrap("2.16.840.1.101.3.4.2.1");
+        assert_eq!(Digest::from_oid(&sha256), Ok(Digest::Sha256));
+        assert_eq!(Digest::Sha256.hash(b"").len(), 32);
+        assert!(Digest::from_oid(&SECP256R1).is_err());
+    }
+
+    fn at(year: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs((year - 1970) * YEAR)
+    }
+
+    fn algorithm(oid: ObjectIdentifier) -> AlgorithmIdentifierOwned {
+        AlgorithmIdentifierOwned {
+            oid,
+            parameters: None,
+        }
+    }
+
+    /// A P-256 certificate for `key` valid `from` to `until`, signed by `issuer`
+    fn certificate(
+        serial: u8,
+        subject: &str,
+        key: &SigningKey,
+        (issuer, issuer_key): (&str, &SigningKey),
+        (from, until): (u64, u64),
+    ) -> Certificate {
+        let time = |year| UtcTime::from_system_time(at(year)).map(Time::UtcTime);
+        let point = key.verifying_key().to_encoded_point(false);
+        let tbs_certificate = TbsCertificate {
+            version: Version::V3,
+            serial_number: SerialNumber::new(&[serial]).unwrap(),
+            signature: algorithm(ECDSA_WITH_SHA256),
+            issuer: Name::from_str(issuer).unwrap(),
+            validity: Validity {
+                not_before: time(from).unwrap(),
+                not_after: time(until).unwrap(),
+            },
+            subject: Name::from_str(subject).unwrap(),
+            subject_public_key_info: SubjectPublicKeyInfoOwned {
+                algorithm: AlgorithmIdentifierOwned {
+                    oid: EC_PUBLIC_KEY,
+                    parameters: Some(Any::from(&SECP256R1)),
+                },
+                subject_public_key: BitString::from_bytes(point.as_bytes()).unwrap(),
+            },
+            issuer_unique_id: None,
+            subject_unique_id: None,
+            extensions: None,
+        };
+        let signature: DerSignature = issuer_key.sign(&tbs_certificate.to_der().unwrap());
+        Certificate {
+            tbs_certificate,
+            signature_algorithm: algorithm(ECDSA_WITH_SHA256),
+            signature: BitString::from_bytes(signature.as_bytes()).unwrap(),
+        }
+    }
+
+    /// An SOD over the hashes of `dump`'s data groups, signed with `key` and
+    /// carrying `certificates`, the first of which is named the signer
+    fn sod(
+        dump: &ChipDump,
+        content_type: ObjectIdentifier,
+        certificates: &[&Certificate],
+        key: &SigningKey,
+    ) -> Vec<u8> {
+        let hash = |number, data: &[u8]| DataGroupHash {
+            data_group_number: number,
+            data_group_hash_value: OctetString::new(Digest::Sha256.hash(data)).unwrap(),
+        };
+        let mut hashes = vec![hash(1, &dump.dg1)];
+        hashes.extend(dump.dg2.as_ref().map(|x| hash(2, x)));
+        let lds = LdsSecurityObject {
+            version: 0,
+            hash_algorithm: algorithm(SHA256),
+            data_group_hash_values: hashes,
+            lds_version_info: None,
+        }
+        .to_der()
+        .unwrap();
+
+        let attribute = |oid, value| Attribute {
+            oid,
+            values: SetOfVec::try_from(vec![value]).unwrap(),
+        };
+        let digest = OctetString::new(Digest::Sha256.hash(&lds)).unwrap();
+        let signed_attrs = SetOfVec::try_from(vec![
+            attribute(CONTENT_TYPE, Any::from(&content_type)),
+            attribute(MESSAGE_DIGEST, Any::encode_from(&digest).unwrap()),
+        ])
+        .unwrap();
+        let signature: DerSignature = key.sign(&signed_attrs.to_der().unwrap());
+
+        let signer = &certificates[0].tbs_certificate;
+        let signer_info = SignerInfo {
+            version: CmsVersion::V1,
+            sid: SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+                issuer: signer.issuer.clone(),
+                serial_number: signer.serial_number.clone(),
+            }),
+            digest_alg: algorithm(SHA256),
+            signed_attrs: Some(signed_attrs),
+            signature_algorithm: algorithm(ECDSA_WITH_SHA256),
+            signature: OctetString::new(signature.as_bytes()).unwrap(),
+            unsigned_attrs: None,
+        };
+        let certificates = certificates
+            .iter()
+            .map(|x| CertificateChoices::Certificate((*x).clone()))
+            .collect::<Vec<_>>();
+        let signed_data = SignedData {
+            version: CmsVersion::V3,
+            digest_algorithms: SetOfVec::try_from(vec![algorithm(SHA256)]).unwrap(),
+            encap_content_info: EncapsulatedContentInfo {
+                econtent_type: content_type,
+                econtent: Some(Any::encode_from(&OctetString::new(lds).unwrap()).unwrap()),
+            },
+            certificates: Some(CertificateSet(SetOfVec::try_from(certificates).unwrap())),
+            crls: None,
+            signer_infos: SignerInfos(SetOfVec::try_from(vec![signer_info]).unwrap()),
+        };
+        let content_info = ContentInfo {
+            content_type: SIGNED_DATA,
+            content: Any::encode_from(&signed_data).unwrap(),
+        }
+        .to_der()
+        .unwrap();
+
+        let length = content_info.len() as u16;
+        let mut sod = vec![0x77, 0x82];
+        sod.extend(length.to_be_bytes());
+        sod.extend(content_info);
+        sod
+    }
+
+    /// A CSCA valid 2020 to 2040, a document signer it issued valid 2023 to
+    /// 2033 and a dump the document signer signed
+    struct Fixture {
+        csca: Certificate,
+        dsc: Certificate,
+        dsc_key: SigningKey,
+        dump: ChipDump,
+    }
+
+    const CSCA: &str = "CN=Utopia CSCA,C=UT";
+    const DSC: &str = "CN=Utopia DS 1,C=UT";
+
+    impl Fixture {
+        fn new() -> Self {
+            let csca_key = SigningKey::from_bytes(&[1; 32].into()).unwrap();
+            let dsc_key = SigningKey::from_bytes(&[2; 32].into()).unwrap();
+            let csca = certificate(1, CSCA, &csca_key, (CSCA, &csca_key), (2020, 2040));
+            let dsc = certificate(2, DSC, &dsc_key, (CSCA, &csca_key), (2023, 2033));
+            let mut dump = ChipDump {
+                dg1: dg1(),
+                dg2: Some(b"facial image".to_vec()),
+                sod: vec![],
+            };
+            dump.sod = sod(&dump, LDS_SECURITY_OBJECT, &[&dsc], &dsc_key);
+            Self {
+                csca,
+                dsc,
+                dsc_key,
+                dump,
+            }
+        }
+
+        fn store(&self) -> CscaStore {
+            CscaStore::new(vec![self.csca.clone()])
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let fixture = Fixture::new();
+        let verified = verify_at(&fixture.dump, &fixture.store(), at(2024)).unwrap();
+        assert_eq!(verified.mrz, MRZ);
+        assert_eq!(verified.signer, DSC);
+        assert_eq!(verified.csca, CSCA);
+        assert_eq!(verified.data_groups, vec![1, 2]);
+
+        // DG2 is optional
+        let dump = ChipDump {
+            dg2: None,
+            ..fixture.dump.clone()
+        };
+        let verified = verify_at(&dump, &fixture.store(), at(2024)).unwrap();
+        assert_eq!(verified.data_groups, vec![1]);
+
+        assert!(matches!(
+            verify_at(&fixture.dump, &CscaStore::default(), at(2024)),
+            Err(ChipError::UntrustedSigner(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_hash_mismatch() {
+        let fixture = Fixture::new();
+        let mut dump = fixture.dump.clone();
+        dump.dg1[10] = b'X';
+        assert_eq!(
+            verify_at(&dump, &fixture.store(), at(2024)),
+            Err(ChipError::HashMismatch { data_group: 1 })
+        );
+
+        let dump = ChipDump {
+            dg2: Some(b"another face".to_vec()),
+            ..fixture.dump.clone()
+        };
+        assert_eq!(
+            verify_at(&dump, &fixture.store(), at(2024)),
+            Err(ChipError::HashMismatch { data_group: 2 })
+        );
+    }
+
+    #[test]
+    fn test_verify_bad_signature() {
+        let fixture = Fixture::new();
+        let forger = SigningKey::from_bytes(&[3; 32].into()).unwrap();
+        let dump = ChipDump {
+            sod: sod(&fixture.dump, LDS_SECURITY_OBJECT, &[&fixture.dsc], &forger),
+            ..fixture.dump.clone()
+        };
+        assert!(matches!(
+            verify_at(&dump, &fixture.store(), at(2024)),
+            Err(ChipError::InvalidSignature(_))
+        ));
+
+        // A document signer the CSCA didn't issue
+        let csca_key = SigningKey::from_bytes(&[4; 32].into()).unwrap();
+        let dsc = certificate(2, DSC, &forger, (CSCA, &csca_key), (2023, 2033));
+        let dump = ChipDump {
+            sod: sod(&fixture.dump, LDS_SECURITY_OBJECT, &[&dsc], &forger),
+            ..fixture.dump.clone()
+        };
+        assert!(matches!(
+            verify_at(&dump, &fixture.store(), at(2024)),
+            Err(ChipError::UntrustedSigner(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_validity() {
+        let fixture = Fixture::new();
+        let store = fixture.store();
+        let not_valid = Err(ChipError::NotValid(DSC.into()));
+        assert_eq!(verify_at(&fixture.dump, &store, at(2022)), not_valid);
+        assert_eq!(verify_at(&fixture.dump, &store, at(2035)), not_valid);
+
+        // A document signer outliving its CSCA
+        let csca_key = SigningKey::from_bytes(&[1; 32].into()).unwrap();
+        let csca = certificate(1, CSCA, &csca_key, (CSCA, &csca_key), (2020, 2030));
+        assert_eq!(
+            verify_at(&fixture.dump, &CscaStore::new(vec![csca]), at(2031)),
+            Err(ChipError::NotValid(CSCA.into()))
+        );
+    }
+
+    #[test]
+    fn test_verify_signer_by_sid() {
+        let fixture = Fixture::new();
+        // The SOD names the second certificate, not the first
+        let other_key = SigningKey::from_bytes(&[5; 32].into()).unwrap();
+        let other = certificate(
+            3,
+            "CN=Utopia DS 2,C=UT",
+            &other_key,
+            (CSCA, &other_key),
+            (2023, 2033),
+        );
+        let dump = ChipDump {
+            sod: sod(
+                &fixture.dump,
+                LDS_SECURITY_OBJECT,
+                &[&fixture.dsc, &other],
+                &fixture.dsc_key,
+            ),
+            ..fixture.dump.clone()
+        };
+        let verified = verify_at(&dump, &fixture.store(), at(2024)).unwrap();
+        assert_eq!(verified.signer, DSC);
+
+        // Signed by a certificate the SOD doesn't carry
+        let dump = ChipDump {
+            sod: sod(
+                &fixture.dump,
+                LDS_SECURITY_OBJECT,
+                &[&other],
+                &fixture.dsc_key,
+            ),
+            ..fixture.dump.clone()
+        };
+        assert!(verify_at(&dump, &fixture.store(), at(2024)).is_err());
+    }
+
+    #[test]
+    fn test_verify_content_type() {
+        let fixture = Fixture::new();
+        let data = ObjectIdentifier::new_unwrap("1.2.840.113549.1.7.1");
+        let dump = ChipDump {
+            sod: sod(&fixture.dump, data, &[&fixture.dsc], &fixture.dsc_key),
+            ..fixture.dump.clone()
+        };
+        assert!(matches!(
+            verify_at(&dump, &fixture.store(), at(2024)),
+            Err(ChipError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_unsigned_sod() {
+        let dump = ChipDump {
+            dg1: dg1(),
+            dg2: None,
+            sod: vec![0x77, 0x03, 0x30, 0x01, 0x00],
+        };
+        assert!(matches!(
+            verify(&dump, &CscaStore::default()),
+            Err(ChipError::Malformed(_))
+        ));
+    }
+}
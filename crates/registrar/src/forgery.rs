@@ -0,0 +1,224 @@
+//! Which forged regions matter, a tampered MRZ or portrait fails extraction
+//! while small artifacts elsewhere on the document are tolerated.
+
+/// A rectangle of the image in pixels, `end` is exclusive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Region {
+    pub start: (u32, u32),
+    pub end: (u32, u32),
+}
+
+impl Region {
+    pub fn area(&self) -> u64 {
+        let width = self.end.0.saturating_sub(self.start.0) as u64;
+        let height = self.end.1.saturating_sub(self.start.1) as u64;
+        width * height
+    }
+
+    pub fn intersects(&self, other: &Region) -> bool {
+        self.start.0 < other.end.0
+            && other.start.0 < self.end.0
+            && self.start.1 < other.end.1
+            && other.start.1 < self.end.1
+    }
+
+    /// The smallest region covering both
+    pub fn union(&self, other: &Region) -> Region {
+        Region {
+            start: (
+                self.start.0.min(other.start.0),
+                self.start.1.min(other.start.1),
+            ),
+            end: (self.end.0.max(other.end.0), self.end.1.max(other.end.1)),
+        }
+    }
+}
+
+/// The zones of a document a forger is after
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Zone {
+    Mrz,
+    Photo,
+}
+
+/// Where the zones are on an image of a document
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Zones(pub Vec<(Zone, Region)>);
+
+impl Zones {
+    /// The MRZ as read by the OCR, the portrait is left of the visual zone
+    /// above it on ICAO data pages. Without an MRZ nothing is known.
+    pub fn from_mrz(mrz: Option<Region>) -> Self {
+        let mrz = match mrz {
+            Some(mrz) => mrz,
+            None => return Self::default(),
+        };
+        let width = mrz.end.0 - mrz.start.0;
+        let photo = Region {
+            start: (mrz.start.0, mrz.start.1 / 5),
+            end: (mrz.start.0 + width * 35 / 100, mrz.start.1),
+        };
+        Self(vec![(Zone::Mrz, mrz), (Zone::Photo, photo)])
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SuspiciousRegion {
+    pub region: Region,
+    /// The share of the document covered, 0 to 1
+    pub relative_area: f32,
+    /// The zones the region overlaps
+    pub zones: Vec<Zone>,
+}
+
+/// How the image was checked for forgeries
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ForgeryCheck {
+    /// Misaligned JPEG compression grids, any image that was ever saved as a
+    /// JPEG carries one, whatever format it's submitted in
+    #[default]
+    JpegGrid,
+    /// No check suits the input, nothing was flagged because nothing was checked
+    NotApplicable { reason: String },
+}
+
+/// The regions the forgery detection flagged
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ForgeryReport {
+    pub check: ForgeryCheck,
+    pub regions: Vec<SuspiciousRegion>,
+}
+
+/// When a report fails extraction
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForgeryPolicy {
+    /// Any suspicious region over these zones fails
+    pub critical_zones: Vec<Zone>,
+    /// Elsewhere, suspicious regions may cover this share of the document
+    pub max_relative_area: f32,
+    /// Refuse inputs no forgery check applies to, such as images that were
+    /// never compressed as a JPEG
+    pub require_check: bool,
+}
+
+impl Default for ForgeryPolicy {
+    fn default() -> Self {
+        Self {
+            critical_zones: vec![Zone::Mrz, Zone::Photo],
+            max_relative_area: 0.05,
+            require_check: true,
+        }
+    }
+}
+
+impl ForgeryReport {
+    pub fn new(regions: &[Region], size: (u32, u32), zones: &Zones) -> Self {
+        let total = (size.0 as u64 * size.1 as u64).max(1) as f32;
+        let regions = regions
+            .iter()
+            .map(|region| SuspiciousRegion {
+                region: *region,
+                relative_area: region.area() as f32 / total,
+                zones: zones
+                    .0
+                    .iter()
+                    .filter(|(_, zone)| region.intersects(zone))
+                    .map(|(x, _)| *x)
+                    .collect(),
+            })
+            .collect();
+        Self {
+            check: ForgeryCheck::JpegGrid,
+            regions,
+        }
+    }
+
+    pub fn not_applicable(reason: impl Into<String>) -> Self {
+        Self {
+            check: ForgeryCheck::NotApplicable {
+                reason: reason.into(),
+            },
+            regions: vec![],
+        }
+    }
+
+    pub fn is_checked(&self) -> bool {
+        !matches!(self.check, ForgeryCheck::NotApplicable { .. })
+    }
+
+    pub fn relative_area(&self) -> f32 {
+        self.regions.iter().map(|x| x.relative_area).sum()
+    }
+
+    /// Whether the document is forged according to `policy`, an unchecked
+    /// document is when the policy requires a check
+    pub fn is_forged(&self, policy: &ForgeryPolicy) -> bool {
+        if !self.is_checked() {
+            return policy.require_check;
+        }
+        let critical = self.regions.iter().any(|region| {
+            region
+                .zones
+                .iter()
+                .any(|x| policy.critical_zones.contains(x))
+        });
+        critical || self.relative_area() > policy.max_relative_area
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(start: (u32, u32), end: (u32, u32)) -> Region {
+        Region { start, end }
+    }
+
+    #[test]
+    fn test_region() {
+        let a = region((0, 0), (10, 10));
+        assert_eq!(a.area(), 100);
+        assert!(a.intersects(&region((5, 5), (20, 20))));
+        assert!(!a.intersects(&region((10, 0), (20, 10))));
+        assert_eq!(a.union(&region((5, 5), (20, 20))), region((0, 0), (20, 20)));
+    }
+
+    #[test]
+    fn test_report() {
+        let zones = Zones::from_mrz(Some(region((50, 800), (950, 900))));
+        assert_eq!(zones.0[1], (Zone::Photo, region((50, 160), (365, 800))));
+        let policy = ForgeryPolicy::default();
+
+        assert!(!ForgeryReport::default().is_forged(&policy));
+
+        // A small artifact in the visual zone
+        let report = ForgeryReport::new(&[region((600, 300), (650, 350))], (1000, 1000), &zones);
+        assert!(report.regions[0].zones.is_empty());
+        assert_eq!(report.relative_area(), 0.0025);
+        assert!(!report.is_forged(&policy));
+
+        let report = ForgeryReport::new(&[region((600, 850), (610, 860))], (1000, 1000), &zones);
+        assert_eq!(report.regions[0].zones, vec![Zone::Mrz]);
+        assert!(report.is_forged(&policy));
+        let lenient = ForgeryPolicy {
+            critical_zones: vec![Zone::Photo],
+            ..policy.clone()
+        };
+        assert!(!report.is_forged(&lenient));
+
+        let report = ForgeryReport::new(&[region((400, 0), (1000, 200))], (1000, 1000), &zones);
+        assert!(report.is_forged(&policy));
+    }
+
+    #[test]
+    fn test_not_applicable() {
+        let report = ForgeryReport::not_applicable("PNG has no compression grid");
+        assert!(!report.is_checked());
+        assert!(report.is_forged(&ForgeryPolicy::default()));
+        let lenient = ForgeryPolicy {
+            require_check: false,
+            ..Default::default()
+        };
+        assert!(!report.is_forged(&lenient));
+    }
+}
@@ -0,0 +1,166 @@
+//! The identity facts lending heuristics use, read from a validated MRZ. The
+//! circuit compares dates as `YYYYMMDD` numbers and countries by their code.
+use crate::mrz::{Field, Format};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    pub fn today() -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        Self::from_days((secs / 86_400) as i64)
+    }
+
+    /// The civil date `days` after 1970-01-01
+    pub fn from_days(days: i64) -> Self {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+        }
+    }
+
+    /// A `YYMMDD` MRZ date, two digit years at or before `pivot` are this century
+    fn from_mrz(yymmdd: &str, pivot: u16) -> Option<Self> {
+        if yymmdd.len() != 6 || !yymmdd.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let yy: u16 = yymmdd[0..2].parse().ok()?;
+        let month: u8 = yymmdd[2..4].parse().ok()?;
+        let day: u8 = yymmdd[4..6].parse().ok()?;
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+        let year = if yy <= pivot { 2000 + yy } else { 1900 + yy };
+        Some(Self { year, month, day })
+    }
+
+    /// `YYYYMMDD`, ordered like the dates
+    pub fn as_number(&self) -> u32 {
+        self.year as u32 * 10_000 + self.month as u32 * 100 + self.day as u32
+    }
+
+    /// Whole years from `self` to `on`
+    pub fn years_until(&self, on: Date) -> u16 {
+        let years = on.year.saturating_sub(self.year);
+        if (on.month, on.day) < (self.month, self.day) {
+            years.saturating_sub(1)
+        } else {
+            years
+        }
+    }
+}
+
+/// The holder's identity facts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    /// ICAO 9303 alpha-3 code, e.g. `GBR` or `D` for Germany
+    pub nationality: String,
+    pub birth_date: Date,
+    pub expiry_date: Date,
+}
+
+impl Identity {
+    /// Read from the corrected MRZ lines, `today` decides the century of the
+    /// birth date. Expiry dates are always this century.
+    pub fn from_mrz(format: Format, lines: &[String], today: Date) -> Option<Self> {
+        let field = |wanted: Field| {
+            let (_, line, range) = format.fields().into_iter().find(|x| x.0 == wanted)?;
+            lines.get(line)?.get(range).map(String::from)
+        };
+        let nationality = field(Field::Nationality)?.trim_end_matches('<').to_string();
+        if nationality.is_empty() {
+            return None;
+        }
+        Some(Self {
+            nationality,
+            birth_date: Date::from_mrz(&field(Field::BirthDate)?, today.year % 100)?,
+            expiry_date: Date::from_mrz(&field(Field::Expiry)?, 99)?,
+        })
+    }
+
+    pub fn age(&self, today: Date) -> u16 {
+        self.birth_date.years_until(today)
+    }
+
+    pub fn is_expired(&self, today: Date) -> bool {
+        self.expiry_date < today
+    }
+}
+
+/// A country code as a number, its ASCII bytes big endian
+pub fn country_code(alpha3: &str) -> u32 {
+    alpha3
+        .bytes()
+        .take(3)
+        .fold(0, |code, byte| code << 8 | byte as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TD3: [&str; 2] = [
+        "P<UTOERIKSSON<<ANNA<MARIA<<<<<<<<<<<<<<<<<<<",
+        "L898902C36UTO7408122F1204159ZE184226B<<<<<10",
+    ];
+
+    fn date(year: u16, month: u8, day: u8) -> Date {
+        Date { year, month, day }
+    }
+
+    #[test]
+    fn test_from_days() {
+        assert_eq!(Date::from_days(0), date(1970, 1, 1));
+        assert_eq!(Date::from_days(19_782), date(2024, 2, 29));
+        assert_eq!(Date::from_days(-1), date(1969, 12, 31));
+    }
+
+    #[test]
+    fn test_from_mrz() {
+        let lines: Vec<String> = TD3.iter().map(|x| x.to_string()).collect();
+        let identity = Identity::from_mrz(Format::Td3, &lines, date(2010, 6, 1)).unwrap();
+        assert_eq!(identity.nationality, "UTO");
+        assert_eq!(identity.birth_date, date(1974, 8, 12));
+        assert_eq!(identity.expiry_date, date(2012, 4, 15));
+        assert_eq!(identity.birth_date.as_number(), 19740812);
+
+        assert_eq!(identity.age(date(2010, 6, 1)), 35);
+        assert_eq!(identity.age(date(2010, 8, 12)), 36);
+        assert!(!identity.is_expired(date(2010, 6, 1)));
+        assert!(identity.is_expired(date(2012, 4, 16)));
+
+        // Born this century
+        let lines = [TD3[0].to_string(), TD3[1].replace("740812", "080812")];
+        let identity = Identity::from_mrz(Format::Td3, &lines, date(2024, 1, 1)).unwrap();
+        assert_eq!(identity.birth_date.year, 2008);
+
+        let lines = [TD3[0].to_string(), TD3[1].replace("740812", "741312")];
+        assert_eq!(
+            Identity::from_mrz(Format::Td3, &lines, date(2024, 1, 1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_country_code() {
+        assert_eq!(country_code("UTO"), 0x55544F);
+        assert_eq!(country_code("D"), 0x44);
+    }
+}
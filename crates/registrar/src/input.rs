@@ -0,0 +1,123 @@
+//! The inputs the registrar accepts, detected by their magic bytes.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Jpeg,
+    Png,
+    /// Photos from iOS devices, these need converting to JPEG or PNG
+    Heic,
+    Pdf,
+    /// Left to the image decoder
+    Unknown,
+}
+
+impl Format {
+    pub fn detect(bytes: &[u8]) -> Self {
+        const HEIC_BRANDS: [&[u8]; 6] = [b"heic", b"heix", b"heim", b"heis", b"mif1", b"msf1"];
+        if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Format::Jpeg
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Format::Png
+        } else if bytes.starts_with(b"%PDF-") {
+            Format::Pdf
+        } else if bytes.len() >= 12
+            && &bytes[4..8] == b"ftyp"
+            && HEIC_BRANDS.contains(&&bytes[8..12])
+        {
+            Format::Heic
+        } else {
+            Format::Unknown
+        }
+    }
+}
+
+/// The JPEG images embedded in a PDF in the order they appear, scans embed
+/// one per page. Images in other encodings would need rasterising and are
+/// skipped.
+pub fn pdf_images(pdf: &[u8]) -> Vec<&[u8]> {
+    let mut images = vec![];
+    let mut from = 0;
+    while let Some(start) = find(pdf, b"stream", from) {
+        from = start + b"stream".len();
+        // `endstream` is matched by the search for `stream` too
+        if pdf[..start].ends_with(b"end") {
+            continue;
+        }
+        let dict_start = rfind(&pdf[..start], b"obj").unwrap_or(0);
+        let dict = &pdf[dict_start..start];
+        if find(dict, b"/DCTDecode", 0).is_none() || find(dict, b"/FlateDecode", 0).is_some() {
+            continue;
+        }
+
+        let data_start = match &pdf[from..] {
+            [b'\r', b'\n', ..] => from + 2,
+            [b'\n', ..] => from + 1,
+            _ => continue,
+        };
+        let data_end = match find(pdf, b"endstream", data_start) {
+            Some(end) => end,
+            None => break,
+        };
+        let data = &pdf[data_start..data_end];
+        let data = data.strip_suffix(b"\n").unwrap_or(data);
+        let data = data.strip_suffix(b"\r").unwrap_or(data);
+        if Format::detect(data) == Format::Jpeg {
+            images.push(data);
+        }
+        from = data_end + b"endstream".len();
+    }
+    images
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|x| x == needle)
+        .map(|x| x + from)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|x| x == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JPEG: &[u8] = b"\xFF\xD8\xFF\xE0scan\xFF\xD9";
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::detect(JPEG), Format::Jpeg);
+        assert_eq!(Format::detect(b"\x89PNG\r\n\x1a\n...."), Format::Png);
+        assert_eq!(Format::detect(b"%PDF-1.7\n"), Format::Pdf);
+        assert_eq!(Format::detect(b"\0\0\0\x18ftypheic\0\0\0\0"), Format::Heic);
+        assert_eq!(Format::detect(b"\0\0\0\x18ftypisom"), Format::Unknown);
+        assert_eq!(Format::detect(b""), Format::Unknown);
+    }
+
+    #[test]
+    fn test_pdf_images() {
+        let mut pdf = b"%PDF-1.4\n1 0 obj\n<< /Type /Page /Contents 3 0 R >>\nendobj\n".to_vec();
+        let image = |n: u8, filter: &str, data: &[u8]| {
+            let mut object = format!(
+                "{} 0 obj\n<< /Type /XObject /Subtype /Image /Filter {} /Length {} >>\nstream\r\n",
+                n,
+                filter,
+                data.len()
+            )
+            .into_bytes();
+            object.extend_from_slice(data);
+            object.extend_from_slice(b"\r\nendstream\nendobj\n");
+            object
+        };
+        pdf.extend(image(2, "/DCTDecode", JPEG));
+        pdf.extend(image(3, "/FlateDecode", b"x\x9c..."));
+        pdf.extend(image(4, "[/FlateDecode /DCTDecode]", b"x\x9c..."));
+        pdf.extend(image(5, "/DCTDecode", JPEG));
+
+        assert_eq!(pdf_images(&pdf), vec![JPEG, JPEG]);
+        assert!(pdf_images(b"%PDF-1.4\n%%EOF").is_empty());
+    }
+}
@@ -1,61 +1,418 @@
 use forgery_detection_zero::Zero;
+use image::DynamicImage;
 use mrtd::{parse, Document};
+use std::path::PathBuf;
 
-// TODO: could use openpace to also read ePassport keys
-// TODO: this is a highly specialized sample, should be refactored to support many passports
-// with more sophisticated tools, this just gets us there for the moment
-fn extract_passport_info() -> Document {
-    use std::path::Path;
-    let mut api = leptess::tesseract::TessApi::new(
-        Some(&format!("{}/tessdata", env!("CARGO_MANIFEST_DIR"))),
-        "OCRB+MRZ",
-    )
-    .unwrap();
-    let image_path = format!("{}/assets/sample.jpg", env!("CARGO_MANIFEST_DIR"));
-    let image = leptess::leptonica::pix_read(Path::new(&image_path)).unwrap();
-    api.set_image(&image);
-
-    check_forgery(&image_path);
-
-    let text = api.get_utf8_text().unwrap();
-    let lines = text.lines();
-
-    let mrz = lines
-        .clone()
-        .skip(lines.count() - 2)
-        .map(String::from)
-        .collect::<Vec<String>>()
-        .join("");
-
-    let doc = parse(&mrz).unwrap();
-    println!("{:?}", doc);
-    doc
-}
-
-// Basic checking of forgeries
-fn check_forgery(image_path: &str) -> bool {
-    let jpeg = image::io::Reader::open(&image_path)
-        .map_err(|_| true)
-        .and_then(|r| r.decode().map_err(|_| true))
-        .unwrap();
-
-    let mut forged = false;
-    for r in Zero::from_image(&jpeg).into_iter() {
-        println!(
-            "Forged region detected: from ({}, {}) to ({}, {})",
-            r.start.0, r.start.1, r.end.0, r.end.1,
-        );
-        forged = true
+pub mod attestation;
+pub mod chip;
+pub mod forgery;
+pub mod identity;
+pub mod input;
+pub mod mrz;
+pub mod ocr;
+pub mod preprocess;
+pub mod validation;
+
+pub use forgery::{ForgeryPolicy, ForgeryReport};
+pub use preprocess::Preprocessing;
+pub use validation::{Validation, Verdict};
+
+/// The tesseract models used to read the MRZ, from the tessdata directory
+const LANGUAGES: &str = "OCRB+MRZ";
+
+/// Reads the machine readable zone of identity documents
+#[derive(Clone, Debug, PartialEq)]
+pub struct Registrar {
+    tessdata: PathBuf,
+    min_confidence: f32,
+    forgery_policy: ForgeryPolicy,
+    preprocessing: Preprocessing,
+}
+
+/// The machine readable zone and the document parsed from it
+#[derive(Debug)]
+pub struct MrzResult {
+    pub format: mrz::Format,
+    /// The MRZ lines, corrected for OCR confusions
+    pub lines: Vec<String>,
+    pub document: Document,
+    /// Whether the extraction can be trusted
+    pub validation: Validation,
+    /// Tampering tolerated by the forgery policy
+    pub forgery: ForgeryReport,
+}
+
+impl MrzResult {
+    /// The facts identity heuristics are proven over, `None` if the dates
+    /// don't parse
+    pub fn identity(&self) -> Option<identity::Identity> {
+        identity::Identity::from_mrz(self.format, &self.lines, identity::Date::today())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RegistrarError {
+    /// The image couldn't be decoded
+    UnreadableImage(String),
+    /// The input needs converting before it can be read
+    UnsupportedFormat(String),
+    /// Tesseract couldn't be initialised or failed to read the image
+    Ocr(String),
+    /// The text read isn't a valid MRZ
+    InvalidMrz { mrz: String, reason: String },
+    /// Tampering the forgery policy doesn't tolerate
+    Forged(ForgeryReport),
+    /// The chip of an ePassport didn't verify
+    Chip(chip::ChipError),
+}
+
+impl std::fmt::Display for RegistrarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistrarError::UnreadableImage(e) => write!(f, "Unreadable image: {}", e),
+            RegistrarError::UnsupportedFormat(e) => write!(f, "Unsupported format: {}", e),
+            RegistrarError::Ocr(e) => write!(f, "OCR failed: {}", e),
+            RegistrarError::InvalidMrz { mrz, reason } => {
+                write!(f, "Invalid MRZ {:?}: {}", mrz, reason)
+            }
+            RegistrarError::Chip(e) => write!(f, "{}", e),
+            RegistrarError::Forged(report) => match &report.check {
+                forgery::ForgeryCheck::NotApplicable { reason } => {
+                    write!(f, "A forgery check is required: {}", reason)
+                }
+                forgery::ForgeryCheck::JpegGrid => write!(
+                    f,
+                    "Forged regions detected covering {:.2}% of the document",
+                    report.relative_area() * 100.0
+                ),
+            },
+        }
+    }
+}
+
+impl std::error::Error for RegistrarError {}
+
+impl Default for Registrar {
+    /// Uses the tessdata bundled with the crate
+    fn default() -> Self {
+        Self::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tessdata"))
+    }
+}
+
+impl Registrar {
+    /// `tessdata` is the directory holding the `OCRB` and `MRZ` models
+    pub fn new(tessdata: impl Into<PathBuf>) -> Self {
+        Self {
+            tessdata: tessdata.into(),
+            min_confidence: validation::DEFAULT_MIN_CONFIDENCE,
+            forgery_policy: ForgeryPolicy::default(),
+            preprocessing: Preprocessing::default(),
+        }
+    }
+
+    /// When detected forgeries fail extraction
+    pub fn forgery_policy(mut self, forgery_policy: ForgeryPolicy) -> Self {
+        self.forgery_policy = forgery_policy;
+        self
+    }
+
+    /// How images are cleaned up before OCR
+    pub fn preprocessing(mut self, preprocessing: Preprocessing) -> Self {
+        self.preprocessing = preprocessing;
+        self
+    }
+
+    /// Fields read with less OCR confidence than this, 0 to 100, aren't accepted
+    pub fn min_confidence(mut self, min_confidence: f32) -> Self {
+        self.min_confidence = min_confidence;
+        self
+    }
+
+    /// Verify an ePassport chip dump against the CSCA store and that its DG1 is
+    /// the MRZ read from the document, tying the image to the issuer's signature
+    pub fn verify_chip(
+        &self,
+        mrz: &MrzResult,
+        dump: &chip::ChipDump,
+        store: &chip::CscaStore,
+    ) -> Result<chip::ChipVerification, RegistrarError> {
+        let verified = chip::verify(dump, store).map_err(RegistrarError::Chip)?;
+        let printed = mrz.lines.concat();
+        if verified.mrz != printed {
+            return Err(RegistrarError::Chip(chip::ChipError::MrzMismatch {
+                chip: verified.mrz,
+                printed,
+            }));
+        }
+        Ok(verified)
+    }
+
+    /// Read and parse the MRZ of a JPEG or PNG image of a document, or the
+    /// first page of a scanned PDF
+    pub fn extract_mrz(&self, input: &[u8]) -> Result<MrzResult, RegistrarError> {
+        match input::Format::detect(input) {
+            input::Format::Pdf => self.extract_mrz_pdf(input, 0),
+            input::Format::Heic => Err(RegistrarError::UnsupportedFormat(
+                "HEIC photos need converting to JPEG or PNG".to_string(),
+            )),
+            _ => self.extract_mrz_image(input),
+        }
+    }
+
+    /// Read the MRZ from the scan of `page` in a PDF, only scans embedded as
+    /// JPEGs are read
+    pub fn extract_mrz_pdf(&self, pdf: &[u8], page: usize) -> Result<MrzResult, RegistrarError> {
+        let images = input::pdf_images(pdf);
+        let image = images.get(page).ok_or_else(|| {
+            RegistrarError::UnsupportedFormat(format!(
+                "The PDF has {} JPEG scans, no page {}",
+                images.len(),
+                page
+            ))
+        })?;
+        self.extract_mrz_image(image)
+    }
+
+    fn extract_mrz_image(&self, image: &[u8]) -> Result<MrzResult, RegistrarError> {
+        let decoded = image::load_from_memory(image)
+            .map_err(|e| RegistrarError::UnreadableImage(e.to_string()))?;
+        let prepared = preprocess::prepare(&decoded.to_luma8(), &self.preprocessing);
+        // The MRZ band alone reads cleanest, the whole page is the fallback
+        let mut candidates = vec![];
+        if self.preprocessing.crop_mrz {
+            candidates.extend(prepared.crop_mrz());
+        }
+        candidates.push(prepared);
+
+        let tessdata = self.tessdata.to_str().ok_or_else(|| {
+            RegistrarError::Ocr(format!("tessdata path {:?} isn't utf8", self.tessdata))
+        })?;
+        let mut api = leptess::LepTess::new(Some(tessdata), LANGUAGES)
+            .map_err(|e| RegistrarError::Ocr(format!("{:?}", e)))?;
+        let mut read = None;
+        let mut text = String::new();
+        for candidate in candidates {
+            let lines = read_lines(&mut api, &candidate.image)?;
+            text = ocr::text(&lines);
+            if let Some(mrz) = mrz::locate(&text) {
+                read = Some((candidate, lines, mrz));
+                break;
+            }
+        }
+        let (prepared, lines, mrz) = read.ok_or_else(|| RegistrarError::InvalidMrz {
+            mrz: text,
+            reason: "No MRZ found".to_string(),
+        })?;
+        let mrz_region = mrz
+            .rows
+            .iter()
+            .filter_map(|row| lines.get(*row)?.region)
+            .reduce(|a, b| a.union(&b))
+            .map(|x| prepared.to_source.map_region(&x));
+        let zones = forgery::Zones::from_mrz(mrz_region);
+        let forgery = check_forgery(&decoded, &zones);
+        if forgery.is_forged(&self.forgery_policy) {
+            return Err(RegistrarError::Forged(forgery));
+        }
+
+        let validation = Validation::new(&mrz, &lines, self.min_confidence);
+        let document = parse(&mrz.joined()).map_err(|e| RegistrarError::InvalidMrz {
+            mrz: mrz.joined(),
+            reason: format!(
+                "{:?}, invalid check digits {:?}",
+                e,
+                validation.invalid_fields()
+            ),
+        })?;
+        Ok(MrzResult {
+            format: mrz.format,
+            lines: mrz.lines,
+            document,
+            validation,
+            forgery,
+        })
     }
-    forged
+}
+
+/// The lines Tesseract reads from a prepared image, handed over as PNG as
+/// Leptonica reads it
+fn read_lines(
+    api: &mut leptess::LepTess,
+    image: &image::GrayImage,
+) -> Result<Vec<ocr::Line>, RegistrarError> {
+    let mut png = std::io::Cursor::new(vec![]);
+    DynamicImage::ImageLuma8(image.clone())
+        .write_to(&mut png, image::ImageOutputFormat::Png)
+        .map_err(|e| RegistrarError::UnreadableImage(e.to_string()))?;
+    api.set_image_from_mem(png.get_ref())
+        .map_err(|e| RegistrarError::UnreadableImage(e.to_string()))?;
+    let tsv = api
+        .get_tsv_text(0)
+        .map_err(|e| RegistrarError::Ocr(e.to_string()))?;
+    Ok(ocr::lines(&tsv))
+}
+
+// Basic checking of forgeries, whatever the format an image that was saved as
+// a JPEG keeps its compression grid. Only images without one go unchecked.
+fn check_forgery(image: &DynamicImage, zones: &forgery::Zones) -> ForgeryReport {
+    let areas = Zero::from_image(image).detect_forgeries();
+    if areas.main_grid().is_none() {
+        return ForgeryReport::not_applicable("No JPEG compression grid found");
+    }
+    let regions: Vec<forgery::Region> = areas
+        .forged_regions()
+        .iter()
+        .map(|r| forgery::Region {
+            start: r.start,
+            end: r.end,
+        })
+        .collect();
+    ForgeryReport::new(&regions, (image.width(), image.height()), zones)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn asset(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{}/assets/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+    }
+
+    fn sample() -> Vec<u8> {
+        asset("sample.jpg")
+    }
+
+    fn png_sample() -> Vec<u8> {
+        let mut png = std::io::Cursor::new(vec![]);
+        image::load_from_memory(&sample())
+            .unwrap()
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        png.into_inner()
+    }
+
     #[test]
     fn test_recognise() {
-        extract_passport_info();
+        let result = Registrar::default().extract_mrz(&sample()).unwrap();
+        assert_eq!(result.format, mrz::Format::Td3);
+        assert_eq!(result.lines.len(), 2);
+        assert!(result.validation.invalid_fields().is_empty());
+        assert!(!result.forgery.is_forged(&ForgeryPolicy::default()));
+        assert_eq!(
+            result.lines,
+            [
+                "P<GBRUNITED<KINGDOM<FIVE<<JODIE<PIPPA<<<<<<<",
+                "1071857032GBR8501178F1601312<<<<<<<<<<<<<<02",
+            ]
+        );
+
+        // mrtd's fields, through its Debug output
+        let document = format!("{:?}", result.document);
+        for field in ["107185703", "GBR", "KINGDOM", "JODIE", "PIPPA"] {
+            assert!(document.contains(field), "{} isn't in {}", field, document);
+        }
+        let identity = result.identity().unwrap();
+        assert_eq!(identity.nationality, "GBR");
+        assert_eq!(identity.birth_date.as_number(), 19850117);
+        assert_eq!(identity.expiry_date.as_number(), 20160131);
+
+        assert_eq!(result.validation.verdict, Verdict::Valid);
+        assert!(result.validation.is_accepted());
+        assert!(result.validation.checks.iter().all(|x| x.valid));
+    }
+
+    /// Captures of the sample, each should read the same MRZ: rotated 7 degrees
+    /// on a scanner, grainy and heavily compressed, photographed at an angle
+    /// under uneven light, and speckled with dust
+    fn corpus() -> Vec<(&'static str, Vec<u8>)> {
+        let sample = image::load_from_memory(&sample()).unwrap().to_luma8();
+        let mut speckled = std::io::Cursor::new(vec![]);
+        DynamicImage::ImageLuma8(preprocess::speckle(&sample, 48))
+            .write_to(&mut speckled, image::ImageOutputFormat::Png)
+            .unwrap();
+        vec![
+            ("rotated", asset("rotated.jpg")),
+            ("noisy", asset("noisy.jpg")),
+            ("photographed", asset("photographed.jpg")),
+            ("speckled", speckled.into_inner()),
+        ]
+    }
+
+    #[test]
+    fn test_corpus() {
+        // Warping destroys the compression grid, only the reading is tested here
+        let unchecked = ForgeryPolicy {
+            critical_zones: vec![],
+            max_relative_area: 1.0,
+            require_check: false,
+        };
+        let registrar = Registrar::default().forgery_policy(unchecked);
+        let expected = Registrar::default().extract_mrz(&sample()).unwrap();
+        for (name, image) in corpus() {
+            let result = registrar
+                .extract_mrz(&image)
+                .unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(result.lines, expected.lines, "{}", name);
+            assert!(result.validation.invalid_fields().is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_unreadable_image() {
+        let err = Registrar::default()
+            .extract_mrz(b"not an image")
+            .unwrap_err();
+        assert!(matches!(err, RegistrarError::UnreadableImage(_)));
+    }
+
+    #[test]
+    fn test_formats() {
+        let err = Registrar::default()
+            .extract_mrz(b"\0\0\0\x18ftypheic\0\0\0\0")
+            .unwrap_err();
+        assert!(matches!(err, RegistrarError::UnsupportedFormat(_)));
+
+        let err = Registrar::default()
+            .extract_mrz(b"%PDF-1.4\n%%EOF")
+            .unwrap_err();
+        assert!(matches!(err, RegistrarError::UnsupportedFormat(_)));
+
+        // The sample re-encoded losslessly keeps its compression grid
+        let result = Registrar::default().extract_mrz(&png_sample()).unwrap();
+        assert!(result.forgery.is_checked());
+        assert!(!result.forgery.is_forged(&ForgeryPolicy::default()));
+    }
+
+    #[test]
+    fn test_check_forgery_without_grid() {
+        // Never compressed, so there's no grid to check
+        let image = DynamicImage::ImageLuma8(image::GrayImage::from_fn(256, 256, |x, y| {
+            image::Luma([((x + y) / 2) as u8])
+        }));
+        let report = check_forgery(&image, &forgery::Zones::from_mrz(None));
+        assert!(!report.is_checked());
+        assert!(report.is_forged(&ForgeryPolicy::default()));
+    }
+
+    #[test]
+    fn test_verify_chip_untrusted() {
+        let result = Registrar::default().extract_mrz(&sample()).unwrap();
+        let dump = chip::ChipDump {
+            dg1: vec![0x61, 0x00],
+            dg2: None,
+            sod: vec![0x77, 0x00],
+        };
+        let err = Registrar::default()
+            .verify_chip(&result, &dump, &chip::CscaStore::default())
+            .unwrap_err();
+        assert!(matches!(err, RegistrarError::Chip(_)));
+    }
+
+    #[test]
+    fn test_missing_tessdata() {
+        let err = Registrar::new("/nonexistent")
+            .extract_mrz(&sample())
+            .unwrap_err();
+        assert!(matches!(err, RegistrarError::Ocr(_)));
     }
 }
@@ -0,0 +1,551 @@
+//! Finding the machine readable zone in OCR text and correcting the characters
+//! OCR commonly confuses, using the check digits to tell which reading is right.
+use std::ops::Range;
+
+/// The ICAO 9303 MRZ formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// ID cards, 3 lines of 30
+    Td1,
+    /// Older ID cards and visas, 2 lines of 36
+    Td2,
+    /// Passports, 2 lines of 44
+    Td3,
+}
+
+/// The MRZ fields
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Field {
+    Issuer,
+    Name,
+    DocumentNumber,
+    Nationality,
+    BirthDate,
+    Sex,
+    Expiry,
+    /// TD3 only, the optional data of other formats has no check digit
+    PersonalNumber,
+    /// The check digit over the other checked fields
+    Composite,
+}
+
+/// Whether the check digit of a field matches
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub field: Field,
+    pub valid: bool,
+}
+
+impl Format {
+    pub fn width(&self) -> usize {
+        match self {
+            Format::Td1 => 30,
+            Format::Td2 => 36,
+            Format::Td3 => 44,
+        }
+    }
+
+    pub fn lines(&self) -> usize {
+        match self {
+            Format::Td1 => 3,
+            Format::Td2 | Format::Td3 => 2,
+        }
+    }
+
+    /// Where each field is, by line and range
+    pub fn fields(&self) -> Vec<(Field, usize, Range<usize>)> {
+        use Field::*;
+        match self {
+            Format::Td1 => vec![
+                (Issuer, 0, 2..5),
+                (DocumentNumber, 0, 5..14),
+                (BirthDate, 1, 0..6),
+                (Sex, 1, 7..8),
+                (Expiry, 1, 8..14),
+                (Nationality, 1, 15..18),
+                (Name, 2, 0..30),
+            ],
+            Format::Td2 | Format::Td3 => {
+                let mut fields = vec![
+                    (Issuer, 0, 2..5),
+                    (Name, 0, 5..self.width()),
+                    (DocumentNumber, 1, 0..9),
+                    (Nationality, 1, 10..13),
+                    (BirthDate, 1, 13..19),
+                    (Sex, 1, 20..21),
+                    (Expiry, 1, 21..27),
+                ];
+                if *self == Format::Td3 {
+                    fields.push((PersonalNumber, 1, 28..42));
+                }
+                fields
+            }
+        }
+    }
+
+    /// The fields protected by a check digit, composite last
+    fn checked_fields(&self) -> Vec<CheckedField> {
+        let field = |field, line, range, check| CheckedField {
+            field,
+            parts: vec![(line, range)],
+            check: (line, check),
+        };
+        match self {
+            Format::Td1 => vec![
+                field(Field::DocumentNumber, 0, 5..14, 14),
+                field(Field::BirthDate, 1, 0..6, 6),
+                field(Field::Expiry, 1, 8..14, 14),
+                CheckedField {
+                    field: Field::Composite,
+                    parts: vec![(0, 5..30), (1, 0..7), (1, 8..15), (1, 18..29)],
+                    check: (1, 29),
+                },
+            ],
+            Format::Td2 | Format::Td3 => {
+                let composite = self.width() - 1;
+                let mut fields = vec![
+                    field(Field::DocumentNumber, 1, 0..9, 9),
+                    field(Field::BirthDate, 1, 13..19, 19),
+                    field(Field::Expiry, 1, 21..27, 27),
+                ];
+                if *self == Format::Td3 {
+                    fields.push(field(Field::PersonalNumber, 1, 28..42, 42));
+                }
+                fields.push(CheckedField {
+                    field: Field::Composite,
+                    parts: vec![(1, 0..10), (1, 13..20), (1, 21..composite)],
+                    check: (1, composite),
+                });
+                fields
+            }
+        }
+    }
+
+    /// What each position may hold, fields not listed are alphanumeric
+    fn kinds(&self) -> Vec<(usize, Range<usize>, Kind)> {
+        use Kind::*;
+        match self {
+            Format::Td1 => vec![
+                (0, 0..5, Alpha),
+                (0, 14..15, Numeric),
+                (1, 0..7, Numeric),
+                (1, 7..8, Alpha),
+                (1, 8..15, Numeric),
+                (1, 15..18, Alpha),
+                (1, 29..30, Numeric),
+                (2, 0..30, Alpha),
+            ],
+            Format::Td2 | Format::Td3 => {
+                let width = self.width();
+                vec![
+                    (0, 0..width, Alpha),
+                    (1, 9..10, Numeric),
+                    (1, 10..13, Alpha),
+                    (1, 13..20, Numeric),
+                    (1, 20..21, Alpha),
+                    (1, 21..28, Numeric),
+                    (1, width - 1..width, Numeric),
+                ]
+            }
+        }
+    }
+
+    /// The name field, where `<` read as `K` can't be told apart by a check digit
+    fn name(&self) -> (usize, Range<usize>) {
+        match self {
+            Format::Td1 => (2, 0..30),
+            Format::Td2 | Format::Td3 => (0, 5..self.width()),
+        }
+    }
+}
+
+/// A field and the position of its check digit
+struct CheckedField {
+    field: Field,
+    parts: Vec<(usize, Range<usize>)>,
+    check: (usize, usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Alpha,
+    Numeric,
+}
+
+/// Pairs of characters OCR mistakes for each other, the digit first
+const CONFUSIONS: [(char, char); 8] = [
+    ('0', 'O'),
+    ('0', 'D'),
+    ('0', 'Q'),
+    ('1', 'I'),
+    ('2', 'Z'),
+    ('5', 'S'),
+    ('6', 'G'),
+    ('8', 'B'),
+];
+
+/// Fields with more ambiguous characters than this aren't corrected
+const MAX_AMBIGUOUS: usize = 16;
+/// The most characters changed to match a check digit
+const MAX_CHANGES: u32 = 2;
+
+/// A located MRZ
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mrz {
+    pub format: Format,
+    pub lines: Vec<String>,
+    /// The line of the text each MRZ line was read from
+    pub rows: Vec<usize>,
+}
+
+impl Mrz {
+    /// The lines joined, as `mrtd::parse` expects
+    pub fn joined(&self) -> String {
+        self.lines.concat()
+    }
+
+    /// Every ICAO 9303 check digit of the format
+    pub fn checks(&self) -> Vec<Check> {
+        let lines = self.chars();
+        self.format
+            .checked_fields()
+            .iter()
+            .map(|x| Check {
+                field: x.field,
+                valid: x.is_valid(&lines),
+            })
+            .collect()
+    }
+
+    /// Whether every check digit matches
+    pub fn is_valid(&self) -> bool {
+        self.checks().iter().all(|x| x.valid)
+    }
+
+    fn chars(&self) -> Vec<Vec<char>> {
+        self.lines.iter().map(|x| x.chars().collect()).collect()
+    }
+}
+
+/// Find the last MRZ block in `text` and correct it. Lines are normalised, a
+/// line within two characters of an MRZ width is padded or trimmed to fit.
+pub fn locate(text: &str) -> Option<Mrz> {
+    let lines: Vec<(usize, Format, String)> = text
+        .lines()
+        .enumerate()
+        .filter_map(|(row, line)| normalise(line).map(|(format, line)| (row, format, line)))
+        .collect();
+
+    let mut found = None;
+    let mut i = 0;
+    while i < lines.len() {
+        let format = lines[i].1;
+        let block = &lines[i..];
+        if block.len() >= format.lines() && block[..format.lines()].iter().all(|x| x.1 == format) {
+            let block = &block[..format.lines()];
+            found = Some(Mrz {
+                format,
+                lines: block.iter().map(|x| x.2.clone()).collect(),
+                rows: block.iter().map(|x| x.0).collect(),
+            });
+            i += format.lines();
+        } else {
+            i += 1;
+        }
+    }
+    found.map(correct)
+}
+
+/// Fix the common OCR confusions, characters are only changed where the field
+/// can't hold them or a check digit confirms the change.
+pub fn correct(mrz: Mrz) -> Mrz {
+    let format = mrz.format;
+    let mut lines = mrz.chars();
+
+    for (line, range, kind) in format.kinds() {
+        for c in &mut lines[line][range] {
+            *c = coerce(*c, kind);
+        }
+    }
+
+    let (line, name) = format.name();
+    correct_name(&mut lines[line][name]);
+
+    let kinds = format.kinds();
+    for field in format.checked_fields() {
+        if !field.is_valid(&lines) {
+            field.correct(&mut lines, |l, i| {
+                !kinds
+                    .iter()
+                    .any(|(line, range, _)| *line == l && range.contains(&i))
+            });
+        }
+    }
+
+    Mrz {
+        format,
+        lines: lines.into_iter().map(String::from_iter).collect(),
+        rows: mrz.rows,
+    }
+}
+
+/// Remove whitespace and map lookalikes of `<`, lines that can't be an MRZ line
+/// are dropped
+fn normalise(line: &str) -> Option<(Format, String)> {
+    let line: String = line
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            '«' | '‹' | '(' | '[' | '{' => '<',
+            c => c.to_ascii_uppercase(),
+        })
+        .collect();
+    if !line
+        .chars()
+        .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '<')
+        || !line.contains('<')
+    {
+        return None;
+    }
+
+    [Format::Td1, Format::Td2, Format::Td3]
+        .into_iter()
+        .find(|x| x.width().abs_diff(line.len()) <= 2)
+        .and_then(|format| {
+            let width = format.width();
+            let mut line = line;
+            if line.len() > width {
+                // Only trim filler, anything else is a misread of the width
+                if !line[width..].chars().all(|c| c == '<' || c == 'K') {
+                    return None;
+                }
+                line.truncate(width);
+            }
+            while line.len() < width {
+                line.push('<');
+            }
+            Some((format, line))
+        })
+}
+
+fn coerce(c: char, kind: Kind) -> char {
+    match kind {
+        Kind::Numeric if !c.is_ascii_digit() && c != '<' => {
+            CONFUSIONS.iter().find(|x| x.1 == c).map_or(c, |x| x.0)
+        }
+        Kind::Alpha if c.is_ascii_digit() => {
+            CONFUSIONS.iter().find(|x| x.0 == c).map_or(c, |x| x.1)
+        }
+        _ => c,
+    }
+}
+
+/// Runs of `K` touching filler are filler, names rarely end in `K`s
+fn correct_name(name: &mut [char]) {
+    let mut i = 0;
+    while i < name.len() {
+        if name[i] != 'K' {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < name.len() && name[i] == 'K' {
+            i += 1;
+        }
+        let before = start > 0 && name[start - 1] == '<';
+        let after = i < name.len() && name[i] == '<';
+        let trailing = name[i..].iter().all(|c| *c == '<' || *c == 'K');
+        if (before && after) || (trailing && (i - start >= 2 || before)) {
+            name[start..i].iter_mut().for_each(|c| *c = '<');
+        }
+    }
+}
+
+impl CheckedField {
+    fn positions(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.parts
+            .iter()
+            .flat_map(|(line, range)| range.clone().map(move |i| (*line, i)))
+    }
+
+    fn is_valid(&self, lines: &[Vec<char>]) -> bool {
+        let data: String = self.positions().map(|(l, i)| lines[l][i]).collect();
+        let (l, i) = self.check;
+        match (check_digit(&data), lines[l][i].to_digit(10)) {
+            (Some(expected), Some(actual)) => expected == actual,
+            // A field left blank has a filler check digit
+            _ => data.chars().all(|c| c == '<') && lines[l][i] == '<',
+        }
+    }
+
+    /// Try changing the ambiguous characters, fewest changes first, a change is
+    /// only made when it's the one way to match the check digit. Only
+    /// alphanumeric positions are ambiguous, the others have been coerced.
+    fn correct(&self, lines: &mut [Vec<char>], alphanumeric: impl Fn(usize, usize) -> bool) {
+        let ambiguous: Vec<((usize, usize), char)> = self
+            .positions()
+            .filter(|(l, i)| alphanumeric(*l, *i))
+            .filter_map(|(l, i)| alternative(lines[l][i]).map(|alt| ((l, i), alt)))
+            .collect();
+        if ambiguous.is_empty() || ambiguous.len() > MAX_AMBIGUOUS {
+            return;
+        }
+
+        for changes in 1..=MAX_CHANGES.min(ambiguous.len() as u32) {
+            let mut matches = (1..1u32 << ambiguous.len())
+                .filter(|mask| mask.count_ones() == changes)
+                .map(|mask| {
+                    let mut candidate = lines.to_vec();
+                    for (bit, ((l, i), alt)) in ambiguous.iter().enumerate() {
+                        if mask & (1 << bit) != 0 {
+                            candidate[*l][*i] = *alt;
+                        }
+                    }
+                    candidate
+                })
+                .filter(|candidate| self.is_valid(candidate));
+            match (matches.next(), matches.next()) {
+                (Some(candidate), None) => {
+                    lines.clone_from_slice(&candidate);
+                    return;
+                }
+                // Ambiguous, leave it for validation to report
+                (Some(_), Some(_)) => return,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// What OCR may have mistaken `c` for
+fn alternative(c: char) -> Option<char> {
+    match c {
+        'K' => Some('<'),
+        '<' => Some('K'),
+        c => CONFUSIONS.iter().find_map(|(digit, letter)| match c {
+            c if c == *digit => Some(*letter),
+            c if c == *letter => Some(*digit),
+            _ => None,
+        }),
+    }
+}
+
+/// The ICAO 9303 check digit, `None` if `data` holds a character the MRZ can't
+pub fn check_digit(data: &str) -> Option<u32> {
+    const WEIGHTS: [u32; 3] = [7, 3, 1];
+    data.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let value = match c {
+                '0'..='9' => c.to_digit(10)?,
+                'A'..='Z' => c as u32 - 'A' as u32 + 10,
+                '<' => 0,
+                _ => return None,
+            };
+            Some(value * WEIGHTS[i % 3])
+        })
+        .sum::<Option<u32>>()
+        .map(|x| x % 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TD1: [&str; 3] = [
+        "I<UTOD231458907<<<<<<<<<<<<<<<",
+        "7408122F1204159UTO<<<<<<<<<<<6",
+        "ERIKSSON<<ANNA<MARIA<<<<<<<<<<",
+    ];
+    const TD2: [&str; 2] = [
+        "I<UTOERIKSSON<<ANNA<MARIA<<<<<<<<<<<",
+        "D231458907UTO7408122F1204159<<<<<<<6",
+    ];
+    const TD3: [&str; 2] = [
+        "P<UTOERIKSSON<<ANNA<MARIA<<<<<<<<<<<<<<<<<<<",
+        "L898902C36UTO7408122F1204159ZE184226B<<<<<10",
+    ];
+
+    #[test]
+    fn test_check_digit() {
+        assert_eq!(check_digit("L898902C3"), Some(6));
+        assert_eq!(check_digit("740812"), Some(2));
+        assert_eq!(check_digit("ZE184226B<<<<<"), Some(1));
+        assert_eq!(check_digit("L8989-2C3"), None);
+    }
+
+    #[test]
+    fn test_locate() {
+        for (format, lines) in [
+            (Format::Td1, &TD1[..]),
+            (Format::Td2, &TD2[..]),
+            (Format::Td3, &TD3[..]),
+        ] {
+            let text = format!("PASSPORT PASSEPORT\nUtopia\n\n{}\n\n", lines.join("\n"));
+            let mrz = locate(&text).unwrap();
+            assert_eq!(mrz.format, format);
+            assert_eq!(mrz.lines, lines);
+            assert_eq!(mrz.rows, (3..3 + lines.len()).collect::<Vec<_>>());
+            assert!(mrz.is_valid());
+        }
+        assert_eq!(locate("PASSPORT\nUtopia"), None);
+    }
+
+    #[test]
+    fn test_locate_ragged() {
+        // Spaces, a dropped filler and trailing filler read as K
+        let text = format!(
+            "{}\n{}KK\n",
+            "P<UTOERIKSSON << ANNA<MARIA<<<<<<<<<<<<<<<<<<", TD3[1]
+        );
+        let mrz = locate(&text).unwrap();
+        assert_eq!(mrz.lines, TD3);
+    }
+
+    #[test]
+    fn test_correct() {
+        let misread = [
+            "P<UT0ERIKSS0N<<ANNA<MAR1A<KKKKKKKKKKKKKKKKKK",
+            "L898902C36UTO74O8I22F12O4159ZE184226B<<<<<1O",
+        ];
+        let mrz = locate(&misread.join("\n")).unwrap();
+        assert_eq!(mrz.lines, TD3);
+        assert!(mrz.is_valid());
+
+        // The document number is alphanumeric, only the check digit can tell
+        let misread = [TD3[0], "L8989O2C36UTO7408122F1204159ZE184226B<<<<<10"];
+        assert_eq!(locate(&misread.join("\n")).unwrap().lines, TD3);
+
+        let misread = [TD1[0].replace("D23", "D2E"), TD1[1].into(), TD1[2].into()];
+        let mrz = locate(&misread.join("\n")).unwrap();
+        assert!(!mrz.is_valid());
+        let invalid: Vec<Field> = mrz
+            .checks()
+            .iter()
+            .filter(|x| !x.valid)
+            .map(|x| x.field)
+            .collect();
+        assert_eq!(invalid, vec![Field::DocumentNumber, Field::Composite]);
+    }
+
+    #[test]
+    fn test_correct_name() {
+        let correct = |name: &str| {
+            let mut name: Vec<char> = name.chars().collect();
+            correct_name(&mut name);
+            String::from_iter(name)
+        };
+        assert_eq!(
+            correct("ERIKSSON<<ANNA<MARIA<K<<KK"),
+            "ERIKSSON<<ANNA<MARIA<<<<<<"
+        );
+        assert_eq!(
+            correct("ERIKSSON<K<ANNA<MARIAK<<<<"),
+            "ERIKSSON<<<ANNA<MARIAK<<<<"
+        );
+        assert_eq!(
+            correct("KARLSSON<<ERIK<<<<<<<<<<<<"),
+            "KARLSSON<<ERIK<<<<<<<<<<<<"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! The text Tesseract read and how confident it was, from its TSV output.
+use crate::forgery::Region;
+
+/// The TSV level of a word
+const WORD_LEVEL: &str = "5";
+
+/// A line of text with the confidence of each character, whitespace removed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Line {
+    pub text: String,
+    /// 0 to 100, a character has the confidence of its word
+    pub confidence: Vec<f32>,
+    /// Where the line is on the image
+    pub region: Option<Region>,
+}
+
+/// The lines of Tesseract's TSV output, in reading order
+pub fn lines(tsv: &str) -> Vec<Line> {
+    // Lines are keyed by page, block, paragraph and line number
+    let mut lines: Vec<(String, Line)> = vec![];
+    for row in tsv.lines().skip(1) {
+        let columns: Vec<&str> = row.split('\t').collect();
+        if columns.len() < 12 || columns[0] != WORD_LEVEL {
+            continue;
+        }
+        let confidence = match columns[10].parse::<f32>() {
+            Ok(x) if x >= 0.0 => x,
+            _ => continue,
+        };
+        let key = columns[1..5].join(".");
+        if lines.last().map(|x| &x.0) != Some(&key) {
+            lines.push((key, Line::default()));
+        }
+        let line = &mut lines.last_mut().expect("pushed above").1;
+        if let Some(word) = word_region(&columns[6..10]) {
+            line.region = Some(line.region.map_or(word, |x| x.union(&word)));
+        }
+        for c in columns[11].chars().filter(|c| !c.is_whitespace()) {
+            line.text.push(c);
+            line.confidence.push(confidence);
+        }
+    }
+    lines.into_iter().map(|x| x.1).collect()
+}
+
+/// The left, top, width and height columns
+fn word_region(columns: &[&str]) -> Option<Region> {
+    let bounds = columns
+        .iter()
+        .map(|x| x.parse::<u32>().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    Some(Region {
+        start: (bounds[0], bounds[1]),
+        end: (bounds[0] + bounds[2], bounds[1] + bounds[3]),
+    })
+}
+
+/// The lines as plain text
+pub fn text(lines: &[Line]) -> String {
+    lines
+        .iter()
+        .map(|x| x.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TSV: &str = "level\tpage_num\tblock_num\tpar_num\tline_num\tword_num\tleft\ttop\twidth\theight\tconf\ttext
+1\t1\t0\t0\t0\t0\t0\t0\t800\t600\t-1\t
+4\t1\t1\t1\t1\t0\t10\t10\t300\t20\t-1\t
+5\t1\t1\t1\t1\t1\t10\t10\t100\t20\t91.5\tPASSPORT
+5\t1\t1\t1\t1\t2\t120\t10\t100\t20\t88\tUtopia
+5\t1\t2\t1\t1\t1\t10\t500\t400\t20\t96\tP<UTOERIKSSON<<ANNA
+5\t1\t2\t1\t1\t2\t420\t500\t300\t20\t42.25\t<MARIA<<
+5\t1\t2\t1\t2\t1\t10\t530\t700\t20\t95\tL898902C36
+";
+
+    #[test]
+    fn test_lines() {
+        let lines = lines(TSV);
+        assert_eq!(
+            text(&lines),
+            "PASSPORTUtopia\nP<UTOERIKSSON<<ANNA<MARIA<<\nL898902C36"
+        );
+        assert_eq!(lines[1].confidence.len(), lines[1].text.len());
+        assert_eq!(lines[1].confidence[0], 96.0);
+        assert_eq!(lines[1].confidence[19], 42.25);
+        assert_eq!(
+            lines[1].region,
+            Some(Region {
+                start: (10, 500),
+                end: (720, 520)
+            })
+        );
+        assert!(super::lines("").is_empty());
+    }
+}
@@ -0,0 +1,665 @@
+//! Cleaning up photos of documents before OCR: the page is flattened and
+//! straightened, binarized against its local background and the band of MRZ
+//! lines cropped. Coordinates on a prepared image map back to the original
+//! with [`Prepared::to_source`], so regions the OCR reports still locate the
+//! zones the forgery detection runs over.
+use crate::forgery::Region;
+use image::{GrayImage, Luma};
+
+/// The longest side images are analysed at, only warps use full resolution
+const ANALYSIS_SIZE: u32 = 600;
+/// The skews tried when straightening, in degrees
+const MAX_SKEW: f64 = 15.0;
+const SKEW_STEP: f64 = 0.5;
+/// How much darker than its neighbourhood ink is, in percent
+const INK_CONTRAST: u64 = 15;
+
+/// Which steps run before OCR
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Preprocessing {
+    /// Warp a photographed page back to a rectangle
+    pub perspective: bool,
+    /// Straighten rotated text lines
+    pub deskew: bool,
+    /// Remove speckles and threshold against the local background
+    pub binarize: bool,
+    /// Read the band of MRZ lines first when one can be found
+    pub crop_mrz: bool,
+}
+
+impl Default for Preprocessing {
+    fn default() -> Self {
+        Self {
+            perspective: true,
+            deskew: true,
+            binarize: true,
+            crop_mrz: true,
+        }
+    }
+}
+
+/// A projective transform of the plane, a row major 3x3 matrix
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Homography(pub [f64; 9]);
+
+impl Homography {
+    pub const IDENTITY: Self = Self([1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+
+    pub fn translation(x: f64, y: f64) -> Self {
+        Self([1.0, 0.0, x, 0.0, 1.0, y, 0.0, 0.0, 1.0])
+    }
+
+    /// Clockwise by `angle` radians, the y axis of images points down
+    pub fn rotation(angle: f64) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self([cos, -sin, 0.0, sin, cos, 0.0, 0.0, 0.0, 1.0])
+    }
+
+    /// The transform taking each of `from` to the matching `to`, none when
+    /// three of the points are on a line
+    pub fn from_points(from: [(f64, f64); 4], to: [(f64, f64); 4]) -> Option<Self> {
+        let mut system = [[0.0; 9]; 8];
+        for (i, ((x, y), (u, v))) in from.into_iter().zip(to).enumerate() {
+            system[2 * i] = [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, u];
+            system[2 * i + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, v];
+        }
+        // Gaussian elimination with partial pivoting
+        for column in 0..8 {
+            let pivot = (column..8).max_by(|a, b| {
+                system[*a][column]
+                    .abs()
+                    .total_cmp(&system[*b][column].abs())
+            })?;
+            if system[pivot][column].abs() < 1e-9 {
+                return None;
+            }
+            system.swap(column, pivot);
+            let pivot = system[column];
+            for (row, equation) in system.iter_mut().enumerate() {
+                if row != column {
+                    let factor = equation[column] / pivot[column];
+                    for (x, p) in equation.iter_mut().zip(pivot).skip(column) {
+                        *x -= factor * p;
+                    }
+                }
+            }
+        }
+        let mut matrix = [1.0; 9];
+        for (i, row) in system.iter().enumerate() {
+            matrix[i] = row[8] / row[i];
+        }
+        Some(Self(matrix))
+    }
+
+    /// `other` then `self`
+    pub fn compose(&self, other: &Self) -> Self {
+        let (a, b) = (&self.0, &other.0);
+        let mut matrix = [0.0; 9];
+        for row in 0..3 {
+            for column in 0..3 {
+                matrix[row * 3 + column] = (0..3).map(|k| a[row * 3 + k] * b[k * 3 + column]).sum();
+            }
+        }
+        Self(matrix)
+    }
+
+    pub fn apply(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let m = &self.0;
+        let w = m[6] * x + m[7] * y + m[8];
+        (
+            (m[0] * x + m[1] * y + m[2]) / w,
+            (m[3] * x + m[4] * y + m[5]) / w,
+        )
+    }
+
+    /// The bounding box of where a region lands to the nearest pixel, clamped
+    /// to positive coordinates
+    pub fn map_region(&self, region: &Region) -> Region {
+        let corners = [
+            (region.start.0, region.start.1),
+            (region.end.0, region.start.1),
+            (region.end.0, region.end.1),
+            (region.start.0, region.end.1),
+        ]
+        .map(|(x, y)| {
+            let (x, y) = self.apply((x as f64, y as f64));
+            (x.round().max(0.0), y.round().max(0.0))
+        });
+        let min = |f: fn(&(f64, f64)) -> f64| corners.iter().map(f).fold(f64::MAX, f64::min);
+        let max = |f: fn(&(f64, f64)) -> f64| corners.iter().map(f).fold(f64::MIN, f64::max);
+        Region {
+            start: (min(|x| x.0) as u32, min(|x| x.1) as u32),
+            end: (max(|x| x.0) as u32, max(|x| x.1) as u32),
+        }
+    }
+}
+
+/// An image ready for OCR
+#[derive(Clone, Debug)]
+pub struct Prepared {
+    pub image: GrayImage,
+    /// Maps coordinates on `image` to the original image
+    pub to_source: Homography,
+}
+
+impl Prepared {
+    /// `image` with `to_previous` mapping its coordinates to the current image
+    fn then(self, (image, to_previous): (GrayImage, Homography)) -> Self {
+        Self {
+            image,
+            to_source: self.to_source.compose(&to_previous),
+        }
+    }
+
+    /// Only the band of MRZ lines, see [`mrz_band`]
+    pub fn crop_mrz(&self) -> Option<Self> {
+        let band = mrz_band(&self.image)?;
+        let image = GrayImage::from_fn(
+            band.end.0 - band.start.0,
+            band.end.1 - band.start.1,
+            |x, y| *self.image.get_pixel(band.start.0 + x, band.start.1 + y),
+        );
+        let to_band = Homography::translation(band.start.0 as f64, band.start.1 as f64);
+        Some(self.clone().then((image, to_band)))
+    }
+}
+
+/// Run the `steps` other than cropping over a grayscale image
+pub fn prepare(image: &GrayImage, steps: &Preprocessing) -> Prepared {
+    let mut prepared = Prepared {
+        image: image.clone(),
+        to_source: Homography::IDENTITY,
+    };
+    if steps.perspective {
+        if let Some(flat) = page_corners(&prepared.image).and_then(|x| flatten(&prepared.image, x))
+        {
+            prepared = prepared.then(flat);
+        }
+    }
+    if steps.deskew {
+        if let Some(angle) = skew(&prepared.image) {
+            let straight = deskew(&prepared.image, angle);
+            prepared = prepared.then(straight);
+        }
+    }
+    if steps.binarize {
+        prepared.image = binarize(&prepared.image);
+    }
+    prepared
+}
+
+/// Sample `image` at the coordinates `to_source` maps each output pixel to,
+/// white outside of it
+pub fn warp(image: &GrayImage, to_source: &Homography, width: u32, height: u32) -> GrayImage {
+    let (w, h) = (image.width() as i64, image.height() as i64);
+    let at = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= w || y >= h {
+            255.0
+        } else {
+            image.get_pixel(x as u32, y as u32).0[0] as f64
+        }
+    };
+    GrayImage::from_fn(width, height, |x, y| {
+        // Pixel centers are at half coordinates
+        let (sx, sy) = to_source.apply((x as f64 + 0.5, y as f64 + 0.5));
+        let (sx, sy) = (sx - 0.5, sy - 0.5);
+        let (x0, y0) = (sx.floor(), sy.floor());
+        let (fx, fy) = (sx - x0, sy - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = at(x0, y0) * (1.0 - fx) + at(x0 + 1, y0) * fx;
+        let bottom = at(x0, y0 + 1) * (1.0 - fx) + at(x0 + 1, y0 + 1) * fx;
+        Luma([(top * (1.0 - fy) + bottom * fy).round() as u8])
+    })
+}
+
+/// The corners of a bright page photographed on a darker background, from the
+/// top left clockwise. None when the page fills the frame, as in scans.
+pub fn page_corners(image: &GrayImage) -> Option<[(f64, f64); 4]> {
+    let (small, scale) = downscale(image);
+    let threshold = otsu(&small);
+    // Extremes along the diagonals are the corners of a convex page
+    let mut corners = [(i64::MAX, (0, 0)); 4];
+    for y in 0..small.height() {
+        for x in 0..small.width() {
+            if small.get_pixel(x, y).0[0] <= threshold {
+                continue;
+            }
+            let (sx, sy) = (x as i64, y as i64);
+            for (corner, key) in corners
+                .iter_mut()
+                .zip([sx + sy, sy - sx, -sx - sy, sx - sy])
+            {
+                if key < corner.0 {
+                    *corner = (key, (x, y));
+                }
+            }
+        }
+    }
+    if corners[0].0 == i64::MAX {
+        return None;
+    }
+    let corners = corners.map(|(_, (x, y))| ((x as f64 + 0.5) * scale, (y as f64 + 0.5) * scale));
+    let area = corners
+        .iter()
+        .zip(corners.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum::<f64>()
+        .abs()
+        / 2.0;
+    let share = area / (image.width() as f64 * image.height() as f64);
+    (0.2..0.9).contains(&share).then_some(corners)
+}
+
+/// The page within `corners` warped to an upright rectangle
+fn flatten(image: &GrayImage, corners: [(f64, f64); 4]) -> Option<(GrayImage, Homography)> {
+    let distance = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1);
+    let [top_left, top_right, bottom_right, bottom_left] = corners;
+    let width = distance(top_left, top_right).max(distance(bottom_left, bottom_right));
+    let height = distance(top_left, bottom_left).max(distance(top_right, bottom_right));
+    let rectangle = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)];
+    let to_source = Homography::from_points(rectangle, corners)?;
+    let flat = warp(image, &to_source, width.ceil() as u32, height.ceil() as u32);
+    Some((flat, to_source))
+}
+
+/// The clockwise angle text lines are rotated by, in radians. None when they
+/// are straight or there's no text to tell.
+pub fn skew(image: &GrayImage) -> Option<f64> {
+    let (small, _) = downscale(image);
+    let threshold = otsu(&small);
+    let mut ink = vec![];
+    for y in 0..small.height() {
+        for x in 0..small.width() {
+            if small.get_pixel(x, y).0[0] <= threshold {
+                ink.push((x as f64, y as f64));
+            }
+        }
+    }
+    if ink.len() < 50 {
+        return None;
+    }
+    // Rows of ink project onto fewest, fullest bins when rotated straight
+    let offset = small.width() as f64;
+    let bins = (small.width() + small.height()) as usize * 2;
+    let steps = (MAX_SKEW / SKEW_STEP) as i32;
+    let (_, best) = (-steps..=steps)
+        .map(|step| {
+            let angle = (step as f64 * SKEW_STEP).to_radians();
+            let (sin, cos) = angle.sin_cos();
+            let mut counts = vec![0u64; bins];
+            for (x, y) in &ink {
+                counts[(y * cos - x * sin + offset) as usize] += 1;
+            }
+            // Ties favour the smaller angle
+            let score = counts.iter().map(|x| x * x).sum::<u64>();
+            ((score, -step.abs()), angle)
+        })
+        .max_by(|a, b| a.0.cmp(&b.0))?;
+    (best != 0.0).then_some(best)
+}
+
+/// `image` rotated back by `angle`, grown to keep the corners
+fn deskew(image: &GrayImage, angle: f64) -> (GrayImage, Homography) {
+    let (w, h) = (image.width() as f64, image.height() as f64);
+    let (sin, cos) = (angle.sin().abs(), angle.cos().abs());
+    let (width, height) = ((w * cos + h * sin).ceil(), (w * sin + h * cos).ceil());
+    let to_source = Homography::translation(w / 2.0, h / 2.0)
+        .compose(&Homography::rotation(angle))
+        .compose(&Homography::translation(-width / 2.0, -height / 2.0));
+    let straight = warp(image, &to_source, width as u32, height as u32);
+    (straight, to_source)
+}
+
+/// Ink black on white, each pixel compared with the mean of its
+/// neighbourhood so shadows and uneven lighting don't swallow text
+pub fn binarize(image: &GrayImage) -> GrayImage {
+    let image = median(image);
+    let (w, h) = (image.width() as usize, image.height() as usize);
+    // Integral image, with a zero row and column
+    let mut sums = vec![0u64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row = 0;
+        for x in 0..w {
+            row += image.get_pixel(x as u32, y as u32).0[0] as u64;
+            sums[(y + 1) * (w + 1) + x + 1] = sums[y * (w + 1) + x + 1] + row;
+        }
+    }
+    let radius = (w.max(h) / 32).max(7);
+    GrayImage::from_fn(w as u32, h as u32, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let (x0, y0) = (x.saturating_sub(radius), y.saturating_sub(radius));
+        let (x1, y1) = ((x + radius + 1).min(w), (y + radius + 1).min(h));
+        let sum = sums[y1 * (w + 1) + x1] + sums[y0 * (w + 1) + x0]
+            - sums[y0 * (w + 1) + x1]
+            - sums[y1 * (w + 1) + x0];
+        let count = ((x1 - x0) * (y1 - y0)) as u64;
+        let value = image.get_pixel(x as u32, y as u32).0[0] as u64;
+        if value * count * 100 <= sum * (100 - INK_CONTRAST) {
+            Luma([0])
+        } else {
+            Luma([255])
+        }
+    })
+}
+
+/// The median of each 3x3 neighbourhood, removing speckle noise
+fn median(image: &GrayImage) -> GrayImage {
+    let (w, h) = (image.width(), image.height());
+    GrayImage::from_fn(w, h, |x, y| {
+        let mut window = [0u8; 9];
+        let mut n = 0;
+        for ny in y.saturating_sub(1)..(y + 2).min(h) {
+            for nx in x.saturating_sub(1)..(x + 2).min(w) {
+                window[n] = image.get_pixel(nx, ny).0[0];
+                n += 1;
+            }
+        }
+        let window = &mut window[..n];
+        window.sort_unstable();
+        Luma([window[n / 2]])
+    })
+}
+
+/// The two or three lines of the MRZ on a binarized image with a margin
+/// around them: the lowest run of similar text lines spanning most of the
+/// image
+pub fn mrz_band(image: &GrayImage) -> Option<Region> {
+    let (w, h) = (image.width(), image.height());
+    let is_ink = |x: u32, y: u32| image.get_pixel(x, y).0[0] < 128;
+    let text_row = |y: u32| (0..w).filter(|x| is_ink(*x, y)).count() as u32 > w / 50;
+
+    // Text lines as rows of ink, gaps of a row are bridged
+    let mut lines: Vec<(u32, u32)> = vec![];
+    for y in (0..h).filter(|y| text_row(*y)) {
+        match lines.last_mut() {
+            Some(line) if y <= line.1 + 1 => line.1 = y + 1,
+            _ => lines.push((y, y + 1)),
+        }
+    }
+    let extent = |(top, bottom): (u32, u32)| {
+        let columns: Vec<u32> = (0..w)
+            .filter(|x| (top..bottom).filter(|y| is_ink(*x, *y)).count() > 1)
+            .collect();
+        Some((*columns.first()?, *columns.last()? + 1))
+    };
+    let is_mrz_line = |line: (u32, u32)| {
+        let height = line.1 - line.0;
+        let wide = extent(line).is_some_and(|(left, right)| (right - left) * 5 >= w * 2);
+        wide && height * 200 >= h && height * 10 <= h
+    };
+
+    for last in (0..lines.len()).rev() {
+        if !is_mrz_line(lines[last]) {
+            continue;
+        }
+        let height = lines[last].1 - lines[last].0;
+        let mut first = last;
+        while first > 0 && last - first < 2 {
+            let (above, below) = (lines[first - 1], lines[first]);
+            let similar = (above.1 - above.0) * 2 > height && (above.1 - above.0) < height * 2;
+            if !similar || below.0 - above.1 > height * 2 || !is_mrz_line(above) {
+                break;
+            }
+            first -= 1;
+        }
+        if first == last {
+            continue;
+        }
+        let (left, right) = (first..=last)
+            .filter_map(|x| extent(lines[x]))
+            .fold((w, 0), |(l, r), (left, right)| (l.min(left), r.max(right)));
+        return Some(Region {
+            start: (
+                left.saturating_sub(height),
+                lines[first].0.saturating_sub(height),
+            ),
+            end: ((right + height).min(w), (lines[last].1 + height).min(h)),
+        });
+    }
+    None
+}
+
+/// A smaller copy for analysis, averaging blocks of pixels, and the factor
+/// it was scaled down by
+fn downscale(image: &GrayImage) -> (GrayImage, f64) {
+    let factor = image
+        .width()
+        .max(image.height())
+        .div_ceil(ANALYSIS_SIZE)
+        .max(1);
+    let (w, h) = (image.width() / factor, image.height() / factor);
+    let small = GrayImage::from_fn(w.max(1), h.max(1), |x, y| {
+        let mut sum = 0u32;
+        let mut count = 0u32;
+        for sy in y * factor..((y + 1) * factor).min(image.height()) {
+            for sx in x * factor..((x + 1) * factor).min(image.width()) {
+                sum += image.get_pixel(sx, sy).0[0] as u32;
+                count += 1;
+            }
+        }
+        Luma([(sum / count.max(1)) as u8])
+    });
+    (small, factor as f64)
+}
+
+/// The threshold best separating dark and bright pixels, Otsu's method
+fn otsu(image: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            histogram[image.get_pixel(x, y).0[0] as usize] += 1;
+        }
+    }
+    let total: u64 = histogram.iter().sum();
+    let sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(i, x)| i as f64 * *x as f64)
+        .sum();
+    let (mut background, mut background_sum) = (0u64, 0.0);
+    let (mut best, mut threshold) = (0.0, 0);
+    for (i, count) in histogram.iter().enumerate() {
+        background += count;
+        background_sum += i as f64 * *count as f64;
+        let foreground = total - background;
+        if background == 0 || foreground == 0 {
+            continue;
+        }
+        let mean_background = background_sum / background as f64;
+        let mean_foreground = (sum - background_sum) / foreground as f64;
+        let variance =
+            background as f64 * foreground as f64 * (mean_background - mean_foreground).powi(2);
+        if variance > best {
+            best = variance;
+            threshold = i as u8;
+        }
+    }
+    threshold
+}
+
+/// `image` with about one pixel in 32 turned black or white, like dust and
+/// sensor noise
+#[cfg(test)]
+pub(crate) fn speckle(image: &GrayImage, mut seed: u32) -> GrayImage {
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        match seed >> 24 {
+            0..=3 => Luma([0]),
+            4..=7 => Luma([255]),
+            _ => *image.get_pixel(x, y),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dark bars on white, like lines of text
+    fn bars(width: u32, height: u32, bars: &[(u32, u32, u32, u32)]) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            let inside = bars
+                .iter()
+                .any(|(l, t, r, b)| (*l..*r).contains(&x) && (*t..*b).contains(&y));
+            Luma([if inside { 0 } else { 255 }])
+        })
+    }
+
+    fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6
+    }
+
+    #[test]
+    fn test_homography() {
+        let from = [(0.0, 0.0), (100.0, 0.0), (100.0, 50.0), (0.0, 50.0)];
+        let to = [(10.0, 20.0), (120.0, 10.0), (130.0, 80.0), (5.0, 70.0)];
+        let h = Homography::from_points(from, to).unwrap();
+        for (a, b) in from.into_iter().zip(to) {
+            assert!(close(h.apply(a), b));
+        }
+        let line = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (0.0, 5.0)];
+        assert_eq!(Homography::from_points(line, to), None);
+
+        let rotation = Homography::rotation(std::f64::consts::FRAC_PI_2);
+        assert!(close(rotation.apply((1.0, 0.0)), (0.0, 1.0)));
+        let moved = Homography::translation(5.0, 0.0).compose(&rotation);
+        assert!(close(moved.apply((1.0, 0.0)), (5.0, 1.0)));
+
+        let region = Region {
+            start: (0, 0),
+            end: (10, 10),
+        };
+        assert_eq!(
+            moved.map_region(&region),
+            Region {
+                start: (0, 0),
+                end: (5, 10)
+            }
+        );
+    }
+
+    #[test]
+    fn test_skew() {
+        let lines: Vec<_> = (0..8)
+            .map(|i| (20, 20 + i * 30, 380, 32 + i * 30))
+            .collect();
+        let straight = bars(400, 300, &lines);
+        assert_eq!(skew(&straight), None);
+
+        let angle = 5f64.to_radians();
+        let rotated = deskew(&straight, -angle).0;
+        let found = skew(&rotated).unwrap();
+        assert!((found - angle).abs() < 1f64.to_radians(), "{}", found);
+        let (back, _) = deskew(&rotated, found);
+        assert_eq!(skew(&back), None);
+    }
+
+    #[test]
+    fn test_page_corners() {
+        let square = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        let corners = [(60.0, 50.0), (340.0, 70.0), (330.0, 250.0), (50.0, 230.0)];
+        let to_page = Homography::from_points(corners, square).unwrap();
+        let photo = GrayImage::from_fn(400, 300, |x, y| {
+            let (u, v) = to_page.apply((x as f64 + 0.5, y as f64 + 0.5));
+            let on_page = (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v);
+            Luma([if on_page { 230 } else { 40 }])
+        });
+        let found = page_corners(&photo).unwrap();
+        for (a, b) in found.into_iter().zip(corners) {
+            assert!(
+                (a.0 - b.0).abs() < 2.0 && (a.1 - b.1).abs() < 2.0,
+                "{:?}",
+                found
+            );
+        }
+        let (flat, _) = flatten(&photo, found).unwrap();
+        assert!(flat.width().abs_diff(280) <= 2 && flat.height().abs_diff(180) <= 2);
+
+        // A scan is all page
+        let scan = bars(400, 300, &[(20, 20, 380, 40)]);
+        assert_eq!(page_corners(&scan), None);
+    }
+
+    #[test]
+    fn test_binarize() {
+        // Text on a background darkening to the right
+        let image = GrayImage::from_fn(200, 100, |x, y| {
+            let background = 240 - x as u8 / 2;
+            let ink = (40..60).contains(&y) && x % 10 < 4;
+            Luma([if ink { background - 80 } else { background }])
+        });
+        let binary = binarize(&image);
+        assert_eq!(binary.get_pixel(181, 50).0[0], 0);
+        assert_eq!(binary.get_pixel(185, 50).0[0], 255);
+        assert_eq!(binary.get_pixel(181, 10).0[0], 255);
+        assert_eq!(binary.get_pixel(1, 50).0[0], 0);
+    }
+
+    #[test]
+    fn test_mrz_band() {
+        let document = bars(
+            500,
+            400,
+            &[
+                (20, 20, 150, 200),
+                (200, 40, 420, 55),
+                (200, 80, 380, 95),
+                (30, 310, 470, 325),
+                (30, 340, 470, 355),
+            ],
+        );
+        assert_eq!(
+            mrz_band(&document),
+            Some(Region {
+                start: (15, 295),
+                end: (485, 370)
+            })
+        );
+        let prepared = Prepared {
+            image: document,
+            to_source: Homography::IDENTITY,
+        }
+        .crop_mrz()
+        .unwrap();
+        assert_eq!(prepared.image.width(), 470);
+        assert!(close(prepared.to_source.apply((0.0, 0.0)), (15.0, 295.0)));
+
+        // A single line isn't an MRZ
+        assert_eq!(mrz_band(&bars(500, 400, &[(30, 340, 470, 355)])), None);
+    }
+
+    #[test]
+    fn test_prepare() {
+        let document = bars(
+            500,
+            400,
+            &[
+                (20, 20, 150, 200),
+                (200, 40, 420, 55),
+                (30, 310, 470, 325),
+                (30, 340, 470, 355),
+            ],
+        );
+        // Rotated 6 degrees with speckles
+        let (rotated, to_document) = deskew(&document, -6f64.to_radians());
+        let prepared = prepare(&speckle(&rotated, 7), &Preprocessing::default())
+            .crop_mrz()
+            .unwrap();
+        let to_document = to_document.compose(&prepared.to_source);
+        let (w, h) = (
+            prepared.image.width() as f64,
+            prepared.image.height() as f64,
+        );
+        let (start, end) = (to_document.apply((0.0, 0.0)), to_document.apply((w, h)));
+        assert!(
+            (start.0 - 15.0).abs() < 10.0 && (start.1 - 295.0).abs() < 10.0,
+            "{:?}",
+            start
+        );
+        assert!(
+            (end.0 - 485.0).abs() < 10.0 && (end.1 - 370.0).abs() < 10.0,
+            "{:?}",
+            end
+        );
+    }
+}
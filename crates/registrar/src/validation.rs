@@ -0,0 +1,144 @@
+//! Whether an extracted MRZ can be trusted, by its check digits and how
+//! confident the OCR was reading each field.
+use crate::mrz::{Check, Field, Mrz};
+use crate::ocr;
+
+/// Fields read with less confidence than this are refused by default
+pub const DEFAULT_MIN_CONFIDENCE: f32 = 70.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldConfidence {
+    pub field: Field,
+    /// The lowest confidence of a character in the field, 0 to 100
+    pub confidence: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Valid,
+    /// Every check digit matches but a field was hard to read
+    LowConfidence,
+    InvalidCheckDigits,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Validation {
+    pub checks: Vec<Check>,
+    pub confidence: Vec<FieldConfidence>,
+    pub verdict: Verdict,
+}
+
+impl Validation {
+    /// Validate `mrz`, located in the text of `lines`
+    pub fn new(mrz: &Mrz, lines: &[ocr::Line], min_confidence: f32) -> Self {
+        let checks = mrz.checks();
+        let confidence: Vec<FieldConfidence> = mrz
+            .format
+            .fields()
+            .into_iter()
+            .map(|(field, line, range)| {
+                let read = mrz
+                    .rows
+                    .get(line)
+                    .and_then(|row| lines.get(*row))
+                    .map_or(&[][..], |x| &x.confidence[..]);
+                // Characters padded in when locating weren't read at all
+                let confidence = read[range.start.min(read.len())..range.end.min(read.len())]
+                    .iter()
+                    .copied()
+                    .reduce(f32::min)
+                    .unwrap_or(0.0);
+                FieldConfidence { field, confidence }
+            })
+            .collect();
+
+        let verdict = if checks.iter().any(|x| !x.valid) {
+            Verdict::InvalidCheckDigits
+        } else if confidence.iter().any(|x| x.confidence < min_confidence) {
+            Verdict::LowConfidence
+        } else {
+            Verdict::Valid
+        };
+        Self {
+            checks,
+            confidence,
+            verdict,
+        }
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        self.verdict == Verdict::Valid
+    }
+
+    /// The fields whose check digit doesn't match
+    pub fn invalid_fields(&self) -> Vec<Field> {
+        self.checks
+            .iter()
+            .filter(|x| !x.valid)
+            .map(|x| x.field)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mrz;
+
+    const TD3: [&str; 2] = [
+        "P<UTOERIKSSON<<ANNA<MARIA<<<<<<<<<<<<<<<<<<<",
+        "L898902C36UTO7408122F1204159ZE184226B<<<<<10",
+    ];
+
+    fn read(text: &[&str], confidence: f32) -> Vec<ocr::Line> {
+        text.iter()
+            .map(|x| ocr::Line {
+                text: x.to_string(),
+                confidence: vec![confidence; x.len()],
+                region: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_valid() {
+        let lines = read(&["PASSPORT", TD3[0], TD3[1]], 95.0);
+        let mrz = mrz::locate(&ocr::text(&lines)).unwrap();
+        let validation = Validation::new(&mrz, &lines, DEFAULT_MIN_CONFIDENCE);
+        assert_eq!(validation.verdict, Verdict::Valid);
+        assert!(validation.is_accepted());
+        assert_eq!(validation.checks.len(), 5);
+        assert!(validation.confidence.iter().all(|x| x.confidence == 95.0));
+    }
+
+    #[test]
+    fn test_low_confidence() {
+        let mut lines = read(&TD3, 95.0);
+        // The birth date
+        lines[1].confidence[15] = 40.0;
+        let mrz = mrz::locate(&ocr::text(&lines)).unwrap();
+        let validation = Validation::new(&mrz, &lines, DEFAULT_MIN_CONFIDENCE);
+        assert_eq!(validation.verdict, Verdict::LowConfidence);
+        let low: Vec<Field> = validation
+            .confidence
+            .iter()
+            .filter(|x| x.confidence < DEFAULT_MIN_CONFIDENCE)
+            .map(|x| x.field)
+            .collect();
+        assert_eq!(low, vec![Field::BirthDate]);
+        assert!(Validation::new(&mrz, &lines, 30.0).is_accepted());
+    }
+
+    #[test]
+    fn test_invalid_check_digits() {
+        let expired = TD3[1].replace("1204159", "1304159");
+        let lines = read(&[TD3[0], &expired], 95.0);
+        let mrz = mrz::locate(&ocr::text(&lines)).unwrap();
+        let validation = Validation::new(&mrz, &lines, DEFAULT_MIN_CONFIDENCE);
+        assert_eq!(validation.verdict, Verdict::InvalidCheckDigits);
+        assert_eq!(
+            validation.invalid_fields(),
+            vec![Field::Expiry, Field::Composite]
+        );
+    }
+}
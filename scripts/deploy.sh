@@ -22,7 +22,7 @@ near contract \
   deploy $CONTRACT \
   use-file ./target/wasm32-unknown-unknown/release/ghostfi.wasm \
   with-init-callinitialize \
-  json-args "{\"prover\":\"$PROVER\"}" \
+  json-args "{\"prover\":\"$PROVER\",\"limits\":{\"max_loan\":\"100\",\"max_outstanding\":\"200\",\"max_exposure\":\"1000\"}}" \
   prepaid-gas '100.0 Tgas' \
   attached-deposit '0.00 NEAR' \
   sign-as $SIGNER \
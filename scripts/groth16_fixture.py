@@ -0,0 +1,296 @@
+#!/usr/bin/env python3
+"""Write the Groth16 fixtures of the lender's onchain verifier tests.
+
+A key over the 43 public inputs of the `apply` circuit and a proof for the
+inputs of `bin/prover-monolith/fixtures/simple.json`, made from a known
+trapdoor since no Groth16 backend for nargo runs in CI. Both are checked with
+the pairing before they're written, once in the snarkjs layout the backends
+export and once converted for `set_verification_key` and `verify_and_loan`.
+
+    python3 scripts/groth16_fixture.py
+"""
+import base64
+import json
+import random
+from pathlib import Path
+
+ROOT = Path(__file__).resolve().parent.parent
+
+# BN254 (alt_bn128) arithmetic and the pairing, after py_ecc's bn128
+p = 21888242871839275222246405745257275088696311157297823662689037894645226208583
+r = 21888242871839275222246405745257275088548364400416034343698204186575808495617
+
+def inv(a, n=p): return pow(a, n - 2, n)
+
+class FQP:
+    def __init__(self, coeffs): self.c = [x % p for x in coeffs]
+    def __add__(s, o): return type(s)([a + b for a, b in zip(s.c, o.c)])
+    def __sub__(s, o): return type(s)([a - b for a, b in zip(s.c, o.c)])
+    def __neg__(s): return type(s)([-a for a in s.c])
+    def __eq__(s, o): return s.c == o.c
+    def __mul__(s, o):
+        if isinstance(o, int): return type(s)([a * o for a in s.c])
+        d = s.deg
+        b = [0] * (2 * d - 1)
+        for i, x in enumerate(s.c):
+            if x:
+                for j, y in enumerate(o.c):
+                    b[i + j] += x * y
+        while len(b) > d:
+            top = b.pop()
+            e = len(b) - d
+            for i, m in enumerate(s.mod):
+                b[e + i] -= top * m
+        return type(s)(b)
+    __rmul__ = __mul__
+    def __pow__(s, n):
+        res, base = type(s).one(), s
+        while n:
+            if n & 1: res = res * base
+            base = base * base
+            n >>= 1
+        return res
+    def inv(s):
+        d = s.deg
+        lm, hm = [1] + [0] * d, [0] * (d + 1)
+        low, high = s.c + [0], s.mod + [1]
+        def deg(x):
+            k = len(x) - 1
+            while k and x[k] % p == 0: k -= 1
+            return k
+        def div(a, b):
+            a = [x % p for x in a]; o = [0] * len(a)
+            da, db = deg(a), deg(b)
+            ib = inv(b[db])
+            for i in range(da - db, -1, -1):
+                o[i] = a[db + i] * ib % p
+                for c in range(db + 1):
+                    a[c + i] -= o[i] * b[c]
+            return [x % p for x in o[:deg(o) + 1]]
+        while deg(low):
+            rr = div(high, low)
+            rr += [0] * (d + 1 - len(rr))
+            nm, new = hm[:], high[:]
+            for i in range(d + 1):
+                for j in range(d + 1 - i):
+                    nm[i + j] -= lm[i] * rr[j]
+                    new[i + j] -= low[i] * rr[j]
+            nm = [x % p for x in nm]; new = [x % p for x in new]
+            lm, low, hm, high = nm, new, lm, low
+        return type(s)(lm[:d]) * inv(low[0])
+    def __truediv__(s, o):
+        if isinstance(o, int): return s * inv(o)
+        return s * o.inv()
+    def is_zero(s): return all(x == 0 for x in s.c)
+
+class FQ2(FQP):
+    deg = 2; mod = [1, 0]
+    @classmethod
+    def one(cls): return cls([1, 0])
+    @classmethod
+    def zero(cls): return cls([0, 0])
+
+class FQ12(FQP):
+    deg = 12; mod = [82, 0, 0, 0, 0, 0, -18, 0, 0, 0, 0, 0]
+    @classmethod
+    def one(cls): return cls([1] + [0] * 11)
+    @classmethod
+    def zero(cls): return cls([0] * 12)
+
+class FQ:
+    """Fq as a 1-degree wrapper so curve ops are generic"""
+    def __init__(s, v): s.v = v % p
+    def __add__(s, o): return FQ(s.v + o.v)
+    def __sub__(s, o): return FQ(s.v - o.v)
+    def __neg__(s): return FQ(-s.v)
+    def __mul__(s, o): return FQ(s.v * (o if isinstance(o, int) else o.v))
+    __rmul__ = __mul__
+    def __truediv__(s, o): return FQ(s.v * inv(o if isinstance(o, int) else o.v))
+    def __eq__(s, o): return s.v == o.v
+    def __pow__(s, n): return FQ(pow(s.v, n, p))
+    def is_zero(s): return s.v == 0
+
+def double(P):
+    if P is None: return None
+    x, y = P
+    m = (x * x * 3) / (y * 2)
+    nx = m * m - x * 2
+    return (nx, -m * nx + m * x - y)
+
+def add(P, Q):
+    if P is None: return Q
+    if Q is None: return P
+    (x1, y1), (x2, y2) = P, Q
+    if x1 == x2:
+        return double(P) if y1 == y2 else None
+    m = (y2 - y1) / (x2 - x1)
+    nx = m * m - x1 - x2
+    return (nx, -m * nx + m * x1 - y1)
+
+def mul(P, n):
+    res = None
+    while n:
+        if n & 1: res = add(res, P)
+        P = double(P)
+        n >>= 1
+    return res
+
+def neg(P): return None if P is None else (P[0], -P[1])
+
+G1 = (FQ(1), FQ(2))
+G2 = (FQ2([10857046999023057135944570762232829481370756359578518086990519993285655852781,
+           11559732032986387107991004021392285783925812861821192530917403151452391805634]),
+      FQ2([8495653923123431417604973247489272438418190587263600148770280649306958101930,
+           4082367875863433681332203403145435568316851327593401208105741076214120093531]))
+B2 = FQ2([3, 0]) / FQ2([9, 1])
+
+def on_g1(P): x, y = P; return y * y - x * x * x == FQ(3)
+def on_g2(P): x, y = P; return (y * y - x * x * x) == B2
+
+ate = 29793968203157093288
+W = FQ12([0, 1] + [0] * 10)
+
+def twist(P):
+    x, y = P
+    xc = [x.c[0] - x.c[1] * 9, x.c[1]]
+    yc = [y.c[0] - y.c[1] * 9, y.c[1]]
+    nx = FQ12([xc[0]] + [0] * 5 + [xc[1]] + [0] * 5)
+    ny = FQ12([yc[0]] + [0] * 5 + [yc[1]] + [0] * 5)
+    return (nx * W ** 2, ny * W ** 3)
+
+def cast(P): return (FQ12([P[0].v] + [0] * 11), FQ12([P[1].v] + [0] * 11))
+
+def line(P1, P2, T):
+    (x1, y1), (x2, y2), (xt, yt) = P1, P2, T
+    if x1 != x2:
+        m = (y2 - y1) / (x2 - x1)
+        return m * (xt - x1) - (yt - y1)
+    if y1 == y2:
+        m = (x1 * x1 * 3) / (y1 * 2)
+        return m * (xt - x1) - (yt - y1)
+    return xt - x1
+
+def miller(Q, P):
+    R, f = Q, FQ12.one()
+    for i in range(63, -1, -1):
+        f = f * f * line(R, R, P)
+        R = double(R)
+        if ate & (2 ** i):
+            f = f * line(R, Q, P)
+            R = add(R, Q)
+    Q1 = (Q[0] ** p, Q[1] ** p)
+    nQ2 = (Q1[0] ** p, -(Q1[1] ** p))
+    f = f * line(R, Q1, P)
+    R = add(R, Q1)
+    f = f * line(R, nQ2, P)
+    return f
+
+def pairing_check(pairs):
+    f = FQ12.one()
+    for g1, g2 in pairs:
+        if g1 is None or g2 is None: continue
+        f = f * miller(twist(g2), cast(g1))
+    return f ** ((p ** 12 - 1) // r) == FQ12.one()
+
+
+B58 = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz"
+
+
+def b58decode(s):
+    n = 0
+    for ch in s:
+        n = n * 58 + B58.index(ch)
+    return n.to_bytes(32, "big")
+
+
+def public_inputs(public_key, amount, nonce, proving_date, registrar_key, heuristics):
+    """`apply_public_inputs` in the lender, as field elements"""
+    inputs = list(public_key) + [amount, nonce, proving_date]
+    inputs += [int.from_bytes(registrar_key[i:i + 16], "big") for i in range(0, 64, 16)]
+    return inputs + list(heuristics)
+
+
+def snarkjs_g1(P):
+    return [str(P[0].v), str(P[1].v), "1"]
+
+
+def snarkjs_g2(P):
+    return [[str(x) for x in P[0].c], [str(x) for x in P[1].c], ["1", "0"]]
+
+
+def lender_g1(P):
+    return base64.b64encode(P[0].v.to_bytes(32, "little") + P[1].v.to_bytes(32, "little")).decode()
+
+
+def lender_g2(P):
+    raw = b"".join(x.to_bytes(32, "little") for x in P[0].c + P[1].c)
+    return base64.b64encode(raw).decode()
+
+
+def main():
+    request = json.loads((ROOT / "bin/prover-monolith/fixtures/simple.json").read_text())
+    public_key = b58decode(request["public_key"].removeprefix("ed25519:"))
+    inputs = public_inputs(public_key, request["requested_amount"], 0, 20231208, bytes(64), [1, 0, 0, 0])
+
+    rng = random.Random(20231208)
+    alpha, beta, gamma, delta = (rng.randrange(1, r) for _ in range(4))
+    ic = [rng.randrange(1, r) for _ in range(len(inputs) + 1)]
+    a, b = rng.randrange(1, r), rng.randrange(1, r)
+    # e(A, B) = e(alpha, beta) * e(vk_x, gamma) * e(C, delta) in the exponent
+    x = (ic[0] + sum(i * k for i, k in zip(inputs, ic[1:]))) % r
+    c = (a * b - alpha * beta - x * gamma) * inv(delta, r) % r
+
+    key = {
+        "alpha_g1": mul(G1, alpha),
+        "beta_g2": mul(G2, beta),
+        "gamma_g2": mul(G2, gamma),
+        "delta_g2": mul(G2, delta),
+        "ic": [mul(G1, k) for k in ic],
+    }
+    proof = {"a": mul(G1, a), "b": mul(G2, b), "c": mul(G1, c)}
+    vk_x = mul(G1, x)
+    assert pairing_check([
+        (neg(proof["a"]), proof["b"]),
+        (key["alpha_g1"], key["beta_g2"]),
+        (vk_x, key["gamma_g2"]),
+        (proof["c"], key["delta_g2"]),
+    ])
+
+    snarkjs_key = {
+        "protocol": "groth16",
+        "curve": "bn128",
+        "nPublic": len(inputs),
+        "vk_alpha_1": snarkjs_g1(key["alpha_g1"]),
+        "vk_beta_2": snarkjs_g2(key["beta_g2"]),
+        "vk_gamma_2": snarkjs_g2(key["gamma_g2"]),
+        "vk_delta_2": snarkjs_g2(key["delta_g2"]),
+        "IC": [snarkjs_g1(P) for P in key["ic"]],
+    }
+    snarkjs_proof = {
+        "protocol": "groth16",
+        "curve": "bn128",
+        "pi_a": snarkjs_g1(proof["a"]),
+        "pi_b": snarkjs_g2(proof["b"]),
+        "pi_c": snarkjs_g1(proof["c"]),
+    }
+    lender_key = {
+        "alpha_g1": lender_g1(key["alpha_g1"]),
+        "beta_g2": lender_g2(key["beta_g2"]),
+        "gamma_g2": lender_g2(key["gamma_g2"]),
+        "delta_g2": lender_g2(key["delta_g2"]),
+        "ic": [lender_g1(P) for P in key["ic"]],
+    }
+    lender_proof = {"a": lender_g1(proof["a"]), "b": lender_g2(proof["b"]), "c": lender_g1(proof["c"])}
+
+    outputs = {
+        "bin/prover-monolith/fixtures/groth16_vk.json": snarkjs_key,
+        "bin/prover-monolith/fixtures/groth16_proof.json": snarkjs_proof,
+        "contracts/register/fixtures/verification_key.json": lender_key,
+        "contracts/register/fixtures/proof.json": lender_proof,
+    }
+    for path, value in outputs.items():
+        (ROOT / path).write_text(json.dumps(value, indent=2) + "\n")
+
+
+if __name__ == "__main__":
+    main()
@@ -22,6 +22,6 @@ near contract call-function as-transaction \
   $CONTRACT register \
   json-args "{\"user\":\"$USER\"}" \
   prepaid-gas '100.0 Tgas' \
-  attached-deposit '0 NEAR' \
+  attached-deposit '0.01 NEAR' \
   sign-as $SIGNER \
   network-config $ENVIRONMENT sign-with-keychain send